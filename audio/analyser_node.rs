@@ -1,23 +1,101 @@
-use crate::block::{Block, Chunk, FRAMES_PER_BLOCK_USIZE};
-use crate::node::AudioNodeEngine;
+use crate::block::{Block, Chunk, Tick};
+use crate::fft::Fft;
 use crate::node::BlockInfo;
+use crate::node::{AudioNodeEngine, AudioNodeError};
 use crate::node::{AudioNodeType, ChannelInfo, ChannelInterpretation};
+use num_complex::Complex32;
 use std::cmp;
 use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// This specifies options for constructing an AnalyserNode.
+///
+/// https://webaudio.github.io/web-audio-api/#AnalyserOptions
+#[derive(Copy, Clone, Debug)]
+pub struct AnalyserNodeOptions {
+    pub fft_size: usize,
+    pub smoothing_time_constant: f64,
+    pub min_decibels: f64,
+    pub max_decibels: f64,
+}
+
+impl Default for AnalyserNodeOptions {
+    fn default() -> Self {
+        AnalyserNodeOptions {
+            fft_size: 2048,
+            smoothing_time_constant: 0.8,
+            min_decibels: -100.,
+            max_decibels: -30.,
+        }
+    }
+}
+
+/// Control messages directed to AnalyserNodes.
+///
+/// Setting the decibel range through the DOM side's `AnalysisEngine`
+/// instead reports whether the new bound is valid given the other one.
+#[derive(Copy, Clone, Debug)]
+pub enum AnalyserNodeMessage {
+    SetFftSize(usize),
+    SetSmoothingTimeConstant(f64),
+    SetMinDecibels(f64),
+    SetMaxDecibels(f64),
+}
 
+impl AnalyserNodeMessage {
+    /// Check the values that are invalid whatever the node's settings,
+    /// on the control thread
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        match *self {
+            AnalyserNodeMessage::SetFftSize(size) => check_fft_size(size),
+            AnalyserNodeMessage::SetSmoothingTimeConstant(constant) => {
+                check_smoothing_constant(constant)
+            },
+            AnalyserNodeMessage::SetMinDecibels(db) | AnalyserNodeMessage::SetMaxDecibels(db) => {
+                if db.is_finite() {
+                    Ok(())
+                } else {
+                    Err(AudioNodeError::IndexSize)
+                }
+            },
+        }
+    }
+}
+
+/// AnalyserNode engine.
+///
+/// The input is passed through unchanged, and a mono down-mix of it is
+/// recorded in the node's `AnalysisEngine`, where it can be read from
+/// any thread holding a clone of that engine.
+///
+/// https://webaudio.github.io/web-audio-api/#AnalyserNode
 #[derive(AudioNodeCommon)]
 pub(crate) struct AnalyserNode {
     channel_info: ChannelInfo,
-    callback: Box<dyn FnMut(Block) + Send>,
+    engine: AnalysisEngine,
 }
 
 impl AnalyserNode {
-    pub fn new(callback: Box<dyn FnMut(Block) + Send>, channel_info: ChannelInfo) -> Self {
+    pub fn new(engine: AnalysisEngine, channel_info: ChannelInfo) -> Self {
         Self {
-            callback,
             channel_info,
+            engine,
         }
     }
+
+    fn handle_analyser_message(&mut self, message: AnalyserNodeMessage, _sample_rate: f32) {
+        // `AudioContext::message_node` checked the values, but a decibel
+        // bound can still cross the other one, and is then ignored
+        let _ = match message {
+            AnalyserNodeMessage::SetFftSize(size) => self.engine.set_fft_size(size),
+            AnalyserNodeMessage::SetSmoothingTimeConstant(constant) => {
+                self.engine.set_smoothing_constant(constant)
+            },
+            AnalyserNodeMessage::SetMinDecibels(db) => self.engine.set_min_decibels(db),
+            AnalyserNodeMessage::SetMaxDecibels(db) => self.engine.set_max_decibels(db),
+        };
+    }
 }
 
 impl AudioNodeEngine for AnalyserNode {
@@ -31,33 +109,60 @@ impl AudioNodeEngine for AnalyserNode {
         let mut push = inputs.blocks[0].clone();
         push.mix(1, ChannelInterpretation::Speakers);
 
-        (self.callback)(push);
+        self.engine.push(push);
 
         // analyser node doesn't modify the inputs
         inputs
     }
+
+    fn tail_time(&self) -> Tick {
+        // Keep recording until the last of the input has left the window
+        Tick(self.engine.get_fft_size() as u64)
+    }
+
+    make_message_handler!(AnalyserNode: handle_analyser_message);
 }
 
 /// From https://webaudio.github.io/web-audio-api/#dom-analysernode-fftsize
 pub const MAX_FFT_SIZE: usize = 32768;
-pub const MAX_BLOCK_COUNT: usize = MAX_FFT_SIZE / FRAMES_PER_BLOCK_USIZE;
 
-/// The actual analysis is done on the DOM side. We provide
-/// the actual base functionality in this struct, so the DOM
-/// just has to do basic shimming
-pub struct AnalysisEngine {
+/// The state shared by all clones of an `AnalysisEngine`.
+///
+/// The AnalyserNode is the only writer. Readers never block it: the time
+/// domain data is guarded by a sequence lock, and readers retry if a
+/// block was written while they were copying it.
+struct SharedAnalysis {
+    /// A ring buffer containing the last MAX_FFT_SIZE sample-frames,
+    /// as f32 bits
+    data: Box<[AtomicU32]>,
+    /// Twice the number of blocks written so far, plus one while
+    /// a block is being written
+    sequence: AtomicUsize,
+    /// The length of the blocks written, which divides MAX_FFT_SIZE
+    frames_per_block: AtomicUsize,
     /// The number of past sample-frames to consider in the FFT
-    fft_size: usize,
-    smoothing_constant: f64,
-    min_decibels: f64,
-    max_decibels: f64,
-    /// This is a ring buffer containing the last MAX_FFT_SIZE
-    /// sample-frames
-    data: Box<[f32; MAX_FFT_SIZE]>,
-    /// The index of the current block
-    current_block: usize,
-    /// Have we computed the FFT already?
+    fft_size: AtomicUsize,
+    /// The following are f64 bits
+    smoothing_constant: AtomicU64,
+    min_decibels: AtomicU64,
+    max_decibels: AtomicU64,
+}
+
+/// Analysis of the signal going through an AnalyserNode.
+///
+/// Cloning an engine gives another view on the same data and settings,
+/// with its own FFT smoothing state. The node records into its own clone,
+/// while the DOM side reads from another.
+pub struct AnalysisEngine {
+    shared: Arc<SharedAnalysis>,
+    /// The last `fft_size` sample-frames, oldest first, as of
+    /// the last snapshot
+    snapshot: Vec<f32>,
+    /// The sequence number and FFT size of the last snapshot
+    snapshot_of: Option<(usize, usize)>,
+    /// Have we computed the FFT of the current snapshot already?
     fft_computed: bool,
+    fft: Option<Fft>,
     /// Cached blackman window data
     blackman_windows: Vec<f32>,
     /// The smoothed FFT data (in frequency domain)
@@ -67,6 +172,14 @@ pub struct AnalysisEngine {
     /// The windowed time domain data
     /// Used during FFT computation
     windowed: Vec<f32>,
+    /// The frequency bins, used during FFT computation
+    bins: Vec<Complex32>,
+}
+
+impl Clone for AnalysisEngine {
+    fn clone(&self) -> Self {
+        Self::with_shared(self.shared.clone())
+    }
 }
 
 impl AnalysisEngine {
@@ -76,120 +189,181 @@ impl AnalysisEngine {
         min_decibels: f64,
         max_decibels: f64,
     ) -> Self {
-        debug_assert!(fft_size >= 32 && fft_size <= 32768);
-        // must be a power of two
-        debug_assert!(fft_size & fft_size - 1 == 0);
-        debug_assert!(smoothing_constant <= 1. && smoothing_constant >= 0.);
+        check_fft_size(fft_size)
+            .expect("IndexSizeError: fftSize must be a power of two in [32, 32768]");
+        debug_assert!((0. ..=1.).contains(&smoothing_constant));
         debug_assert!(max_decibels > min_decibels);
+        let shared = SharedAnalysis {
+            data: (0..MAX_FFT_SIZE).map(|_| AtomicU32::new(0)).collect(),
+            sequence: AtomicUsize::new(0),
+            frames_per_block: AtomicUsize::new(0),
+            fft_size: AtomicUsize::new(fft_size),
+            smoothing_constant: AtomicU64::new(smoothing_constant.to_bits()),
+            min_decibels: AtomicU64::new(min_decibels.to_bits()),
+            max_decibels: AtomicU64::new(max_decibels.to_bits()),
+        };
+        Self::with_shared(Arc::new(shared))
+    }
+
+    pub fn from_options(options: AnalyserNodeOptions) -> Self {
+        Self::new(
+            options.fft_size,
+            options.smoothing_time_constant,
+            options.min_decibels,
+            options.max_decibels,
+        )
+    }
+
+    fn with_shared(shared: Arc<SharedAnalysis>) -> Self {
         Self {
-            fft_size,
-            smoothing_constant,
-            min_decibels,
-            max_decibels,
-            data: Box::new([0.; MAX_FFT_SIZE]),
-            current_block: MAX_BLOCK_COUNT - 1,
+            shared,
+            snapshot: Vec::new(),
+            snapshot_of: None,
             fft_computed: false,
-            blackman_windows: Vec::with_capacity(fft_size),
-            computed_fft_data: Vec::with_capacity(fft_size / 2),
-            smoothed_fft_data: Vec::with_capacity(fft_size / 2),
-            windowed: Vec::with_capacity(fft_size),
+            fft: None,
+            blackman_windows: Vec::new(),
+            computed_fft_data: Vec::new(),
+            smoothed_fft_data: Vec::new(),
+            windowed: Vec::new(),
+            bins: Vec::new(),
         }
     }
 
-    pub fn set_fft_size(&mut self, fft_size: usize) {
-        debug_assert!(fft_size >= 32 && fft_size <= 32768);
-        // must be a power of two
-        debug_assert!(fft_size & fft_size - 1 == 0);
-        self.fft_size = fft_size;
-        self.fft_computed = false;
+    /// Fails with `IndexSize` unless the size is a power of two
+    /// in [32, 32768]
+    pub fn set_fft_size(&mut self, fft_size: usize) -> Result<(), AudioNodeError> {
+        check_fft_size(fft_size)?;
+        self.shared.fft_size.store(fft_size, Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn get_fft_size(&self) -> usize {
-        self.fft_size
+        self.shared.fft_size.load(Ordering::Relaxed)
     }
 
-    pub fn set_smoothing_constant(&mut self, smoothing_constant: f64) {
-        debug_assert!(smoothing_constant <= 1. && smoothing_constant >= 0.);
-        self.smoothing_constant = smoothing_constant;
-        self.fft_computed = false;
+    /// Fails with `IndexSize` unless the constant is in [0, 1]
+    pub fn set_smoothing_constant(
+        &mut self,
+        smoothing_constant: f64,
+    ) -> Result<(), AudioNodeError> {
+        check_smoothing_constant(smoothing_constant)?;
+        self.shared
+            .smoothing_constant
+            .store(smoothing_constant.to_bits(), Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn get_smoothing_constant(&self) -> f64 {
-        self.smoothing_constant
+        f64::from_bits(self.shared.smoothing_constant.load(Ordering::Relaxed))
     }
 
-    pub fn set_min_decibels(&mut self, min_decibels: f64) {
-        debug_assert!(min_decibels < self.max_decibels);
-        self.min_decibels = min_decibels;
+    /// Fails with `IndexSize` unless the bound is below the maximum
+    pub fn set_min_decibels(&mut self, min_decibels: f64) -> Result<(), AudioNodeError> {
+        if min_decibels.is_nan() || min_decibels >= self.get_max_decibels() {
+            return Err(AudioNodeError::IndexSize);
+        }
+        self.shared
+            .min_decibels
+            .store(min_decibels.to_bits(), Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn get_min_decibels(&self) -> f64 {
-        self.min_decibels
+        f64::from_bits(self.shared.min_decibels.load(Ordering::Relaxed))
     }
 
-    pub fn set_max_decibels(&mut self, max_decibels: f64) {
-        debug_assert!(self.min_decibels < max_decibels);
-        self.max_decibels = max_decibels;
+    /// Fails with `IndexSize` unless the bound is above the minimum
+    pub fn set_max_decibels(&mut self, max_decibels: f64) -> Result<(), AudioNodeError> {
+        if max_decibels.is_nan() || max_decibels <= self.get_min_decibels() {
+            return Err(AudioNodeError::IndexSize);
+        }
+        self.shared
+            .max_decibels
+            .store(max_decibels.to_bits(), Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn get_max_decibels(&self) -> f64 {
-        self.max_decibels
+        f64::from_bits(self.shared.max_decibels.load(Ordering::Relaxed))
     }
 
-    fn advance(&mut self) {
-        self.current_block += 1;
-        if self.current_block >= MAX_BLOCK_COUNT {
-            self.current_block = 0;
-        }
-    }
-
-    /// Get the data of the current block
-    fn curent_block_mut(&mut self) -> &mut [f32] {
-        let index = FRAMES_PER_BLOCK_USIZE * self.current_block;
-        &mut self.data[index..(index + FRAMES_PER_BLOCK_USIZE)]
+    /// The position in the ring buffer right after the block written
+    /// last, given a sequence number
+    fn end_of_data(sequence: usize, frames_per_block: usize) -> usize {
+        (sequence / 2).wrapping_mul(frames_per_block) % MAX_FFT_SIZE
     }
 
-    /// Given an index from 0 to fft_size, convert it into an index into
-    /// the backing array
-    fn convert_index(&self, index: usize) -> usize {
-        let offset = self.fft_size - index;
-        let last_element = (1 + self.current_block) * FRAMES_PER_BLOCK_USIZE - 1;
-        if offset > last_element {
-            MAX_FFT_SIZE - offset + last_element
+    /// Record a block of mono input. Only the AnalyserNode may call this.
+    pub(crate) fn push(&mut self, block: Block) {
+        debug_assert!(block.chan_count() == 1);
+        let shared = &*self.shared;
+        let sequence = shared.sequence.load(Ordering::Relaxed);
+        shared.sequence.store(sequence + 1, Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        let frames = block.frames();
+        shared.frames_per_block.store(frames, Ordering::Relaxed);
+        let index = Self::end_of_data(sequence, frames);
+        let dest = &shared.data[index..(index + frames)];
+        if block.is_silence() {
+            for sample in dest {
+                sample.store(0, Ordering::Relaxed);
+            }
         } else {
-            last_element - offset
+            for (sample, value) in dest.iter().zip(block.data_chan(0)) {
+                sample.store(value.to_bits(), Ordering::Relaxed);
+            }
         }
-    }
 
-    /// Given an index into the backing array, increment it
-    fn advance_index(&self, index: &mut usize) {
-        *index += 1;
-        if *index >= MAX_FFT_SIZE {
-            *index = 0;
-        }
+        shared.sequence.store(sequence + 2, Ordering::Release);
     }
 
-    pub fn push(&mut self, mut block: Block) {
-        debug_assert!(block.chan_count() == 1);
-        self.advance();
-        if !block.is_silence() {
-            self.curent_block_mut().copy_from_slice(block.data_mut());
+    /// Copy the last `fft_size` sample-frames out of the shared ring
+    /// buffer, unless nothing changed since the last snapshot
+    fn take_snapshot(&mut self) {
+        let fft_size = self.get_fft_size();
+        loop {
+            let sequence = self.shared.sequence.load(Ordering::Acquire);
+            if sequence % 2 == 1 {
+                // the node is writing a block
+                std::hint::spin_loop();
+                continue;
+            }
+            if self.snapshot_of == Some((sequence, fft_size)) {
+                return;
+            }
+
+            self.snapshot.resize(fft_size, 0.);
+            let frames = self.shared.frames_per_block.load(Ordering::Relaxed);
+            let end = Self::end_of_data(sequence, frames);
+            let mut data_idx = (end + MAX_FFT_SIZE - fft_size) % MAX_FFT_SIZE;
+            for sample in &mut self.snapshot {
+                *sample = f32::from_bits(self.shared.data[data_idx].load(Ordering::Relaxed));
+                data_idx = (data_idx + 1) % MAX_FFT_SIZE;
+            }
+
+            atomic::fence(Ordering::Acquire);
+            if self.shared.sequence.load(Ordering::Relaxed) == sequence {
+                self.snapshot_of = Some((sequence, fft_size));
+                self.fft_computed = false;
+                return;
+            }
         }
-        self.fft_computed = false;
     }
 
     /// https://webaudio.github.io/web-audio-api/#blackman-window
     fn compute_blackman_windows(&mut self) {
-        if self.blackman_windows.len() == self.fft_size {
+        let fft_size = self.snapshot.len();
+        if self.blackman_windows.len() == fft_size {
             return;
         }
         const ALPHA: f32 = 0.16;
         const ALPHA_0: f32 = (1. - ALPHA) / 2.;
         const ALPHA_1: f32 = 1. / 2.;
         const ALPHA_2: f32 = ALPHA / 2.;
-        self.blackman_windows.resize(self.fft_size, 0.);
-        let coeff = PI * 2. / self.fft_size as f32;
-        for n in 0..self.fft_size {
+        self.blackman_windows.resize(fft_size, 0.);
+        let coeff = PI * 2. / fft_size as f32;
+        for n in 0..fft_size {
             self.blackman_windows[n] = ALPHA_0 - ALPHA_1 * (coeff * n as f32).cos()
                 + ALPHA_2 * (2. * coeff * n as f32).cos();
         }
@@ -197,78 +371,93 @@ impl AnalysisEngine {
 
     fn apply_blackman_window(&mut self) {
         self.compute_blackman_windows();
-        self.windowed.resize(self.fft_size, 0.);
-
-        let mut data_idx = self.convert_index(0);
-        for n in 0..self.fft_size {
-            self.windowed[n] = self.blackman_windows[n] * self.data[data_idx];
-            self.advance_index(&mut data_idx);
-        }
+        self.windowed.clear();
+        self.windowed.extend(
+            self.blackman_windows
+                .iter()
+                .zip(&self.snapshot)
+                .map(|(w, s)| w * s),
+        );
     }
 
+    /// https://webaudio.github.io/web-audio-api/#fft-windowing-and-smoothing-over-time
     fn compute_fft(&mut self) {
+        self.take_snapshot();
         if self.fft_computed {
             return;
         }
         self.fft_computed = true;
+        let fft_size = self.snapshot.len();
         self.apply_blackman_window();
-        self.computed_fft_data.resize(self.fft_size / 2, 0.);
-        self.smoothed_fft_data.resize(self.fft_size / 2, 0.);
-
-        for k in 0..(self.fft_size / 2) {
-            let mut sum_real = 0.;
-            let mut sum_imaginary = 0.;
-            let factor = -2. * PI * k as f32 / self.fft_size as f32;
-            for n in 0..(self.fft_size) {
-                sum_real += self.windowed[n] * (factor * n as f32).cos();
-                sum_imaginary += self.windowed[n] * (factor * n as f32).sin();
-            }
-            let sum_real = sum_real / self.fft_size as f32;
-            let sum_imaginary = sum_imaginary / self.fft_size as f32;
-            let magnitude = (sum_real * sum_real + sum_imaginary * sum_imaginary).sqrt();
-            self.smoothed_fft_data[k] = (self.smoothing_constant * self.smoothed_fft_data[k] as f64
-                + (1. - self.smoothing_constant) * magnitude as f64)
+
+        if self.fft.as_ref().map(|f| f.size()) != Some(fft_size) {
+            self.fft = Some(Fft::new(fft_size));
+        }
+        let fft = self.fft.as_mut().expect("created above");
+        self.bins.resize(fft.real_bins(), Complex32::new(0., 0.));
+        fft.forward_real(&self.windowed, &mut self.bins);
+
+        self.computed_fft_data.resize(fft_size / 2, 0.);
+        self.smoothed_fft_data.resize(fft_size / 2, 0.);
+        let smoothing_constant = self.get_smoothing_constant();
+        for k in 0..(fft_size / 2) {
+            let magnitude = self.bins[k].norm() / fft_size as f32;
+            self.smoothed_fft_data[k] = (smoothing_constant * self.smoothed_fft_data[k] as f64
+                + (1. - smoothing_constant) * magnitude as f64)
                 as f32;
             self.computed_fft_data[k] = 20. * self.smoothed_fft_data[k].log(10.);
         }
     }
 
-    pub fn fill_time_domain_data(&self, dest: &mut [f32]) {
-        let mut data_idx = self.convert_index(0);
-        let end = cmp::min(self.fft_size, dest.len());
-        for n in 0..end {
-            dest[n] = self.data[data_idx];
-            self.advance_index(&mut data_idx);
-        }
+    pub fn fill_time_domain_data(&mut self, dest: &mut [f32]) {
+        self.take_snapshot();
+        let end = cmp::min(self.snapshot.len(), dest.len());
+        dest[0..end].copy_from_slice(&self.snapshot[0..end]);
     }
 
-    pub fn fill_byte_time_domain_data(&self, dest: &mut [u8]) {
-        let mut data_idx = self.convert_index(0);
-        let end = cmp::min(self.fft_size, dest.len());
-        for n in 0..end {
-            let result = 128. * (1. + self.data[data_idx]);
-            dest[n] = clamp_255(result);
-            self.advance_index(&mut data_idx)
+    pub fn fill_byte_time_domain_data(&mut self, dest: &mut [u8]) {
+        self.take_snapshot();
+        for (d, s) in dest.iter_mut().zip(&self.snapshot) {
+            *d = clamp_255(128. * (1. + s));
         }
     }
 
     pub fn fill_frequency_data(&mut self, dest: &mut [f32]) {
         self.compute_fft();
         let len = cmp::min(dest.len(), self.computed_fft_data.len());
-        dest[0..len].copy_from_slice(&mut self.computed_fft_data[0..len]);
+        dest[0..len].copy_from_slice(&self.computed_fft_data[0..len]);
     }
 
     pub fn fill_byte_frequency_data(&mut self, dest: &mut [u8]) {
         self.compute_fft();
         let len = cmp::min(dest.len(), self.computed_fft_data.len());
-        let ratio = 255. / (self.max_decibels - self.min_decibels);
+        let min_decibels = self.get_min_decibels();
+        let ratio = 255. / (self.get_max_decibels() - min_decibels);
         for freq in 0..len {
-            let result = ratio * (self.computed_fft_data[freq] as f64 - self.min_decibels);
+            let result = ratio * (self.computed_fft_data[freq] as f64 - min_decibels);
             dest[freq] = clamp_255(result as f32);
         }
     }
 }
 
+/// https://webaudio.github.io/web-audio-api/#dom-analysernode-fftsize
+fn check_fft_size(fft_size: usize) -> Result<(), AudioNodeError> {
+    if fft_size.is_power_of_two() && (32..=MAX_FFT_SIZE).contains(&fft_size) {
+        Ok(())
+    } else {
+        Err(AudioNodeError::IndexSize)
+    }
+}
+
+/// https://webaudio.github.io/web-audio-api/#dom-analysernode-smoothingtimeconstant
+fn check_smoothing_constant(smoothing_constant: f64) -> Result<(), AudioNodeError> {
+    if (0. ..=1.).contains(&smoothing_constant) {
+        Ok(())
+    } else {
+        Err(AudioNodeError::IndexSize)
+    }
+}
+
 fn clamp_255(val: f32) -> u8 {
     if val > 255. {
         255
@@ -0,0 +1,96 @@
+//! Compares the vectorized mixing kernels with their portable versions,
+//! and times the `Block` operations built on them.
+//!
+//! Run with `cargo bench -p servo-media-audio`.
+
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use servo_media_audio::block::{Block, FRAMES_PER_BLOCK_USIZE};
+use servo_media_audio::node::ChannelInterpretation;
+use servo_media_audio::simd::{self, scalar};
+
+fn samples(len: usize) -> Vec<f32> {
+    (0..len).map(|i| (i as f32 * 0.01).sin()).collect()
+}
+
+fn kernels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kernels");
+    // A mono block, and a 5.1 block
+    for len in [FRAMES_PER_BLOCK_USIZE, 6 * FRAMES_PER_BLOCK_USIZE] {
+        let src = samples(len);
+        let gains = vec![1.; len];
+        let mut dst = samples(len);
+
+        group.bench_with_input(BenchmarkId::new("add/simd", len), &len, |b, _| {
+            b.iter(|| simd::add(black_box(&mut dst), black_box(&src)))
+        });
+        group.bench_with_input(BenchmarkId::new("add/scalar", len), &len, |b, _| {
+            b.iter(|| scalar::add(black_box(&mut dst), black_box(&src)))
+        });
+        group.bench_with_input(BenchmarkId::new("scale/simd", len), &len, |b, _| {
+            b.iter(|| simd::scale(black_box(&mut dst), black_box(1.)))
+        });
+        group.bench_with_input(BenchmarkId::new("scale/scalar", len), &len, |b, _| {
+            b.iter(|| scalar::scale(black_box(&mut dst), black_box(1.)))
+        });
+        group.bench_with_input(BenchmarkId::new("mul/simd", len), &len, |b, _| {
+            b.iter(|| simd::mul(black_box(&mut dst), black_box(&gains)))
+        });
+        group.bench_with_input(BenchmarkId::new("mul/scalar", len), &len, |b, _| {
+            b.iter(|| scalar::mul(black_box(&mut dst), black_box(&gains)))
+        });
+        group.bench_with_input(BenchmarkId::new("add_scaled/simd", len), &len, |b, _| {
+            b.iter(|| simd::add_scaled(black_box(&mut dst), black_box(&src), black_box(0.5)))
+        });
+        group.bench_with_input(BenchmarkId::new("add_scaled/scalar", len), &len, |b, _| {
+            b.iter(|| scalar::add_scaled(black_box(&mut dst), black_box(&src), black_box(0.5)))
+        });
+    }
+
+    let stereo = samples(2 * FRAMES_PER_BLOCK_USIZE);
+    let mut out = vec![0.; stereo.len()];
+    group.bench_function("interleave/simd", |b| {
+        b.iter(|| simd::interleave(black_box(&stereo), 2, black_box(&mut out)))
+    });
+    group.bench_function("interleave/scalar", |b| {
+        b.iter(|| scalar::interleave(black_box(&stereo), 2, black_box(&mut out)))
+    });
+    group.finish();
+}
+
+fn blocks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block");
+    let stereo = Block::for_vec(samples(2 * FRAMES_PER_BLOCK_USIZE), FRAMES_PER_BLOCK_USIZE);
+    let surround = Block::for_vec(samples(6 * FRAMES_PER_BLOCK_USIZE), FRAMES_PER_BLOCK_USIZE);
+
+    // What mixing the inputs of a port connected to many sources costs
+    for sources in [16, 256] {
+        group.bench_with_input(BenchmarkId::new("sum", sources), &sources, |b, &n| {
+            b.iter(|| {
+                (0..n)
+                    .map(|_| stereo.clone())
+                    .fold(Block::silence(FRAMES_PER_BLOCK_USIZE), |acc, block| {
+                        acc.sum(block)
+                    })
+            })
+        });
+    }
+    group.bench_function("mix/5.1-to-stereo", |b| {
+        b.iter(|| {
+            let mut block = surround.clone();
+            block.mix(2, ChannelInterpretation::Speakers);
+            block
+        })
+    });
+    group.bench_function("apply_gain", |b| {
+        let mut block = stereo.clone();
+        b.iter(|| block.apply_gain(black_box(1.)))
+    });
+    group.bench_function("interleave", |b| {
+        let mut block = stereo.clone();
+        b.iter(|| block.interleave())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, kernels, blocks);
+criterion_main!(benches);
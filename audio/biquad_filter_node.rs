@@ -1,11 +1,13 @@
 use crate::block::Chunk;
 use crate::block::Tick;
+use crate::iir_filter_node::FrequencyResponse;
 use crate::node::AudioNodeEngine;
 use crate::node::BlockInfo;
 use crate::node::{AudioNodeMessage, AudioNodeType, ChannelInfo};
 use crate::param::{Param, ParamType};
 use smallvec::SmallVec;
 use std::f64::consts::{PI, SQRT_2};
+use std::sync::mpsc::SyncSender;
 
 #[derive(Copy, Clone, Debug)]
 pub struct BiquadFilterNodeOptions {
@@ -40,9 +42,38 @@ impl Default for BiquadFilterNodeOptions {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum BiquadFilterNodeMessage {
     SetFilterType(FilterType),
+    /// Get the coefficients for the current (possibly automated) param
+    /// values, from which the receiver computes the frequency response
+    GetCoefficients(SyncSender<BiquadCoefficients>),
+}
+
+/// The coefficients of a biquad filter, normalized so that `a0` is 1.
+///
+/// https://webaudio.github.io/web-audio-api/#filters-characteristics
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BiquadCoefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl BiquadCoefficients {
+    /// The response of the filter at each frequency, in Hz
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-biquadfilternode-getfrequencyresponse
+    pub fn frequency_response(&self, frequency_hz: &[f32], sample_rate: f32) -> FrequencyResponse {
+        FrequencyResponse::new(
+            &[self.b0, self.b1, self.b2],
+            &[1., self.a1, self.a2],
+            frequency_hz,
+            sample_rate,
+        )
+    }
 }
 
 /// The last two input and output values, per-channel
@@ -398,6 +429,15 @@ impl AudioNodeEngine for BiquadFilterNode {
         }
     }
 
+    fn param_types(&self) -> &[ParamType] {
+        &[
+            ParamType::Frequency,
+            ParamType::Detune,
+            ParamType::Q,
+            ParamType::Gain,
+        ]
+    }
+
     fn message_specific(&mut self, message: AudioNodeMessage, sample_rate: f32) {
         match message {
             AudioNodeMessage::BiquadFilterNode(m) => match m {
@@ -405,6 +445,15 @@ impl AudioNodeEngine for BiquadFilterNode {
                     self.filter = f;
                     self.update_coefficients(sample_rate);
                 },
+                BiquadFilterNodeMessage::GetCoefficients(tx) => {
+                    let _ = tx.try_send(BiquadCoefficients {
+                        b0: self.b0,
+                        b1: self.b1,
+                        b2: self.b2,
+                        a1: self.a1,
+                        a2: self.a2,
+                    });
+                },
             },
             _ => (),
         }
@@ -1,16 +1,35 @@
 use crate::graph::{PortIndex, PortKind};
 use crate::node::ChannelInterpretation;
+use crate::simd;
 use byte_slice_cast::*;
 use euclid::default::Vector3D;
 use smallvec::{SmallVec, smallvec};
-use std::f32::consts::SQRT_2;
+use std::f32::consts::FRAC_1_SQRT_2;
 use std::mem;
 use std::ops::*;
 
 // defined by spec
 // https://webaudio.github.io/web-audio-api/#render-quantum
+/// The number of frames in a block, unless a context asks for another
+/// render quantum size
 pub const FRAMES_PER_BLOCK: Tick = Tick(128);
 pub const FRAMES_PER_BLOCK_USIZE: usize = FRAMES_PER_BLOCK.0 as usize;
+/// The smallest render quantum size supported. Sizes must be powers of two.
+pub const MIN_FRAMES_PER_BLOCK: usize = 32;
+/// The largest render quantum size supported
+pub const MAX_FRAMES_PER_BLOCK: usize = 4096;
+
+/// The supported render quantum size nearest to `frames`: a power of two
+/// between MIN_FRAMES_PER_BLOCK and MAX_FRAMES_PER_BLOCK
+pub fn nearest_frames_per_block(frames: usize) -> usize {
+    let frames = frames.clamp(MIN_FRAMES_PER_BLOCK, MAX_FRAMES_PER_BLOCK);
+    let below = 1 << frames.ilog2();
+    if frames - below <= 2 * below - frames {
+        below
+    } else {
+        2 * below
+    }
+}
 
 /// A tick, i.e. the time taken for a single frame
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
@@ -41,15 +60,16 @@ impl Chunk {
         self.blocks.len()
     }
 
-    pub fn explicit_silence() -> Self {
-        let mut block = Block::default();
+    pub fn explicit_silence(frames: usize) -> Self {
+        let mut block = Block::silence(frames);
         block.explicit_silence();
         let blocks = smallvec![block];
         Self { blocks }
     }
 }
 
-/// We render audio in blocks of size FRAMES_PER_BLOCK
+/// We render audio in blocks of the render quantum size of the context,
+/// FRAMES_PER_BLOCK by default
 ///
 /// A single block may contain multiple channels
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -60,41 +80,44 @@ pub struct Block {
     /// same content at once. Happens when audio is upmixed or when a source like
     /// an oscillator node has multiple channel outputs
     repeat: bool,
+    /// The number of frames in each channel
+    frames: usize,
     /// If this vector is empty, it is a shorthand for "silence"
     /// It is possible to obtain an explicitly silent buffer via .explicit_silence()
     ///
-    /// This must be of length channels * FRAMES_PER_BLOCK, unless `repeat` is true,
-    /// in which case it will be of length FRAMES_PER_BLOCK
+    /// This must be of length channels * frames, unless `repeat` is true,
+    /// in which case it will be of length frames
     buffer: Vec<f32>,
 }
 
-impl Default for Block {
-    fn default() -> Self {
+impl Block {
+    /// Silent mono block of the given number of frames
+    pub fn silence(frames: usize) -> Self {
         Block {
             channels: 1,
             repeat: false,
+            frames,
             buffer: Vec::new(),
         }
     }
-}
 
-impl Block {
     /// Empty block with no channels, for pushing
     /// new channels to.
     ///
     /// Must be used with push_chan
-    pub fn empty() -> Self {
+    pub fn empty(frames: usize) -> Self {
         Block {
             channels: 0,
-            ..Default::default()
+            ..Block::silence(frames)
         }
     }
 
-    pub fn for_channels_explicit(channels: u8) -> Self {
+    pub fn for_channels_explicit(channels: u8, frames: usize) -> Self {
         Block {
             channels,
             repeat: false,
-            buffer: vec![0.; FRAMES_PER_BLOCK_USIZE * channels as usize],
+            frames,
+            buffer: vec![0.; frames * channels as usize],
         }
     }
 
@@ -103,15 +126,22 @@ impl Block {
         self.data_mut().as_mut_byte_slice()
     }
 
-    pub fn for_vec(buffer: Vec<f32>) -> Self {
-        assert!(buffer.len() % FRAMES_PER_BLOCK_USIZE == 0);
+    /// Block made of consecutive channels of `frames` frames each
+    pub fn for_vec(buffer: Vec<f32>, frames: usize) -> Self {
+        assert!(buffer.len().is_multiple_of(frames));
         Block {
-            channels: (buffer.len() / FRAMES_PER_BLOCK_USIZE) as u8,
+            channels: (buffer.len() / frames) as u8,
             repeat: false,
+            frames,
             buffer,
         }
     }
 
+    /// The number of frames in each channel
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
     /// Zero-gain sum with another buffer
     ///
     /// Used after mixing multiple inputs to a single port
@@ -122,14 +152,13 @@ impl Block {
             self
         } else {
             debug_assert_eq!(self.channels, other.channels);
+            debug_assert_eq!(self.frames, other.frames);
             if self.repeat ^ other.repeat {
                 self.explicit_repeat();
                 other.explicit_repeat();
             }
             debug_assert_eq!(self.buffer.len(), other.buffer.len());
-            for (a, b) in self.buffer.iter_mut().zip(other.buffer.iter()) {
-                *a += b
-            }
+            simd::add(&mut self.buffer, &other.buffer);
             self
         }
     }
@@ -137,7 +166,7 @@ impl Block {
     /// If this is in "silence" mode without a buffer, allocate a silent buffer
     pub fn explicit_silence(&mut self) {
         if self.buffer.is_empty() {
-            self.buffer.resize(FRAMES_PER_BLOCK_USIZE, 0.);
+            self.buffer.resize(self.frames, 0.);
             self.repeat = true;
         }
     }
@@ -150,9 +179,9 @@ impl Block {
 
     pub fn explicit_repeat(&mut self) {
         if self.repeat {
-            debug_assert!(self.buffer.len() == FRAMES_PER_BLOCK_USIZE);
+            debug_assert!(self.buffer.len() == self.frames);
             if self.channels > 1 {
-                let mut new = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE * self.channels as usize);
+                let mut new = Vec::with_capacity(self.frames * self.channels as usize);
                 for _ in 0..self.channels {
                     new.extend(&self.buffer)
                 }
@@ -161,15 +190,31 @@ impl Block {
             }
             self.repeat = false;
         } else if self.is_silence() {
-            self.buffer
-                .resize(FRAMES_PER_BLOCK_USIZE * self.channels as usize, 0.);
+            self.buffer.resize(self.frames * self.channels as usize, 0.);
+        }
+    }
+
+    /// Whether every sample is quieter than the given amplitude
+    pub fn is_quieter_than(&self, amplitude: f32) -> bool {
+        self.buffer.iter().all(|sample| sample.abs() < amplitude)
+    }
+
+    /// Silence all the frames outside of the given range
+    pub fn silence_outside(&mut self, frames: Range<usize>) {
+        if self.is_silence() {
+            return;
+        }
+        for chan in 0..self.channels {
+            let data = self.data_chan_mut(chan);
+            data[..frames.start].fill(0.);
+            data[frames.end..].fill(0.);
         }
     }
 
     pub fn data_chan_mut(&mut self, chan: u8) -> &mut [f32] {
         self.explicit_repeat();
-        let start = chan as usize * FRAMES_PER_BLOCK_USIZE;
-        &mut self.buffer[start..start + FRAMES_PER_BLOCK_USIZE]
+        let start = chan as usize * self.frames;
+        &mut self.buffer[start..start + self.frames]
     }
 
     #[inline]
@@ -181,13 +226,13 @@ impl Block {
         let offset = if self.repeat {
             0
         } else {
-            chan as usize * FRAMES_PER_BLOCK_USIZE
+            chan as usize * self.frames
         };
-        &self.buffer[offset..offset + FRAMES_PER_BLOCK_USIZE]
+        &self.buffer[offset..offset + self.frames]
     }
 
     pub fn take(&mut self) -> Block {
-        let mut new = Block::default();
+        let mut new = Block::silence(self.frames);
         new.channels = self.channels;
         mem::replace(self, new)
     }
@@ -219,7 +264,7 @@ impl Block {
     pub fn push_chan(&mut self, data: &[f32]) {
         assert!(!self.repeat);
         assert!(!self.is_silence() || self.channels == 0);
-        assert!(data.len() == FRAMES_PER_BLOCK_USIZE);
+        assert!(data.len() == self.frames);
         self.buffer.extend(data);
         self.channels += 1;
     }
@@ -279,9 +324,9 @@ impl Block {
                     self.resize_silence(4);
                 },
                 (1, 6) => {
-                    let mut v = Vec::with_capacity(channels as usize * FRAMES_PER_BLOCK_USIZE);
+                    let mut v = Vec::with_capacity(channels as usize * self.frames);
                     // output.{L, R} = 0
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
+                    v.resize(2 * self.frames, 0.);
                     // output.C = input
                     v.extend(&self.buffer);
                     self.buffer = v;
@@ -304,13 +349,13 @@ impl Block {
                     // a `repeat` quad block should be rare
                     self.explicit_repeat();
 
-                    let mut v = Vec::with_capacity(6 * FRAMES_PER_BLOCK_USIZE);
+                    let mut v = Vec::with_capacity(6 * self.frames);
                     // output.{L, R} = input.{L, R}
-                    v.extend(&self.buffer[0..2 * FRAMES_PER_BLOCK_USIZE]);
+                    v.extend(&self.buffer[0..2 * self.frames]);
                     // output.{C, LFE} = 0
-                    v.resize(4 * FRAMES_PER_BLOCK_USIZE, 0.);
+                    v.resize(4 * self.frames, 0.);
                     // output.{SL, R} = input.{SL, SR}
-                    v.extend(&self.buffer[2 * FRAMES_PER_BLOCK_USIZE..]);
+                    v.extend(&self.buffer[2 * self.frames..]);
                     self.buffer = v;
                     self.channels = channels;
                 },
@@ -320,107 +365,49 @@ impl Block {
 
                 // mono
                 (2, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = 0.5 * (input.L + input.R);
-                        let o =
-                            0.5 * (self.data_chan_frame(frame, 0) + self.data_chan_frame(frame, 1));
-                        v.push(o);
-                    }
-                    self.buffer = v;
-                    self.channels = 1;
-                    self.repeat = false;
+                    // output = 0.5 * (input.L + input.R);
+                    self.mix_matrix(&[&[0.5, 0.5]]);
                 },
                 (4, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = 0.5 * (input.L + input.R + input.SL + input.SR);
-                        let o = 0.25
-                            * (self.data_chan_frame(frame, 0)
-                                + self.data_chan_frame(frame, 1)
-                                + self.data_chan_frame(frame, 2)
-                                + self.data_chan_frame(frame, 3));
-                        v.push(o);
-                    }
-                    self.buffer = v;
-                    self.channels = 1;
-                    self.repeat = false;
+                    // output = 0.5 * (input.L + input.R + input.SL + input.SR);
+                    self.mix_matrix(&[&[0.25, 0.25, 0.25, 0.25]]);
                 },
                 (6, 1) => {
-                    let mut v = Vec::with_capacity(FRAMES_PER_BLOCK_USIZE);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
-                        // output = sqrt(0.5) * (input.L + input.R) + input.C + 0.5 * (input.SL + input.SR)
-                        let o =
-                            // sqrt(0.5) * (input.L + input.R)
-                            SQRT_2 * (self.data_chan_frame(frame, 0) +
-                                      self.data_chan_frame(frame, 1)) +
-                            // input.C
-                            self.data_chan_frame(frame, 2) +
-                            // (ignore LFE)
-                            // + 0 * self.buffer[frame + 3 * FRAMES_PER_BLOCK_USIZE]
-                            // 0.5 * (input.SL + input.SR)
-                            0.5 * (self.data_chan_frame(frame, 4) +
-                                   self.data_chan_frame(frame, 5));
-                        v.push(o);
-                    }
-                    self.buffer = v;
-                    self.channels = 1;
-                    self.repeat = false;
+                    // output = sqrt(0.5) * (input.L + input.R) + input.C + 0.5 * (input.SL + input.SR)
+                    // (ignore LFE)
+                    self.mix_matrix(&[&[FRAC_1_SQRT_2, FRAC_1_SQRT_2, 1., 0., 0.5, 0.5]]);
                 },
 
                 // stereo
                 (4, 2) => {
-                    let mut v = Vec::with_capacity(2 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    self.mix_matrix(&[
                         // output.L = 0.5 * (input.L + input.SL)
-                        v[frame] =
-                            0.5 * (self.data_chan_frame(frame, 0) + self.data_chan_frame(frame, 2));
+                        &[0.5, 0., 0.5, 0.],
                         // output.R = 0.5 * (input.R + input.SR)
-                        v[frame + FRAMES_PER_BLOCK_USIZE] =
-                            0.5 * (self.data_chan_frame(frame, 1) + self.data_chan_frame(frame, 3));
-                    }
-                    self.buffer = v;
-                    self.channels = 2;
-                    self.repeat = false;
+                        &[0., 0.5, 0., 0.5],
+                    ]);
                 },
                 (6, 2) => {
-                    let mut v = Vec::with_capacity(2 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(2 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    self.mix_matrix(&[
                         // output.L = L + sqrt(0.5) * (input.C + input.SL)
-                        v[frame] = self.data_chan_frame(frame, 0)
-                            + SQRT_2
-                                * (self.data_chan_frame(frame, 2) + self.data_chan_frame(frame, 4));
+                        &[1., 0., FRAC_1_SQRT_2, 0., FRAC_1_SQRT_2, 0.],
                         // output.R = R + sqrt(0.5) * (input.C + input.SR)
-                        v[frame + FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 1)
-                            + SQRT_2
-                                * (self.data_chan_frame(frame, 2) + self.data_chan_frame(frame, 5));
-                    }
-                    self.buffer = v;
-                    self.channels = 2;
-                    self.repeat = false;
+                        &[0., 1., FRAC_1_SQRT_2, 0., 0., FRAC_1_SQRT_2],
+                    ]);
                 },
 
                 // quad
                 (6, 4) => {
-                    let mut v = Vec::with_capacity(6 * FRAMES_PER_BLOCK_USIZE);
-                    v.resize(6 * FRAMES_PER_BLOCK_USIZE, 0.);
-                    for frame in 0..FRAMES_PER_BLOCK_USIZE {
+                    self.mix_matrix(&[
                         // output.L = L + sqrt(0.5) * input.C
-                        v[frame] = self.data_chan_frame(frame, 0)
-                            + SQRT_2 * self.data_chan_frame(frame, 2);
+                        &[1., 0., FRAC_1_SQRT_2, 0., 0., 0.],
                         // output.R = R + sqrt(0.5) * input.C
-                        v[frame + FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 1)
-                            + SQRT_2 * self.data_chan_frame(frame, 2);
+                        &[0., 1., FRAC_1_SQRT_2, 0., 0., 0.],
                         // output.SL = input.SL
-                        v[frame + 2 * FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 4);
+                        &[0., 0., 0., 0., 1., 0.],
                         // output.SR = input.SR
-                        v[frame + 3 * FRAMES_PER_BLOCK_USIZE] = self.data_chan_frame(frame, 5);
-                    }
-                    self.buffer = v;
-                    self.channels = 4;
-                    self.repeat = false;
+                        &[0., 0., 0., 0., 0., 1.],
+                    ]);
                 },
 
                 // If it's not a known kind of speaker configuration, treat as
@@ -433,11 +420,40 @@ impl Block {
         }
     }
 
+    /// Replace the channels with the given mix of them, one row of
+    /// coefficients per output channel. Block must not be silence.
+    fn mix_matrix(&mut self, matrix: &[&[f32]]) {
+        let mut v = vec![0.; matrix.len() * self.frames];
+        for (row, out) in matrix.iter().zip(v.chunks_exact_mut(self.frames)) {
+            debug_assert_eq!(row.len(), self.channels as usize);
+            for (chan, &coeff) in row.iter().enumerate() {
+                if coeff != 0. {
+                    simd::add_scaled(out, self.data_chan(chan as u8), coeff);
+                }
+            }
+        }
+        self.buffer = v;
+        self.channels = matrix.len() as u8;
+        self.repeat = false;
+    }
+
+    /// Multiply every sample by `gain`
+    pub fn apply_gain(&mut self, gain: f32) {
+        simd::scale(&mut self.buffer, gain);
+    }
+
+    /// Multiply every frame by the matching gain, in all channels
+    pub fn apply_gains(&mut self, gains: &[f32]) {
+        debug_assert_eq!(gains.len(), self.frames);
+        for chan in self.buffer.chunks_exact_mut(self.frames) {
+            simd::mul(chan, gains);
+        }
+    }
+
     /// Resize to add or remove channels, fill extra channels with silence
     pub fn resize_silence(&mut self, channels: u8) {
         self.explicit_repeat();
-        self.buffer
-            .resize(FRAMES_PER_BLOCK_USIZE * channels as usize, 0.);
+        self.buffer.resize(self.frames * channels as usize, 0.);
         self.channels = channels;
     }
 
@@ -453,15 +469,8 @@ impl Block {
 
     pub fn interleave(&mut self) -> Vec<f32> {
         self.explicit_repeat();
-        let mut vec = Vec::with_capacity(self.buffer.len());
-        // FIXME this isn't too efficient
-        vec.resize(self.buffer.len(), 0.);
-        for frame in 0..FRAMES_PER_BLOCK_USIZE {
-            let channels = self.channels as usize;
-            for chan in 0..channels {
-                vec[frame * channels + chan] = self.buffer[chan * FRAMES_PER_BLOCK_USIZE + frame]
-            }
-        }
+        let mut vec = vec![0.; self.buffer.len()];
+        simd::interleave(&self.buffer, self.channels as usize, &mut vec);
         vec
     }
 
@@ -516,7 +525,7 @@ impl<'a> FrameIterator<'a> {
     #[inline]
     pub fn next<'b>(&'b mut self) -> Option<FrameRef<'b>> {
         let curr = self.frame;
-        if curr < FRAMES_PER_BLOCK {
+        if curr.0 < self.block.frames as u64 {
             self.frame.advance();
             Some(FrameRef {
                 frame: curr,
@@ -564,7 +573,7 @@ impl<'a> FrameRef<'a> {
             for chan in 0..self.block.channels {
                 f(
                     &mut self.block.buffer
-                        [chan as usize * FRAMES_PER_BLOCK_USIZE + self.frame.0 as usize],
+                        [chan as usize * self.block.frames + self.frame.0 as usize],
                     chan,
                 )
             }
@@ -637,7 +646,6 @@ impl Div<f64> for Tick {
 }
 
 impl Tick {
-    pub const FRAMES_PER_BLOCK: Tick = FRAMES_PER_BLOCK;
     const EPSILON: f64 = 1e-7;
     pub fn from_time(time: f64, rate: f32) -> Tick {
         Tick((time * rate as f64 - Tick::EPSILON).ceil() as u64)
@@ -647,3 +655,74 @@ impl Tick {
         self.0 += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_render_quantum_sizes_are_rounded() {
+        assert_eq!(nearest_frames_per_block(128), 128);
+        assert_eq!(nearest_frames_per_block(0), MIN_FRAMES_PER_BLOCK);
+        assert_eq!(nearest_frames_per_block(100), 128);
+        assert_eq!(nearest_frames_per_block(96), 64);
+        assert_eq!(nearest_frames_per_block(200), 256);
+        assert_eq!(nearest_frames_per_block(1000), 1024);
+        assert_eq!(nearest_frames_per_block(usize::MAX), MAX_FRAMES_PER_BLOCK);
+    }
+
+    /// The down-mix of a single frame, as the spec writes it
+    ///
+    /// https://webaudio.github.io/web-audio-api/#down-mix
+    fn down_mix_frame(input: &[f32], channels: usize) -> Vec<f32> {
+        let s = FRAC_1_SQRT_2;
+        match (input, channels) {
+            (&[l, r], 1) => vec![0.5 * (l + r)],
+            (&[l, r, sl, sr], 1) => vec![0.25 * (l + r + sl + sr)],
+            (&[l, r, c, _, sl, sr], 1) => vec![s * (l + r) + c + 0.5 * (sl + sr)],
+            (&[l, r, sl, sr], 2) => vec![0.5 * (l + sl), 0.5 * (r + sr)],
+            (&[l, r, c, _, sl, sr], 2) => vec![l + s * (c + sl), r + s * (c + sr)],
+            (&[l, r, c, _, sl, sr], 4) => vec![l + s * c, r + s * c, sl, sr],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn speaker_down_mixes_match_the_spec() {
+        for (from, to) in [(2, 1), (4, 1), (6, 1), (4, 2), (6, 2), (6, 4)] {
+            // Odd lengths exercise the scalar tails of the kernels
+            for frames in [1, 3, 7, 9, 17, 128, 131] {
+                let mut block = Block::for_channels_explicit(from, frames);
+                for chan in 0..from {
+                    for (frame, sample) in block.data_chan_mut(chan).iter_mut().enumerate() {
+                        *sample = (frame as f32 * 0.37 + chan as f32).sin();
+                    }
+                }
+                let input: Vec<Vec<f32>> = (0..frames)
+                    .map(|frame| {
+                        (0..from)
+                            .map(|chan| block.data_chan_frame(frame, chan))
+                            .collect()
+                    })
+                    .collect();
+
+                block.mix(to, ChannelInterpretation::Speakers);
+                assert_eq!(block.chan_count(), to);
+                for (frame, input) in input.iter().enumerate() {
+                    let expected = down_mix_frame(input, to as usize);
+                    for chan in 0..to {
+                        let actual = block.data_chan_frame(frame, chan);
+                        assert!(
+                            (actual - expected[chan as usize]).abs() < 1e-6,
+                            "{} to {} channels, frame {} of {}",
+                            from,
+                            to,
+                            frame,
+                            frames
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
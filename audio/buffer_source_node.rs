@@ -1,6 +1,6 @@
-use crate::block::{Block, Chunk, FRAMES_PER_BLOCK, Tick};
+use crate::block::{Block, Chunk, Tick};
 use crate::node::{AudioNodeEngine, AudioScheduledSourceNodeMessage, BlockInfo, OnEndedCallback};
-use crate::node::{AudioNodeType, ChannelInfo, ShouldPlay};
+use crate::node::{AudioNodeType, ChannelInfo, Garbage, ShouldPlay};
 use crate::param::{Param, ParamType};
 
 /// Control messages directed to AudioBufferSourceNodes.
@@ -93,6 +93,8 @@ pub(crate) struct AudioBufferSourceNode {
     stop_at: Option<Tick>,
     /// The ended event callback.
     pub onended_callback: Option<OnEndedCallback>,
+    /// The buffer replaced by the last SetBuffer message
+    replaced: Option<AudioBuffer>,
 }
 
 impl AudioBufferSourceNode {
@@ -114,13 +116,24 @@ impl AudioBufferSourceNode {
             start_when: 0.,
             stop_at: None,
             onended_callback: None,
+            replaced: None,
         }
     }
 
+    /// Whether playback reached the end of the buffer, or of the duration
+    /// given to start()
+    fn played_out(&self) -> bool {
+        let Some(ref buffer) = self.buffer else {
+            return false;
+        };
+        !self.loop_enabled && (self.buffer_pos < 0. || self.buffer_pos >= buffer.len() as f64)
+            || self.buffer_duration <= 0.
+    }
+
     pub fn handle_message(&mut self, message: AudioBufferSourceNodeMessage, _: f32) {
         match message {
             AudioBufferSourceNodeMessage::SetBuffer(buffer) => {
-                self.buffer = buffer;
+                self.replaced = std::mem::replace(&mut self.buffer, buffer);
             },
             // XXX(collares): To fully support dynamically updating loop bounds,
             // Must truncate self.buffer_pos if it is now outside the loop.
@@ -145,6 +158,14 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         AudioNodeType::AudioBufferSourceNode
     }
 
+    fn has_finished(&self, frame: Tick) -> bool {
+        self.has_ended(frame) || self.played_out()
+    }
+
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        self.replaced.take().map(Garbage::Buffer)
+    }
+
     fn input_count(&self) -> u32 {
         0
     }
@@ -153,13 +174,13 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         debug_assert!(inputs.len() == 0);
 
         if self.buffer.is_none() {
-            inputs.blocks.push(Default::default());
+            inputs.blocks.push(Block::silence(info.frames_per_block));
             return inputs;
         }
 
-        let (start_at, stop_at) = match self.should_play_at(info.frame) {
+        let (start_at, stop_at) = match self.should_play_at(info) {
             ShouldPlay::No => {
-                inputs.blocks.push(Default::default());
+                inputs.blocks.push(Block::silence(info.frames_per_block));
                 return inputs;
             },
             ShouldPlay::Between(start, end) => (start.0 as usize, end.0 as usize),
@@ -266,16 +287,17 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         // We will output at most this many frames (fewer if we run out of data).
         let frames_to_output = stop_at - start_at;
 
-        // Fast path for the case where we can just copy FRAMES_PER_BLOCK
-        // frames straight from the buffer.
-        if frames_to_output == FRAMES_PER_BLOCK.0 as usize
+        // Fast path for the case where we can just copy a whole block
+        // of frames straight from the buffer.
+        let frames_per_block = info.frames_per_block as f64;
+        if frames_to_output == info.frames_per_block
             && forward
             && buffer_offset_per_tick == 1.
             && self.buffer_pos.trunc() == self.buffer_pos
-            && self.buffer_pos + (FRAMES_PER_BLOCK.0 as f64) <= actual_loop_end
-            && FRAMES_PER_BLOCK.0 as f64 <= self.buffer_duration
+            && self.buffer_pos + frames_per_block <= actual_loop_end
+            && frames_per_block <= self.buffer_duration
         {
-            let mut block = Block::empty();
+            let mut block = Block::empty(info.frames_per_block);
             let pos = self.buffer_pos as usize;
 
             for chan in 0..buffer.chans() {
@@ -283,11 +305,11 @@ impl AudioNodeEngine for AudioBufferSourceNode {
             }
 
             inputs.blocks.push(block);
-            self.buffer_pos += FRAMES_PER_BLOCK.0 as f64;
-            self.buffer_duration -= FRAMES_PER_BLOCK.0 as f64;
+            self.buffer_pos += frames_per_block;
+            self.buffer_duration -= frames_per_block;
         } else {
             // Slow path, with interpolation.
-            let mut block = Block::default();
+            let mut block = Block::silence(info.frames_per_block);
             block.repeat(buffer.chans());
             block.explicit_repeat();
 
@@ -331,9 +353,7 @@ impl AudioNodeEngine for AudioBufferSourceNode {
             inputs.blocks.push(block);
         }
 
-        if !self.loop_enabled && (self.buffer_pos < 0. || self.buffer_pos >= buffer.len() as f64)
-            || self.buffer_duration <= 0.
-        {
+        if self.played_out() {
             self.maybe_trigger_onended_callback();
         }
 
@@ -348,6 +368,10 @@ impl AudioNodeEngine for AudioBufferSourceNode {
         }
     }
 
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::PlaybackRate, ParamType::Detune]
+    }
+
     make_message_handler!(
         AudioBufferSourceNode: handle_message,
         AudioScheduledSourceNode: handle_source_node_message
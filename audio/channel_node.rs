@@ -1,4 +1,3 @@
-use crate::block::FRAMES_PER_BLOCK_USIZE;
 use crate::block::{Block, Chunk};
 use crate::node::AudioNodeType;
 use crate::node::BlockInfo;
@@ -29,16 +28,16 @@ impl AudioNodeEngine for ChannelMergerNode {
         AudioNodeType::ChannelMergerNode
     }
 
-    fn process(&mut self, mut inputs: Chunk, _: &BlockInfo) -> Chunk {
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == self.channels as usize);
 
-        let mut block = Block::default();
+        let mut block = Block::silence(info.frames_per_block);
         block.repeat(self.channels);
         block.explicit_repeat();
 
         for (i, channel) in block
             .data_mut()
-            .chunks_mut(FRAMES_PER_BLOCK_USIZE)
+            .chunks_mut(info.frames_per_block)
             .enumerate()
         {
             channel.copy_from_slice(inputs.blocks[i].data_mut())
@@ -78,18 +77,19 @@ impl AudioNodeEngine for ChannelSplitterNode {
         AudioNodeType::ChannelSplitterNode
     }
 
-    fn process(&mut self, mut inputs: Chunk, _: &BlockInfo) -> Chunk {
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 1);
 
         let original = inputs.blocks.pop().unwrap();
 
         if original.is_silence() {
-            inputs
-                .blocks
-                .resize(original.chan_count() as usize, Block::default())
+            inputs.blocks.resize(
+                original.chan_count() as usize,
+                Block::silence(info.frames_per_block),
+            )
         } else {
             for chan in 0..original.chan_count() {
-                let mut block = Block::empty();
+                let mut block = Block::empty(info.frames_per_block);
                 block.push_chan(original.data_chan(chan));
                 inputs.blocks.push(block);
             }
@@ -1,5 +1,5 @@
-use crate::block::Chunk;
 use crate::block::Tick;
+use crate::block::{Block, Chunk};
 use crate::node::BlockInfo;
 use crate::node::{AudioNodeEngine, AudioScheduledSourceNodeMessage, OnEndedCallback};
 use crate::node::{AudioNodeType, ChannelInfo, ShouldPlay};
@@ -46,12 +46,16 @@ impl AudioNodeEngine for ConstantSourceNode {
         AudioNodeType::ConstantSourceNode
     }
 
+    fn has_finished(&self, frame: Tick) -> bool {
+        self.has_ended(frame)
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 0);
 
-        inputs.blocks.push(Default::default());
+        inputs.blocks.push(Block::silence(info.frames_per_block));
 
-        let (start_at, stop_at) = match self.should_play_at(info.frame) {
+        let (start_at, stop_at) = match self.should_play_at(info) {
             ShouldPlay::No => {
                 return inputs;
             },
@@ -88,5 +92,9 @@ impl AudioNodeEngine for ConstantSourceNode {
             _ => panic!("Unknown param {:?} for the offset", id),
         }
     }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::Offset]
+    }
     make_message_handler!(AudioScheduledSourceNode: handle_source_node_message);
 }
@@ -1,17 +1,49 @@
 use crate::AudioBackend;
+use crate::biquad_filter_node::BiquadFilterNodeMessage;
+use crate::block::{FRAMES_PER_BLOCK_USIZE, Tick, nearest_frames_per_block};
+use crate::buffer_source_node::AudioBuffer;
+use crate::convolver_node::{ConvolverError, ConvolverImpulse, ConvolverNodeMessage};
 use crate::decoder::{AudioDecoder, AudioDecoderCallbacks, AudioDecoderOptions};
-use crate::graph::{AudioGraph, InputPort, NodeId, OutputPort, PortId};
-use crate::node::{AudioNodeInit, AudioNodeMessage, ChannelInfo};
+use crate::export::{AudioFileFormat, AudioFileWriter};
+use crate::graph::{AudioGraph, GraphSnapshot, InputPort, NodeId, OutputPort, PortId};
+use crate::iir_filter_node::FrequencyResponse;
+use crate::node::{AudioNodeError, AudioNodeInit, AudioNodeMessage};
+use crate::node::{ChannelCountMode, ChannelInfo};
+use crate::offline_sink::OfflineStreamCallback;
+use crate::oscillator_node::{OscillatorNodeMessage, OscillatorType};
+use crate::panner_node::{HrtfRenderer, PannerNodeMessage, PanningModel};
+use crate::profiling::{NodeProfile, RenderCapacityReport};
+use crate::queue::{Producer, queue};
 use crate::render_thread::AudioRenderThread;
-use crate::render_thread::AudioRenderThreadMsg;
+use crate::render_thread::{AudioRenderThreadMsg, NewNode, RenderThreadWaker};
+use crate::sink::{AudioSink, AudioSinkLatency};
+use crate::transaction::{GraphCommand, GraphTransaction};
 use servo_media_traits::{BackendMsg, ClientContextId, MediaInstance};
-use std::cell::Cell;
-use std::sync::mpsc::{self, Sender};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::Builder;
+use std::thread::{self, Builder};
+use std::time::{Duration, Instant};
 
 use crate::sink::AudioSinkError;
 
+/// How many control messages can wait for the rendering thread before
+/// senders have to wait for room.
+const MESSAGE_QUEUE_CAPACITY: usize = 1024;
+
+/// How many times to yield, then how long to sleep, between attempts to
+/// queue a message while the queue is full
+const SEND_YIELDS: u32 = 16;
+const SEND_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How many sample-frames are encoded at once when rendering to a file.
+const EXPORT_CHUNK_FRAMES: usize = 1 << 14;
+
 /// Describes the state of the audio context on the control thread.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ProcessingState {
@@ -27,9 +59,81 @@ pub enum ProcessingState {
 
 pub type StateChangeResult = Result<(), ()>;
 
+/// The processing state, published by the rendering thread for the
+/// control thread to read without a round trip
+pub(crate) struct PublishedState(AtomicU8);
+
+impl PublishedState {
+    fn new(state: ProcessingState) -> Self {
+        PublishedState(AtomicU8::new(state as u8))
+    }
+
+    pub(crate) fn get(&self) -> ProcessingState {
+        match self.0.load(Ordering::Acquire) {
+            0 => ProcessingState::Suspended,
+            1 => ProcessingState::Running,
+            _ => ProcessingState::Closed,
+        }
+    }
+
+    pub(crate) fn set(&self, state: ProcessingState) {
+        self.0.store(state as u8, Ordering::Release);
+    }
+}
+
+/// Why an offline context can't be suspended at a given time.
+///
+/// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontext-suspend
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuspendError {
+    /// The time is negative or not finite.
+    InvalidTime,
+    /// The context is closed, the time is not between the current time and
+    /// the end of the rendering, or a suspension is already scheduled in
+    /// the same render quantum.
+    InvalidState,
+    /// Only offline contexts can be suspended at a given time.
+    NotSupported,
+}
+
+/// Something that happened to an audio context on the rendering thread.
+///
+/// https://webaudio.github.io/web-audio-api/#AudioContext-events
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioContextEvent {
+    /// The rendering thread moved to the given state, whether asked to by
+    /// the control thread or by the media backend.
+    StateChanged(ProcessingState),
+    /// The audio sink failed. Only the first of a series of identical
+    /// failures is reported.
+    SinkError(AudioSinkError),
+    /// The audio sink ran out of audio to play, because rendering did not
+    /// keep up with the audio output device.
+    Underrun,
+    /// Render capacity measured over the interval requested with
+    /// `AudioContext::start_render_capacity`.
+    RenderCapacity(RenderCapacityReport),
+    /// The rendering thread stopped because of the given error. The context
+    /// cannot process audio anymore.
+    RenderThreadFailed(String),
+}
+
+/// A sample-frame being played by the audio output device.
+///
+/// https://webaudio.github.io/web-audio-api/#dictdef-audiotimestamp
+#[derive(Clone, Copy, Debug)]
+pub struct AudioTimestamp {
+    /// The time of the sample-frame, in the context's time coordinate system
+    pub context_time: f64,
+    /// The moment at which the sample-frame was played
+    pub performance_time: Instant,
+}
+
 /// Identify the type of playback, which affects tradeoffs between audio output
 /// and power consumption.
-#[derive(Copy, Clone)]
+///
+/// https://webaudio.github.io/web-audio-api/#dom-audiocontextoptions-latencyhint
+#[derive(Copy, Clone, Debug)]
 pub enum LatencyCategory {
     /// Balance audio output latency and power consumption.
     Balanced,
@@ -38,15 +142,47 @@ pub enum LatencyCategory {
     /// Prioritize sustained playback without interruption over audio output latency.
     /// Lowest power consumption.
     Playback,
+    /// Aim for the given latency, in seconds. The actual latency may differ,
+    /// depending on what the audio output device supports.
+    Seconds(f64),
+}
+
+/// Identify the audio output device a real time audio context plays on.
+///
+/// https://webaudio.github.io/web-audio-api/#dom-audiocontextoptions-sinkid
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AudioSinkId {
+    /// The default audio output device of the system.
+    #[default]
+    Default,
+    /// The audio output device with the given `MediaDeviceInfo::device_id`.
+    Device(String),
+    /// Render audio without playing it anywhere.
+    None,
 }
 
 /// User-specified options for a real time audio context.
-#[derive(Copy, Clone)]
+///
+/// Not `Copy`, as `sink_id` can name a device.
+#[derive(Clone)]
 pub struct RealTimeAudioContextOptions {
     /// Number of samples that will play in one second, measured in Hz.
     pub sample_rate: f32,
     /// Type of playback.
     pub latency_hint: LatencyCategory,
+    /// The audio output device to play on, until
+    /// `AudioContext::set_sink_id` picks another one.
+    pub sink_id: AudioSinkId,
+    /// Number of threads rendering independent parts of the graph in
+    /// parallel with the rendering thread. With none, the whole graph is
+    /// rendered on the rendering thread.
+    pub render_workers: usize,
+    /// The number of frames rendered at once. Smaller sizes lower the
+    /// latency, larger ones the rendering overhead. Rounded to the nearest
+    /// supported size, a power of two from 32 to 4096.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontextoptions-rendersizehint
+    pub render_quantum_size: usize,
 }
 
 impl Default for RealTimeAudioContextOptions {
@@ -54,6 +190,9 @@ impl Default for RealTimeAudioContextOptions {
         Self {
             sample_rate: 44100.,
             latency_hint: LatencyCategory::Interactive,
+            sink_id: AudioSinkId::Default,
+            render_workers: 0,
+            render_quantum_size: FRAMES_PER_BLOCK_USIZE,
         }
     }
 }
@@ -67,6 +206,14 @@ pub struct OfflineAudioContextOptions {
     pub length: usize,
     /// Number of samples that will be rendered in one second, measured in Hz.
     pub sample_rate: f32,
+    /// Number of threads rendering independent parts of the graph in
+    /// parallel with the rendering thread.
+    pub render_workers: usize,
+    /// The number of frames rendered at once. Rounded to the nearest
+    /// supported size, a power of two from 32 to 4096.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontextoptions-rendersizehint
+    pub render_quantum_size: usize,
 }
 
 impl Default for OfflineAudioContextOptions {
@@ -75,6 +222,8 @@ impl Default for OfflineAudioContextOptions {
             channels: 1,
             length: 0,
             sample_rate: 44100.,
+            render_workers: 0,
+            render_quantum_size: FRAMES_PER_BLOCK_USIZE,
         }
     }
 }
@@ -91,8 +240,13 @@ impl From<OfflineAudioContextOptions> for AudioContextOptions {
     }
 }
 
+/// Makes sinks playing to the audio output device with the given id
+type SinkFactory = dyn Fn(&AudioSinkId) -> Result<Box<dyn AudioSink>, AudioSinkError> + Send;
+
 /// User-specified options for a real time or offline audio context.
-#[derive(Copy, Clone)]
+///
+/// Not `Copy`, as the options of real time contexts aren't.
+#[derive(Clone)]
 pub enum AudioContextOptions {
     RealTimeAudioContext(RealTimeAudioContextOptions),
     OfflineAudioContext(OfflineAudioContextOptions),
@@ -113,16 +267,30 @@ pub struct AudioContext {
     /// Owner backend communication channel.
     backend_chan: Arc<Mutex<Sender<BackendMsg>>>,
     /// Rendering thread communication channel.
-    sender: Sender<AudioRenderThreadMsg>,
-    /// State of the audio context on the control thread.
-    state: Cell<ProcessingState>,
+    queue: Producer<AudioRenderThreadMsg>,
+    /// The context time, published by the rendering thread.
+    current_time: Arc<AtomicU64>,
+    /// The id of the last node created.
+    last_node_id: Cell<NodeId>,
+    /// The channel count of every ScriptProcessorNode, which can't change
+    script_processors: RefCell<HashMap<NodeId, u8>>,
+    /// Builds node engines, on the control thread.
+    make_node: Box<dyn Fn(NodeId, AudioNodeInit, ChannelInfo) -> NewNode + Send>,
+    /// State of the audio context, published by the rendering thread.
+    state: Arc<PublishedState>,
     /// Number of samples that will be played in one second.
     sample_rate: f32,
+    /// The render quantum size.
+    frames_per_block: usize,
     /// The identifier of an AudioDestinationNode with a single input
     /// representing the final destination for all audio.
     dest_node: NodeId,
     listener: NodeId,
     make_decoder: Arc<dyn Fn() -> Box<dyn AudioDecoder> + Sync + Send>,
+    /// Makes and initializes sinks, on the control thread.
+    make_sink: Box<SinkFactory>,
+    /// The audio output device. None for offline contexts.
+    sink_id: RefCell<Option<AudioSinkId>>,
 }
 
 impl AudioContext {
@@ -131,35 +299,65 @@ impl AudioContext {
         id: usize,
         client_context_id: &ClientContextId,
         backend_chan: Arc<Mutex<Sender<BackendMsg>>>,
-        options: AudioContextOptions,
+        mut options: AudioContextOptions,
     ) -> Result<Self, AudioSinkError> {
-        let (sample_rate, channels) = match options {
-            AudioContextOptions::RealTimeAudioContext(ref options) => (options.sample_rate, 2),
-            AudioContextOptions::OfflineAudioContext(ref options) => {
-                (options.sample_rate, options.channels)
+        // https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-renderquantumsize
+        let render_quantum_size = match options {
+            AudioContextOptions::RealTimeAudioContext(ref mut options) => {
+                &mut options.render_quantum_size
+            },
+            AudioContextOptions::OfflineAudioContext(ref mut options) => {
+                &mut options.render_quantum_size
             },
         };
-
-        let (sender, receiver) = mpsc::channel();
-        let sender_ = sender.clone();
-        let graph = AudioGraph::new(channels);
+        *render_quantum_size = nearest_frames_per_block(*render_quantum_size);
+
+        let (sample_rate, channels, sink_id, latency_hint, render_workers, frames_per_block) =
+            match options {
+                AudioContextOptions::RealTimeAudioContext(ref options) => (
+                    options.sample_rate,
+                    2,
+                    Some(options.sink_id.clone()),
+                    options.latency_hint,
+                    options.render_workers,
+                    options.render_quantum_size,
+                ),
+                AudioContextOptions::OfflineAudioContext(ref options) => (
+                    options.sample_rate,
+                    options.channels,
+                    None,
+                    LatencyCategory::Playback,
+                    options.render_workers,
+                    options.render_quantum_size,
+                ),
+            };
+        let (mut queue, receiver) = queue(MESSAGE_QUEUE_CAPACITY);
+        let current_time = Arc::new(AtomicU64::new(0f64.to_bits()));
+        let current_time_ = current_time.clone();
+        let state = Arc::new(PublishedState::new(ProcessingState::Suspended));
+        let state_ = state.clone();
+        let offline = matches!(options, AudioContextOptions::OfflineAudioContext(_));
+        let graph = AudioGraph::new(channels, frames_per_block, render_workers);
         let dest_node = graph.dest_id();
         let listener = graph.listener_id();
 
         let (init_sender, init_receiver) = mpsc::channel();
-        Builder::new()
+        let join_handle = Builder::new()
             .name("AudioRenderThread".to_owned())
             .spawn(move || {
                 AudioRenderThread::start::<B>(
                     receiver,
-                    sender_,
                     sample_rate,
+                    current_time_,
+                    state_,
                     graph,
                     options,
                     init_sender,
                 )
             })
             .expect("Failed to spawn AudioRenderThread");
+        queue.set_consumer_thread(join_handle.thread().clone());
+        let waker = RenderThreadWaker::new(join_handle.thread().clone());
 
         let init_thread_result = init_receiver
             .recv()
@@ -173,12 +371,25 @@ impl AudioContext {
             id,
             client_context_id: *client_context_id,
             backend_chan,
-            sender,
-            state: Cell::new(ProcessingState::Suspended),
+            queue,
+            current_time,
+            last_node_id: Cell::new(listener),
+            script_processors: Default::default(),
+            make_node: Box::new(move |id, node_type, ch| {
+                NewNode::new::<B>(id, node_type, ch, sample_rate, frames_per_block, offline)
+            }),
+            state,
             sample_rate,
+            frames_per_block,
             dest_node,
             listener,
             make_decoder: Arc::new(|| B::make_decoder()),
+            make_sink: Box::new(move |sink_id| {
+                let sink = AudioRenderThread::make_sink::<B>(sink_id)?;
+                sink.init(sample_rate, frames_per_block, latency_hint, waker.clone())?;
+                Ok(sink)
+            }),
+            sink_id: RefCell::new(sink_id),
         })
     }
 
@@ -194,18 +405,127 @@ impl AudioContext {
         self.listener
     }
 
+    /// The number of frames rendered at once
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-renderquantumsize
+    pub fn render_quantum_size(&self) -> usize {
+        self.frames_per_block
+    }
+
     pub fn current_time(&self) -> f64 {
-        let (tx, rx) = mpsc::channel();
-        let _ = self.sender.send(AudioRenderThreadMsg::GetCurrentTime(tx));
-        rx.recv().unwrap()
+        f64::from_bits(self.current_time.load(Ordering::Relaxed))
     }
 
-    pub fn create_node(&self, node_type: AudioNodeInit, ch: ChannelInfo) -> Result<NodeId, ()> {
-        let (tx, rx) = mpsc::channel();
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::CreateNode(node_type, tx, ch));
-        rx.recv().map_err(|_| ())
+    /// Queue a message for the rendering thread, waiting for room if the
+    /// queue is full. Fails once the rendering thread is gone.
+    ///
+    /// The rendering thread doesn't signal when it makes room, as that
+    /// could block it, so this polls the queue: yielding at first, then
+    /// sleeping for `SEND_RETRY_INTERVAL` between attempts. A full queue
+    /// means the rendering thread is stalled or parked, for instance while
+    /// a real-time sink has enough data, so the wait can be long.
+    fn send(&self, mut msg: AudioRenderThreadMsg) -> Result<(), ()> {
+        for attempt in 0.. {
+            match self.queue.push(msg) {
+                Ok(()) => return Ok(()),
+                Err(_) if self.queue.is_closed() => return Err(()),
+                Err(returned) => {
+                    msg = returned;
+                    if attempt < SEND_YIELDS {
+                        thread::yield_now();
+                    } else {
+                        thread::sleep(SEND_RETRY_INTERVAL);
+                    }
+                },
+            }
+        }
+        unreachable!()
+    }
+
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-sinkid
+    pub fn sink_id(&self) -> AudioSinkId {
+        self.sink_id.borrow().clone().unwrap_or(AudioSinkId::None)
+    }
+
+    /// Move playback to another audio output device. The audio graph
+    /// and the processing state are unaffected. The device is looked up
+    /// and its sink set up on this thread, so that rendering goes on
+    /// meanwhile.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-setsinkid
+    pub fn set_sink_id(&self, sink_id: AudioSinkId) -> Result<(), AudioSinkError> {
+        match *self.sink_id.borrow() {
+            None => return Err(AudioSinkError::NotSupported),
+            Some(ref current) if *current == sink_id => return Ok(()),
+            Some(_) => {},
+        }
+        let sink = (self.make_sink)(&sink_id)?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        let _ = self.send(AudioRenderThreadMsg::SetSink(sink, tx));
+        rx.recv().map_err(|_| AudioSinkError::StateChangeFailed)??;
+        *self.sink_id.borrow_mut() = Some(sink_id);
+        Ok(())
+    }
+
+    fn latency(&self) -> AudioSinkLatency {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let _ = self.send(AudioRenderThreadMsg::GetLatency(tx));
+        rx.recv().unwrap_or_default()
+    }
+
+    /// Seconds taken to hand rendered audio over to the audio subsystem.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-baselatency
+    pub fn base_latency(&self) -> f64 {
+        self.latency().base
+    }
+
+    /// Seconds between the audio subsystem receiving audio and the output
+    /// device playing it.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-outputlatency
+    pub fn output_latency(&self) -> f64 {
+        self.latency().output
+    }
+
+    /// None if the rendering thread is gone, as there is no output to
+    /// time anymore.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-getoutputtimestamp
+    pub fn get_output_timestamp(&self) -> Option<AudioTimestamp> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(AudioRenderThreadMsg::GetOutputTimestamp(tx))
+            .ok()?;
+        rx.recv().ok()
+    }
+
+    /// Create a node. It is built and given an id right away, on this
+    /// thread, and added to the graph by the rendering thread later on.
+    ///
+    /// Fails if the options are ones the spec rejects, or if the rendering
+    /// thread is gone.
+    pub fn create_node(
+        &self,
+        node_type: AudioNodeInit,
+        ch: ChannelInfo,
+    ) -> Result<NodeId, AudioNodeError> {
+        node_type.check()?;
+        let id = self.last_node_id.get().next();
+        let script_processor_channels = match node_type {
+            AudioNodeInit::ScriptProcessorNode(ref options) => {
+                Some(options.number_of_input_channels.max(1))
+            },
+            _ => None,
+        };
+        self.send(AudioRenderThreadMsg::AddNode((self.make_node)(
+            id, node_type, ch,
+        )))
+        .map_err(|_| AudioNodeError::InvalidState)?;
+        self.last_node_id.set(id);
+        if let Some(channels) = script_processor_channels {
+            self.script_processors.borrow_mut().insert(id, channels);
+        }
+        Ok(id)
     }
 
     // Resume audio processing.
@@ -214,68 +534,228 @@ impl AudioContext {
     // Suspend audio processing.
     make_state_change!(suspend, Suspended, Suspend);
 
+    /// Suspend rendering once it reaches `time`, rounded down to a render
+    /// quantum boundary, so that the graph can be changed before calling
+    /// `resume`. Only offline contexts support this, for times after the
+    /// current time and before the end of the rendering, with at most one
+    /// suspension per render quantum.
+    ///
+    /// The returned receiver gets a message once rendering is suspended,
+    /// which is also reported as an `AudioContextEvent::StateChanged`,
+    /// and by `state`.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontext-suspend
+    pub fn suspend_at(&self, time: f64) -> Result<Receiver<()>, SuspendError> {
+        if !time.is_finite() || time < 0. {
+            return Err(SuspendError::InvalidTime);
+        }
+        if self.state.get() == ProcessingState::Closed {
+            return Err(SuspendError::InvalidState);
+        }
+        let frame = (time * self.sample_rate as f64) as u64;
+        let frame = Tick(frame - frame % self.frames_per_block as u64);
+        let (notify, suspended) = mpsc::sync_channel(1);
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(AudioRenderThreadMsg::SuspendAt(frame, notify, tx))
+            .map_err(|_| SuspendError::InvalidState)?;
+        rx.recv().map_err(|_| SuspendError::InvalidState)??;
+        Ok(suspended)
+    }
+
     // Stop audio processing and close render thread.
     make_state_change!(close, Closed, Close);
 
-    pub fn message_node(&self, id: NodeId, msg: AudioNodeMessage) {
-        let _ = self.sender.send(AudioRenderThreadMsg::MessageNode(id, msg));
+    /// Send the events of this context to the given channel, replacing any
+    /// channel set before.
+    pub fn set_event_sender(&self, sender: Sender<AudioContextEvent>) {
+        let _ = self.send(AudioRenderThreadMsg::SetEventSender(sender));
+    }
+
+    /// Describe the audio graph as it is now, for inspection.
+    pub fn graph_snapshot(&self) -> Option<GraphSnapshot> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let _ = self.send(AudioRenderThreadMsg::GetGraphSnapshot(tx));
+        rx.recv().ok()
+    }
+
+    /// Report the render capacity every `update_interval` seconds, as
+    /// `AudioContextEvent::RenderCapacity` events.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiorendercapacity-start
+    pub fn start_render_capacity(&self, update_interval: f64) {
+        let _ = self.send(AudioRenderThreadMsg::StartRenderCapacity(update_interval));
+    }
+
+    /// https://webaudio.github.io/web-audio-api/#dom-audiorendercapacity-stop
+    pub fn stop_render_capacity(&self) {
+        let _ = self.send(AudioRenderThreadMsg::StopRenderCapacity);
+    }
+
+    /// Start or stop recording the time spent processing each node.
+    /// Stopping discards what was recorded.
+    pub fn set_node_profiling(&self, enabled: bool) {
+        let _ = self.send(AudioRenderThreadMsg::SetNodeProfiling(enabled));
+    }
+
+    /// The time spent processing each node since profiling started, from
+    /// the most expensive node on.
+    pub fn node_profiles(&self) -> Vec<NodeProfile> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let _ = self.send(AudioRenderThreadMsg::GetNodeProfiles(tx));
+        rx.recv().unwrap_or_default()
+    }
+
+    /// Start a batch of graph changes applied at a given time.
+    pub fn transaction(&self) -> GraphTransaction<'_> {
+        GraphTransaction::new(self)
+    }
+
+    pub(crate) fn schedule_commands(&self, time: f64, commands: Vec<GraphCommand>) {
+        let _ = self.send(AudioRenderThreadMsg::ScheduleCommands(time, commands));
+    }
+
+    /// Let the rendering thread drop the node once it has no inputs, has
+    /// finished playing and can't be heard anymore. The node must not be
+    /// used after this.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#lifetime-AudioNode
+    pub fn release_node(&self, id: NodeId) {
+        self.script_processors.borrow_mut().remove(&id);
+        let _ = self.send(AudioRenderThreadMsg::ReleaseNode(id));
+    }
+
+    /// Pass a message to a node. Messages the spec rejects fail here,
+    /// without reaching the rendering thread.
+    pub fn message_node(&self, id: NodeId, msg: AudioNodeMessage) -> Result<(), AudioNodeError> {
+        match msg {
+            AudioNodeMessage::ConvolverNode(ConvolverNodeMessage::SetBuffer(buffer)) => {
+                if let Err(error) = self.set_convolver_buffer(id, buffer) {
+                    log::warn!("ConvolverNode buffer not set: {:?}", error);
+                }
+                return Ok(());
+            },
+            AudioNodeMessage::PannerNode(PannerNodeMessage::SetPanningModel(
+                PanningModel::HRTF,
+            )) => {
+                match HrtfRenderer::new(self.sample_rate, self.frames_per_block) {
+                    Some(renderer) => {
+                        let msg = PannerNodeMessage::SetHrtfRenderer(Box::new(renderer));
+                        let msg = AudioNodeMessage::PannerNode(msg);
+                        let _ = self.send(AudioRenderThreadMsg::MessageNode(id, msg));
+                    },
+                    None => {
+                        log::warn!("HRTF panning needs the hrtf feature, panning model not set")
+                    },
+                }
+                return Ok(());
+            },
+            AudioNodeMessage::AnalyserNode(ref message) => message.check()?,
+            // https://webaudio.github.io/web-audio-api/#ScriptProcessorNode
+            AudioNodeMessage::SetChannelCount(count)
+                if self
+                    .script_processors
+                    .borrow()
+                    .get(&id)
+                    .is_some_and(|&channels| channels != count) =>
+            {
+                return Err(AudioNodeError::NotSupported);
+            },
+            AudioNodeMessage::SetChannelMode(mode)
+                if mode != ChannelCountMode::Explicit
+                    && self.script_processors.borrow().contains_key(&id) =>
+            {
+                return Err(AudioNodeError::NotSupported);
+            },
+            // https://webaudio.github.io/web-audio-api/#dom-oscillatornode-type
+            AudioNodeMessage::OscillatorNode(OscillatorNodeMessage::SetOscillatorType(
+                OscillatorType::Custom,
+            )) => return Err(AudioNodeError::InvalidState),
+            _ => {},
+        }
+        let _ = self.send(AudioRenderThreadMsg::MessageNode(id, msg));
+        Ok(())
+    }
+
+    /// The response of a BiquadFilterNode at each frequency, in Hz, for
+    /// its current (possibly automated) param values. The node only
+    /// reports its coefficients, the response is computed on this thread.
+    /// None if the node isn't a BiquadFilterNode, or is gone.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-biquadfilternode-getfrequencyresponse
+    pub fn biquad_frequency_response(
+        &self,
+        id: NodeId,
+        frequency_hz: &[f32],
+    ) -> Option<FrequencyResponse> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let msg = AudioNodeMessage::BiquadFilterNode(BiquadFilterNodeMessage::GetCoefficients(tx));
+        self.message_node(id, msg).ok()?;
+        let coefficients = rx.recv().ok()?;
+        Some(coefficients.frequency_response(frequency_hz, self.sample_rate))
+    }
+
+    /// Set the impulse response of a ConvolverNode. It is transformed for
+    /// convolution here, rather than on the rendering thread.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-convolvernode-buffer
+    pub fn set_convolver_buffer(
+        &self,
+        id: NodeId,
+        buffer: Option<AudioBuffer>,
+    ) -> Result<(), ConvolverError> {
+        let impulse = match buffer {
+            Some(buffer) => Some(Box::new(ConvolverImpulse::new(
+                &buffer,
+                self.frames_per_block,
+            )?)),
+            None => None,
+        };
+        let msg = AudioNodeMessage::ConvolverNode(ConvolverNodeMessage::SetImpulse(impulse));
+        let _ = self.send(AudioRenderThreadMsg::MessageNode(id, msg));
+        Ok(())
     }
 
     pub fn connect_ports(&self, from: PortId<OutputPort>, to: PortId<InputPort>) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::ConnectPorts(from, to));
+        let _ = self.send(AudioRenderThreadMsg::ConnectPorts(from, to));
     }
 
     pub fn disconnect_all_from(&self, node: NodeId) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectAllFrom(node));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectAllFrom(node));
     }
 
     // /// Disconnect all outgoing connections from a node's output
     // ///
     // /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-output
     pub fn disconnect_output(&self, out: PortId<OutputPort>) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectOutput(out));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectOutput(out));
     }
 
     /// Disconnect connections from a node to another node
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode
     pub fn disconnect_between(&self, from: NodeId, to: NodeId) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectBetween(from, to));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectBetween(from, to));
     }
 
     /// Disconnect connections from a node to another node's input
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationparam
     pub fn disconnect_to(&self, from: NodeId, to: PortId<InputPort>) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectTo(from, to));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectTo(from, to));
     }
 
     /// Disconnect all outgoing connections from a node's output to another node
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode-output
     pub fn disconnect_output_between(&self, out: PortId<OutputPort>, to: NodeId) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectOutputBetween(out, to));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectOutputBetween(out, to));
     }
 
     // /// Disconnect all outgoing connections from a node's output to another node's input
     // ///
     // /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode-output-input
     pub fn disconnect_output_between_to(&self, out: PortId<OutputPort>, inp: PortId<InputPort>) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::DisconnectOutputBetweenTo(out, inp));
+        let _ = self.send(AudioRenderThreadMsg::DisconnectOutputBetweenTo(out, inp));
     }
 
     /// Asynchronously decodes the audio file data contained in the given
@@ -298,20 +778,64 @@ impl AudioContext {
         &self,
         callback: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>,
     ) {
-        let _ = self
-            .sender
-            .send(AudioRenderThreadMsg::SetSinkEosCallback(callback));
+        let _ = self.send(AudioRenderThreadMsg::SetSinkEosCallback(callback));
+    }
+
+    /// Hand the audio rendered by an offline context over to `callback`
+    /// as it is produced, in chunks of about `chunk_frames` sample-frames,
+    /// instead of buffering all of it for the EOS callback. Each chunk
+    /// reports the rendering progress. If the callback fails, rendering
+    /// stops and an `AudioContextEvent::SinkError` is sent.
+    ///
+    /// This has to be called before rendering starts.
+    pub fn set_stream_callback(
+        &self,
+        chunk_frames: usize,
+        callback: OfflineStreamCallback,
+    ) -> Result<(), AudioSinkError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(AudioRenderThreadMsg::SetSinkStreamCallback(
+            chunk_frames,
+            callback,
+            tx,
+        ))
+        .map_err(|_| AudioSinkError::StateChangeFailed)?;
+        rx.recv().map_err(|_| AudioSinkError::StateChangeFailed)?
+    }
+
+    /// Write the audio rendered by an offline context to the file at
+    /// `path`, in the given format, as it is produced. The EOS callback
+    /// then gets no audio.
+    ///
+    /// This has to be called before rendering starts, and works with any
+    /// backend since nothing is played.
+    pub fn render_to_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: AudioFileFormat,
+    ) -> Result<(), AudioSinkError> {
+        if self.sink_id.borrow().is_some() {
+            return Err(AudioSinkError::NotSupported);
+        }
+        let file =
+            File::create(path).map_err(|error| AudioSinkError::Backend(error.to_string()))?;
+        let writer = AudioFileWriter::new(
+            BufWriter::new(file),
+            format,
+            self.sample_rate.round() as u32,
+        );
+        self.set_stream_callback(EXPORT_CHUNK_FRAMES, writer.into_stream_callback())
     }
 
     fn set_mute(&self, val: bool) {
-        let _ = self.sender.send(AudioRenderThreadMsg::SetMute(val));
+        let _ = self.send(AudioRenderThreadMsg::SetMute(val));
     }
 }
 
 impl Drop for AudioContext {
     fn drop(&mut self) {
-        let (tx, _) = mpsc::channel();
-        let _ = self.sender.send(AudioRenderThreadMsg::Close(tx));
+        let (tx, _) = mpsc::sync_channel(1);
+        let _ = self.send(AudioRenderThreadMsg::Close(tx));
 
         // Ask the backend to unregister this instance and wait for ACK
         let (tx_ack, rx_ack) = mpsc::channel();
@@ -339,16 +863,12 @@ impl MediaInstance for AudioContext {
     }
 
     fn suspend(&self) -> Result<(), ()> {
-        let (tx, _) = mpsc::channel();
-        self.sender
-            .send(AudioRenderThreadMsg::Suspend(tx))
-            .map_err(|_| ())
+        let (tx, _) = mpsc::sync_channel(1);
+        self.send(AudioRenderThreadMsg::Suspend(tx)).map_err(|_| ())
     }
 
     fn resume(&self) -> Result<(), ()> {
-        let (tx, _) = mpsc::channel();
-        self.sender
-            .send(AudioRenderThreadMsg::Resume(tx))
-            .map_err(|_| ())
+        let (tx, _) = mpsc::sync_channel(1);
+        self.send(AudioRenderThreadMsg::Resume(tx)).map_err(|_| ())
     }
 }
@@ -0,0 +1,504 @@
+use crate::block::{Block, Chunk, Tick};
+use crate::buffer_source_node::AudioBuffer;
+use crate::fft::Fft;
+use crate::node::Garbage;
+use crate::node::{AudioNodeEngine, AudioNodeError, AudioNodeType, BlockInfo, ChannelInfo};
+use crate::node::{ChannelCountMode, ChannelInterpretation};
+use num_complex::Complex32;
+use std::fmt;
+
+// Constants used to compute the normalization scale of an impulse response
+// https://webaudio.github.io/web-audio-api/#dom-convolvernode-normalize
+const GAIN_CALIBRATION: f32 = 0.00125;
+const GAIN_CALIBRATION_SAMPLE_RATE: f32 = 44100.;
+const MIN_POWER: f32 = 0.000125;
+
+/// This specifies options for constructing a ConvolverNode.
+#[derive(Debug, Clone)]
+pub struct ConvolverNodeOptions {
+    /// The impulse response. Must have 1, 2 or 4 channels.
+    pub buffer: Option<AudioBuffer>,
+    /// Whether the impulse response is scaled by an equal-power
+    /// normalization when it is set.
+    pub normalize: bool,
+}
+
+impl Default for ConvolverNodeOptions {
+    fn default() -> Self {
+        ConvolverNodeOptions {
+            buffer: None,
+            normalize: true,
+        }
+    }
+}
+
+impl ConvolverNodeOptions {
+    /// https://webaudio.github.io/web-audio-api/#dom-convolvernode-buffer
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        match self.buffer {
+            Some(ref buffer) if !matches!(buffer.chans(), 1 | 2 | 4) => {
+                Err(AudioNodeError::NotSupported)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Control messages directed to ConvolverNodes.
+#[derive(Debug, Clone)]
+pub enum ConvolverNodeMessage {
+    /// Set the impulse response. Must have 1, 2 or 4 channels.
+    ///
+    /// `AudioContext::message_node` turns this into `SetImpulse`, and
+    /// drops it if the channel count isn't supported. Use
+    /// `AudioContext::set_convolver_buffer` to find out about that.
+    SetBuffer(Option<AudioBuffer>),
+    /// Set an impulse response prepared on the control thread.
+    SetImpulse(Option<Box<ConvolverImpulse>>),
+    /// Set the normalize attribute. Only takes effect the next
+    /// time a buffer is set.
+    SetNormalize(bool),
+}
+
+/// Errors setting the impulse response of a ConvolverNode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConvolverError {
+    /// The impulse response doesn't have 1, 2 or 4 channels.
+    UnsupportedChannelCount(u8),
+}
+
+/// The impulse response of a single channel, split into render
+/// quantum sized partitions, each stored in the frequency domain
+#[derive(Clone)]
+struct Kernel {
+    partitions: Vec<Vec<Complex32>>,
+}
+
+/// The convolution state of a single input channel
+///
+/// This is a frequency-domain delay line: the spectra of the last
+/// `partitions` input blocks, shared by all the kernels that this
+/// input channel is convolved with.
+#[derive(Clone)]
+struct InputLine {
+    /// The last two input blocks, in the time domain
+    time: Vec<f32>,
+    /// Ring buffer of input spectra
+    spectra: Vec<Vec<Complex32>>,
+    /// Index of the most recent entry in `spectra`
+    head: usize,
+}
+
+impl InputLine {
+    fn new(partitions: usize, fft: &Fft) -> Self {
+        InputLine {
+            time: vec![0.; fft.size()],
+            spectra: vec![vec![Complex32::new(0., 0.); fft.real_bins()]; partitions],
+            head: 0,
+        }
+    }
+
+    /// Push a new block of input (None for silence) and compute its spectrum
+    fn push(&mut self, fft: &mut Fft, input: Option<&[f32]>) {
+        let half = self.time.len() / 2;
+        self.time.copy_within(half.., 0);
+        match input {
+            Some(input) => self.time[half..].copy_from_slice(input),
+            None => self.time[half..].fill(0.),
+        }
+        self.head = (self.head + 1) % self.spectra.len();
+        fft.forward_real(&self.time, &mut self.spectra[self.head]);
+    }
+
+    /// The spectrum of the input block from `delay` blocks ago
+    fn spectrum(&self, delay: usize) -> &[Complex32] {
+        let len = self.spectra.len();
+        &self.spectra[(self.head + len - delay) % len]
+    }
+}
+
+/// An impulse response ready for convolution, with the state of the
+/// convolution, which depends on the length of the impulse response.
+///
+/// This is built on the control thread, transforming the impulse
+/// response being too slow for the rendering thread.
+#[derive(Clone)]
+pub struct ConvolverImpulse {
+    /// One kernel per channel of the impulse response
+    kernels: Vec<Kernel>,
+    /// One line per (possible) input channel
+    lines: Vec<InputLine>,
+    /// The length of a partition, which is the render quantum size
+    partition_len: usize,
+    /// The gain applied if the node normalizes its impulse response
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-convolvernode-normalize
+    scale: f32,
+}
+
+impl ConvolverImpulse {
+    pub(crate) fn new(buffer: &AudioBuffer, partition_len: usize) -> Result<Self, ConvolverError> {
+        if !matches!(buffer.chans(), 1 | 2 | 4) {
+            return Err(ConvolverError::UnsupportedChannelCount(buffer.chans()));
+        }
+
+        let mut fft = Fft::new(2 * partition_len);
+        let partitions = buffer.len().div_ceil(partition_len).max(1);
+        let bins = fft.real_bins();
+        let mut padded = vec![0.; fft.size()];
+        let kernels = buffer
+            .buffers
+            .iter()
+            .map(|chan| {
+                let partitions = (0..partitions)
+                    .map(|partition| {
+                        let start = partition * partition_len;
+                        let end = chan.len().min(start + partition_len);
+                        padded.fill(0.);
+                        padded[..end - start].copy_from_slice(&chan[start..end]);
+                        let mut spectrum = vec![Complex32::new(0., 0.); bins];
+                        fft.forward_real(&padded, &mut spectrum);
+                        spectrum
+                    })
+                    .collect();
+                Kernel { partitions }
+            })
+            .collect();
+        let lines = (0..2).map(|_| InputLine::new(partitions, &fft)).collect();
+        Ok(ConvolverImpulse {
+            kernels,
+            lines,
+            partition_len,
+            scale: normalization_scale(buffer),
+        })
+    }
+
+    fn partitions(&self) -> usize {
+        self.kernels[0].partitions.len()
+    }
+}
+
+impl fmt::Debug for ConvolverImpulse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConvolverImpulse")
+            .field("channels", &self.kernels.len())
+            .field("partitions", &self.partitions())
+            .field("partition_len", &self.partition_len)
+            .finish()
+    }
+}
+
+/// ConvolverNode engine.
+///
+/// Implements uniformly partitioned overlap-save convolution, so that
+/// the cost of a render quantum is one FFT per input channel, one inverse
+/// FFT per output channel and a complex multiply-add per partition.
+///
+/// https://webaudio.github.io/web-audio-api/#ConvolverNode
+#[derive(AudioNodeCommon)]
+pub(crate) struct ConvolverNode {
+    channel_info: ChannelInfo,
+    normalize: bool,
+    /// None if no impulse response has been set
+    impulse: Option<Box<ConvolverImpulse>>,
+    /// The impulse response replaced by the last SetImpulse message
+    replaced: Option<Box<ConvolverImpulse>>,
+    /// The gain of the output, depending on whether the impulse
+    /// response was normalized when it was set
+    gain: f32,
+    /// The length of a partition, which is the render quantum size.
+    /// Overlap-save needs an FFT twice that size.
+    partition_len: usize,
+    fft: Fft,
+    /// Scratch space for accumulating an output spectrum
+    accumulator: Vec<Complex32>,
+    /// Scratch space for the inverse transform of `accumulator`
+    output: Vec<f32>,
+    /// How many more blocks of non-silent output are left after
+    /// the input has gone silent
+    tail_blocks_left: usize,
+}
+
+impl ConvolverNode {
+    /// The buffer of the options has to be supported, see
+    /// `AudioContext::create_node`
+    pub fn new(
+        options: ConvolverNodeOptions,
+        channel_info: ChannelInfo,
+        frames_per_block: usize,
+    ) -> Self {
+        let fft = Fft::new(2 * frames_per_block);
+        let bins = fft.real_bins();
+        let impulse = options.buffer.map(|buffer| {
+            Box::new(
+                ConvolverImpulse::new(&buffer, frames_per_block)
+                    .expect("unsupported buffers are rejected when creating the node"),
+            )
+        });
+        let mut node = Self {
+            channel_info,
+            normalize: options.normalize,
+            impulse: None,
+            replaced: None,
+            gain: 1.,
+            partition_len: frames_per_block,
+            fft,
+            accumulator: vec![Complex32::new(0., 0.); bins],
+            output: vec![0.; 2 * frames_per_block],
+            tail_blocks_left: 0,
+        };
+        node.set_impulse(impulse);
+        node
+    }
+
+    fn handle_convolver_message(&mut self, message: ConvolverNodeMessage, _sample_rate: f32) {
+        match message {
+            ConvolverNodeMessage::SetBuffer(_) => {
+                unreachable!("SetBuffer is turned into SetImpulse on the control thread")
+            },
+            ConvolverNodeMessage::SetImpulse(impulse) => self.set_impulse(impulse),
+            ConvolverNodeMessage::SetNormalize(normalize) => self.normalize = normalize,
+        }
+    }
+
+    fn partitions(&self) -> usize {
+        self.impulse
+            .as_ref()
+            .map_or(0, |impulse| impulse.partitions())
+    }
+
+    fn set_impulse(&mut self, impulse: Option<Box<ConvolverImpulse>>) {
+        debug_assert!(
+            impulse
+                .as_ref()
+                .is_none_or(|impulse| impulse.partition_len == self.partition_len)
+        );
+        self.gain = match impulse {
+            Some(ref impulse) if self.normalize => impulse.scale,
+            _ => 1.,
+        };
+        self.replaced = std::mem::replace(&mut self.impulse, impulse);
+        self.tail_blocks_left = 0;
+    }
+
+    /// Convolve the given (input line, kernel) pairs, summing the results
+    /// into `out`
+    fn convolve(&mut self, terms: &[(usize, usize)], out: &mut [f32]) {
+        let impulse = self
+            .impulse
+            .as_ref()
+            .expect("No impulse response to convolve with");
+        self.accumulator.fill(Complex32::new(0., 0.));
+        for &(line, kernel) in terms {
+            let line = &impulse.lines[line];
+            let kernel = &impulse.kernels[kernel];
+            for (delay, h) in kernel.partitions.iter().enumerate() {
+                for ((acc, x), h) in self.accumulator.iter_mut().zip(line.spectrum(delay)).zip(h) {
+                    *acc += x * h;
+                }
+            }
+        }
+        self.fft.inverse_real(&self.accumulator, &mut self.output);
+        // overlap-save: the first half is circular-convolution garbage
+        for (o, s) in out.iter_mut().zip(&self.output[self.partition_len..]) {
+            *o = s * self.gain;
+        }
+    }
+}
+
+impl AudioNodeEngine for ConvolverNode {
+    fn node_type(&self) -> AudioNodeType {
+        AudioNodeType::ConvolverNode
+    }
+
+    fn tail_time(&self) -> Tick {
+        Tick((self.partitions() * self.partition_len) as u64)
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
+        debug_assert!(inputs.len() == 1);
+
+        let Some(ref mut impulse) = self.impulse else {
+            // with no impulse response the output is silence
+            let chans = inputs.blocks[0].chan_count();
+            inputs.blocks[0] = Block::silence(info.frames_per_block);
+            inputs.blocks[0].mix(chans, ChannelInterpretation::Discrete);
+            return inputs;
+        };
+
+        let input = &inputs.blocks[0];
+        let in_chans = input.chan_count().min(2) as usize;
+        if input.is_silence() {
+            if self.tail_blocks_left == 0 {
+                return inputs;
+            }
+            self.tail_blocks_left -= 1;
+            for line in &mut impulse.lines {
+                line.push(&mut self.fft, None);
+            }
+        } else {
+            self.tail_blocks_left = impulse.partitions();
+            for (chan, line) in impulse.lines.iter_mut().enumerate() {
+                let data = if chan < in_chans {
+                    Some(input.data_chan(chan as u8))
+                } else {
+                    None
+                };
+                line.push(&mut self.fft, data);
+            }
+        }
+
+        // https://webaudio.github.io/web-audio-api/#Convolution-channel-configurations
+        let routing: &[&[(usize, usize)]] = match (in_chans, impulse.kernels.len()) {
+            (1, 1) => &[&[(0, 0)]],
+            (_, 1) => &[&[(0, 0)], &[(1, 0)]],
+            (1, 2) => &[&[(0, 0)], &[(0, 1)]],
+            (1, _) => &[&[(0, 0), (0, 2)], &[(0, 1), (0, 3)]],
+            (_, 2) => &[&[(0, 0)], &[(1, 1)]],
+            // true stereo
+            _ => &[&[(0, 0), (1, 2)], &[(0, 1), (1, 3)]],
+        };
+
+        let mut block = Block::for_channels_explicit(routing.len() as u8, info.frames_per_block);
+        for (chan, terms) in routing.iter().enumerate() {
+            self.convolve(terms, block.data_chan_mut(chan as u8));
+        }
+        inputs.blocks[0] = block;
+        inputs
+    }
+
+    fn set_channel_count(&mut self, c: u8) {
+        if c > 2 {
+            panic!("NotSupportedError: convolver nodes cannot have more than two channels");
+        }
+        self.channel_info.count = c;
+    }
+
+    fn set_channel_count_mode(&mut self, m: ChannelCountMode) {
+        if m == ChannelCountMode::Max {
+            panic!("NotSupportedError: convolver nodes cannot have their mode set to max");
+        }
+        self.channel_info.mode = m;
+    }
+
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        self.replaced
+            .take()
+            .map(|impulse| Garbage::Boxed(impulse as Box<dyn Send>))
+    }
+
+    make_message_handler!(ConvolverNode: handle_convolver_message);
+}
+
+/// https://webaudio.github.io/web-audio-api/#dom-convolvernode-normalize
+fn normalization_scale(buffer: &AudioBuffer) -> f32 {
+    let mut power: f32 = buffer
+        .buffers
+        .iter()
+        .flat_map(|chan| chan.iter())
+        .map(|s| s * s)
+        .sum();
+    power = (power / (buffer.chans() as usize * buffer.len()) as f32).sqrt();
+
+    if !power.is_finite() || power < MIN_POWER {
+        power = MIN_POWER;
+    }
+
+    let mut scale = GAIN_CALIBRATION / power;
+    if buffer.sample_rate != 0. {
+        scale *= GAIN_CALIBRATION_SAMPLE_RATE / buffer.sample_rate;
+    }
+    // true stereo compensation
+    if buffer.chans() == 4 {
+        scale *= 0.5;
+    }
+    scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+
+    const FRAMES: usize = 128;
+
+    /// Convolve a unit impulse on every input channel with an impulse
+    /// response whose channel `c` is a unit impulse delayed by `c` frames
+    fn convolve_impulses(input_chans: u8, response_chans: u8) -> Block {
+        let buffers = (0..response_chans as usize)
+            .map(|chan| {
+                let mut data = vec![0.; 8];
+                data[chan] = 1.;
+                data
+            })
+            .collect();
+        let options = ConvolverNodeOptions {
+            buffer: Some(AudioBuffer::from_buffers(buffers, 44100.)),
+            normalize: false,
+        };
+        let mut node = ConvolverNode::new(options, ChannelInfo::default(), FRAMES);
+        let mut input = Block::for_channels_explicit(input_chans, FRAMES);
+        for chan in 0..input_chans {
+            input.data_chan_mut(chan)[0] = 1.;
+        }
+        let info = BlockInfo {
+            sample_rate: 44100.,
+            frame: Tick(0),
+            time: 0.,
+            frames_per_block: FRAMES,
+        };
+        let mut output = node.process(
+            Chunk {
+                blocks: smallvec![input],
+            },
+            &info,
+        );
+        output.blocks[0].take()
+    }
+
+    fn impulses(data: &[f32]) -> Vec<usize> {
+        (0..data.len()).filter(|&i| data[i].abs() > 1e-4).collect()
+    }
+
+    #[test]
+    fn mono_input_uses_all_four_channels() {
+        let output = convolve_impulses(1, 4);
+        assert_eq!(output.chan_count(), 2);
+        assert_eq!(impulses(output.data_chan(0)), [0, 2]);
+        assert_eq!(impulses(output.data_chan(1)), [1, 3]);
+    }
+
+    #[test]
+    fn channel_configurations() {
+        let output = convolve_impulses(1, 1);
+        assert_eq!(output.chan_count(), 1);
+        assert_eq!(impulses(output.data_chan(0)), [0]);
+
+        let output = convolve_impulses(1, 2);
+        assert_eq!(impulses(output.data_chan(0)), [0]);
+        assert_eq!(impulses(output.data_chan(1)), [1]);
+
+        let output = convolve_impulses(2, 1);
+        assert_eq!(impulses(output.data_chan(0)), [0]);
+        assert_eq!(impulses(output.data_chan(1)), [0]);
+
+        let output = convolve_impulses(2, 2);
+        assert_eq!(impulses(output.data_chan(0)), [0]);
+        assert_eq!(impulses(output.data_chan(1)), [1]);
+
+        let output = convolve_impulses(2, 4);
+        assert_eq!(impulses(output.data_chan(0)), [0, 2]);
+        assert_eq!(impulses(output.data_chan(1)), [1, 3]);
+    }
+
+    #[test]
+    fn unsupported_channel_counts_are_errors() {
+        for chans in [3, 5] {
+            let buffer = AudioBuffer::new(chans, 8, 44100.);
+            assert_eq!(
+                ConvolverImpulse::new(&buffer, FRAMES).err(),
+                Some(ConvolverError::UnsupportedChannelCount(chans))
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::block::{Block, Chunk, Tick};
+use crate::node::{AudioNodeEngine, AudioNodeError, AudioNodeType, BlockInfo, ChannelInfo};
+use crate::param::{Param, ParamType};
+
+/// This specifies options for constructing a DelayNode.
+#[derive(Copy, Clone, Debug)]
+pub struct DelayNodeOptions {
+    /// The maximum delay time, in seconds. Must be in (0, 180).
+    pub max_delay_time: f64,
+    /// The initial delay time, in seconds.
+    pub delay_time: f64,
+}
+
+impl Default for DelayNodeOptions {
+    fn default() -> Self {
+        DelayNodeOptions {
+            max_delay_time: 1.,
+            delay_time: 0.,
+        }
+    }
+}
+
+impl DelayNodeOptions {
+    /// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-createdelay
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        if self.max_delay_time > 0. && self.max_delay_time < 180. {
+            Ok(())
+        } else {
+            Err(AudioNodeError::NotSupported)
+        }
+    }
+}
+
+/// DelayNode engine.
+///
+/// Keeps a ring buffer per channel holding `max_delay_time` worth of input,
+/// plus a render quantum so that a block can be written before it is read.
+///
+/// When the node is part of a cycle the graph asks for its output before
+/// giving it its input, in which case the delay is clamped to at least one
+/// render quantum.
+///
+/// https://webaudio.github.io/web-audio-api/#DelayNode
+#[derive(AudioNodeCommon)]
+pub(crate) struct DelayNode {
+    channel_info: ChannelInfo,
+    delay_time: Param,
+    max_delay_time: f64,
+    sample_rate: f32,
+    frames_per_block: usize,
+    /// One ring buffer per channel, grown as inputs with more channels arrive
+    buffers: Vec<Vec<f32>>,
+    /// The length of every ring buffer
+    len: usize,
+    /// The position the next input block will be written to
+    write_index: usize,
+    /// How many frames of silence have been written since the last
+    /// non-silent input. Once this covers the whole ring buffer, the
+    /// output is silent.
+    silent_frames: usize,
+}
+
+impl DelayNode {
+    pub fn new(
+        options: DelayNodeOptions,
+        channel_info: ChannelInfo,
+        sample_rate: f32,
+        frames_per_block: usize,
+    ) -> Self {
+        debug_assert!(options.check().is_ok());
+        let max_frames = (options.max_delay_time * sample_rate as f64).ceil() as usize;
+        // one extra frame of history for the interpolation
+        let len = max_frames.max(frames_per_block) + frames_per_block + 1;
+        Self {
+            channel_info,
+            delay_time: Param::new(options.delay_time as f32),
+            max_delay_time: options.max_delay_time,
+            sample_rate,
+            frames_per_block,
+            buffers: Vec::new(),
+            len,
+            write_index: 0,
+            silent_frames: len,
+        }
+    }
+
+    fn is_drained(&self) -> bool {
+        self.silent_frames >= self.len
+    }
+
+    /// Write a block of input at `write_index`, without advancing it
+    fn write(&mut self, mut input: Block) {
+        if input.is_silence() {
+            if self.is_drained() {
+                return;
+            }
+            for buffer in &mut self.buffers {
+                for i in 0..self.frames_per_block {
+                    buffer[(self.write_index + i) % self.len] = 0.;
+                }
+            }
+            return;
+        }
+
+        let chans = input.chan_count() as usize;
+        if chans > self.buffers.len() {
+            self.buffers.resize(chans, vec![0.; self.len]);
+        }
+        input.mix(self.buffers.len() as u8, self.channel_info.interpretation);
+        for (chan, buffer) in self.buffers.iter_mut().enumerate() {
+            let data = input.data_chan(chan as u8);
+            for (i, sample) in data.iter().enumerate() {
+                buffer[(self.write_index + i) % self.len] = *sample;
+            }
+        }
+        self.silent_frames = 0;
+    }
+
+    /// Read a block of output, for the block starting at `write_index`
+    ///
+    /// `min_delay` is in frames
+    fn read(&mut self, info: &BlockInfo, min_delay: f64) -> Block {
+        if self.is_drained() || self.buffers.is_empty() {
+            return Block::silence(self.frames_per_block);
+        }
+
+        let max_delay = self.max_delay_time * self.sample_rate as f64;
+        let mut block =
+            Block::for_channels_explicit(self.buffers.len() as u8, self.frames_per_block);
+        for i in 0..self.frames_per_block {
+            self.delay_time.update(info, Tick(i as u64));
+            let delay = (self.delay_time.value() as f64 * self.sample_rate as f64)
+                .max(min_delay)
+                .min(max_delay.max(min_delay));
+            let position = (self.write_index + i + self.len) as f64 - delay;
+            let base = position.floor();
+            let frac = (position - base) as f32;
+            let index = base as usize % self.len;
+            let next = (index + 1) % self.len;
+            for (chan, buffer) in self.buffers.iter().enumerate() {
+                block.data_chan_mut(chan as u8)[i] =
+                    buffer[index] * (1. - frac) + buffer[next] * frac;
+            }
+        }
+        block
+    }
+
+    fn advance(&mut self) {
+        self.write_index = (self.write_index + self.frames_per_block) % self.len;
+        if self.silent_frames < self.len {
+            self.silent_frames += self.frames_per_block;
+        }
+    }
+}
+
+impl AudioNodeEngine for DelayNode {
+    fn node_type(&self) -> AudioNodeType {
+        AudioNodeType::DelayNode
+    }
+
+    fn tail_time(&self) -> Tick {
+        Tick(self.len as u64)
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
+        debug_assert!(inputs.len() == 1);
+
+        let input = inputs.blocks[0].take();
+        self.write(input);
+        inputs.blocks[0] = self.read(info, 0.);
+        self.advance();
+        inputs
+    }
+
+    fn breaks_cycles(&self) -> bool {
+        true
+    }
+
+    fn read_output(&mut self, info: &BlockInfo) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk
+            .blocks
+            .push(self.read(info, self.frames_per_block as f64));
+        chunk
+    }
+
+    fn write_input(&mut self, mut inputs: Chunk, _info: &BlockInfo) {
+        debug_assert!(inputs.len() == 1);
+
+        let input = inputs.blocks[0].take();
+        self.write(input);
+        self.advance();
+    }
+
+    fn get_param(&mut self, id: ParamType) -> &mut Param {
+        match id {
+            ParamType::DelayTime => &mut self.delay_time,
+            _ => panic!("Unknown param {:?} for DelayNode", id),
+        }
+    }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::DelayTime]
+    }
+}
@@ -0,0 +1,423 @@
+use crate::block::{Block, Chunk, Tick};
+use crate::node::ChannelCountMode;
+use crate::node::{AudioNodeEngine, AudioNodeType, BlockInfo, ChannelInfo};
+use crate::param::{Param, ParamType};
+use std::sync::mpsc::SyncSender;
+
+/// How far ahead of the gain computation the detector looks, in seconds
+const LOOK_AHEAD: f32 = 0.006;
+
+/// The level reported for silence, in dB
+const SILENCE_DB: f32 = -200.;
+
+/// This specifies options for constructing a DynamicsCompressorNode.
+#[derive(Copy, Clone, Debug)]
+pub struct DynamicsCompressorNodeOptions {
+    /// The level above which compression starts, in dB
+    pub threshold: f32,
+    /// The width of the soft knee above the threshold, in dB
+    pub knee: f32,
+    /// The ratio of input to output level change above the knee
+    pub ratio: f32,
+    /// The attack time of the gain reduction, in seconds
+    pub attack: f32,
+    /// The release time of the gain reduction, in seconds
+    pub release: f32,
+}
+
+impl Default for DynamicsCompressorNodeOptions {
+    fn default() -> Self {
+        DynamicsCompressorNodeOptions {
+            threshold: -24.,
+            knee: 30.,
+            ratio: 12.,
+            attack: 0.003,
+            release: 0.25,
+        }
+    }
+}
+
+/// Control messages directed to DynamicsCompressorNodes.
+#[derive(Debug, Clone)]
+pub enum DynamicsCompressorNodeMessage {
+    /// Get the current gain reduction, in dB (always <= 0). The channel
+    /// needs room for the reply.
+    GetReduction(SyncSender<f32>),
+}
+
+/// DynamicsCompressorNode engine.
+///
+/// The level of the incoming signal is run through a soft-knee
+/// compression curve, and the resulting gain reduction is smoothed with
+/// the attack and release times before being applied to the signal,
+/// delayed by `LOOK_AHEAD` so that the gain can drop before a transient
+/// reaches the output.
+///
+/// https://webaudio.github.io/web-audio-api/#DynamicsCompressorNode
+#[derive(AudioNodeCommon)]
+pub(crate) struct DynamicsCompressorNode {
+    channel_info: ChannelInfo,
+    threshold: Param,
+    knee: Param,
+    ratio: Param,
+    attack: Param,
+    release: Param,
+    /// The current (smoothed) gain reduction, in dB
+    reduction: f32,
+    /// Look-ahead ring buffers, one per (possible) input channel
+    look_ahead: [Vec<f32>; 2],
+    /// The position in `look_ahead` of the next frame
+    look_ahead_index: usize,
+    /// The channel count of the last non-silent input, which the
+    /// look-ahead keeps playing while it drains
+    look_ahead_chans: u8,
+    /// How many frames of silence have been pushed through the look-ahead
+    /// since the last non-silent input
+    silent_frames: usize,
+}
+
+impl DynamicsCompressorNode {
+    pub fn new(
+        options: DynamicsCompressorNodeOptions,
+        channel_info: ChannelInfo,
+        sample_rate: f32,
+    ) -> Self {
+        let len = ((LOOK_AHEAD * sample_rate).round() as usize).max(1);
+        Self {
+            channel_info,
+            threshold: Param::new_krate(options.threshold),
+            knee: Param::new_krate(options.knee),
+            ratio: Param::new_krate(options.ratio),
+            attack: Param::new_krate(options.attack),
+            release: Param::new_krate(options.release),
+            reduction: 0.,
+            look_ahead: [vec![0.; len], vec![0.; len]],
+            look_ahead_index: 0,
+            look_ahead_chans: 1,
+            silent_frames: len,
+        }
+    }
+
+    fn handle_compressor_message(
+        &mut self,
+        message: DynamicsCompressorNodeMessage,
+        _sample_rate: f32,
+    ) {
+        match message {
+            DynamicsCompressorNodeMessage::GetReduction(tx) => {
+                let _ = tx.try_send(self.reduction);
+            },
+        }
+    }
+
+    fn update_parameters(&mut self, info: &BlockInfo, tick: Tick) {
+        self.threshold.update(info, tick);
+        self.knee.update(info, tick);
+        self.ratio.update(info, tick);
+        self.attack.update(info, tick);
+        self.release.update(info, tick);
+    }
+}
+
+/// The (static) compression curve
+///
+/// https://webaudio.github.io/web-audio-api/#compression-curve
+struct Curve {
+    threshold: f32,
+    knee: f32,
+    ratio: f32,
+}
+
+impl Curve {
+    /// Map an input level to an output level, both in dB
+    fn apply(&self, level: f32) -> f32 {
+        let Curve {
+            threshold,
+            knee,
+            ratio,
+        } = *self;
+        if level <= threshold {
+            level
+        } else if level < threshold + knee {
+            let over = level - threshold;
+            level + (1. / ratio - 1.) * over * over / (2. * knee)
+        } else {
+            threshold + knee / 2. + knee / (2. * ratio) + (level - threshold - knee) / ratio
+        }
+    }
+
+    /// The gain applied to the output so that a full-scale input comes
+    /// out at roughly full scale again, as a linear factor
+    ///
+    /// https://webaudio.github.io/web-audio-api/#makeup-gain
+    fn makeup_gain(&self) -> f32 {
+        let full_range_gain = db_to_linear(self.apply(0.));
+        (1. / full_range_gain).powf(0.6)
+    }
+}
+
+impl AudioNodeEngine for DynamicsCompressorNode {
+    fn node_type(&self) -> AudioNodeType {
+        AudioNodeType::DynamicsCompressionNode
+    }
+
+    fn tail_time(&self) -> Tick {
+        // The look-ahead still holds input that hasn't been output
+        Tick(self.look_ahead[0].len() as u64)
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
+        debug_assert!(inputs.len() == 1);
+
+        self.update_parameters(info, Tick(0));
+        let curve = Curve {
+            threshold: self.threshold.value().clamp(-100., 0.),
+            knee: self.knee.value().clamp(0., 40.),
+            ratio: self.ratio.value().clamp(1., 20.),
+        };
+        let attack = self.attack.value().clamp(0., 1.);
+        let release = self.release.value().clamp(0., 1.);
+        let attack_coef = smoothing_coefficient(attack, info.sample_rate);
+        let release_coef = smoothing_coefficient(release, info.sample_rate);
+
+        let len = self.look_ahead[0].len();
+        let input = inputs.blocks[0].take();
+        if input.is_silence() && self.silent_frames >= len {
+            // Nothing left in the look-ahead, only the gain reduction
+            // needs to keep releasing
+            self.reduction *= release_coef.powi(info.frames_per_block as i32);
+            inputs.blocks[0] = input;
+            return inputs;
+        }
+        if input.is_silence() {
+            self.silent_frames += info.frames_per_block;
+        } else {
+            self.silent_frames = 0;
+            let chans = input.chan_count().min(2);
+            // A channel that wasn't in use holds stale samples
+            for buffer in self.look_ahead[..chans as usize]
+                .iter_mut()
+                .skip(self.look_ahead_chans as usize)
+            {
+                buffer.fill(0.);
+            }
+            self.look_ahead_chans = chans;
+        }
+
+        let chans = self.look_ahead_chans;
+        let makeup = curve.makeup_gain();
+        let mut output = Block::for_channels_explicit(chans, info.frames_per_block);
+        for i in 0..info.frames_per_block {
+            // detect on the incoming signal ...
+            let mut peak: f32 = 0.;
+            for chan in 0..chans {
+                peak = peak.max(input.data_chan_frame(i, chan).abs());
+            }
+            let level = linear_to_db(peak);
+            let target = curve.apply(level) - level;
+            let coef = if target < self.reduction {
+                attack_coef
+            } else {
+                release_coef
+            };
+            self.reduction = target + coef * (self.reduction - target);
+            let gain = db_to_linear(self.reduction) * makeup;
+
+            // ... and apply the gain to the delayed signal
+            let index = self.look_ahead_index;
+            for chan in 0..chans {
+                let delayed = self.look_ahead[chan as usize][index];
+                self.look_ahead[chan as usize][index] = input.data_chan_frame(i, chan);
+                output.data_chan_mut(chan)[i] = delayed * gain;
+            }
+            self.look_ahead_index = (index + 1) % len;
+        }
+        inputs.blocks[0] = output;
+        inputs
+    }
+
+    fn set_channel_count(&mut self, c: u8) {
+        if c > 2 {
+            panic!("NotSupportedError: compressor nodes cannot have more than two channels");
+        }
+        self.channel_info.count = c;
+    }
+
+    fn set_channel_count_mode(&mut self, m: ChannelCountMode) {
+        if m == ChannelCountMode::Max {
+            panic!("NotSupportedError: compressor nodes cannot have their mode set to max");
+        }
+        self.channel_info.mode = m;
+    }
+
+    fn get_param(&mut self, id: ParamType) -> &mut Param {
+        match id {
+            ParamType::Threshold => &mut self.threshold,
+            ParamType::Knee => &mut self.knee,
+            ParamType::Ratio => &mut self.ratio,
+            ParamType::Attack => &mut self.attack,
+            ParamType::Release => &mut self.release,
+            _ => panic!("Unknown param {:?} for DynamicsCompressorNode", id),
+        }
+    }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[
+            ParamType::Threshold,
+            ParamType::Knee,
+            ParamType::Ratio,
+            ParamType::Attack,
+            ParamType::Release,
+        ]
+    }
+
+    make_message_handler!(DynamicsCompressionNode: handle_compressor_message);
+}
+
+/// The per-frame coefficient of a one-pole smoother that takes `time`
+/// seconds to cover most of the distance to its target
+fn smoothing_coefficient(time: f32, sample_rate: f32) -> f32 {
+    if time <= 0. {
+        0.
+    } else {
+        (-1. / (time * sample_rate)).exp()
+    }
+}
+
+fn linear_to_db(value: f32) -> f32 {
+    if value <= 0. {
+        SILENCE_DB
+    } else {
+        (20. * value.log10()).max(SILENCE_DB)
+    }
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::AudioNodeMessage;
+    use smallvec::smallvec;
+
+    const FRAMES: usize = 128;
+
+    fn process(node: &mut DynamicsCompressorNode, input: Block, block: u64) -> Block {
+        let info = BlockInfo {
+            sample_rate: 44100.,
+            frame: Tick(block * FRAMES as u64),
+            time: (block * FRAMES as u64) as f64 / 44100.,
+            frames_per_block: FRAMES,
+        };
+        let mut output = node.process(
+            Chunk {
+                blocks: smallvec![input],
+            },
+            &info,
+        );
+        output.blocks[0].take()
+    }
+
+    #[test]
+    fn stereo_look_ahead_drains_after_the_input() {
+        let mut node =
+            DynamicsCompressorNode::new(Default::default(), ChannelInfo::default(), 44100.);
+        let len = node.look_ahead[0].len();
+        let mut input = Block::for_channels_explicit(2, FRAMES);
+        input.data_chan_mut(0).fill(0.01);
+        input.data_chan_mut(1).fill(-0.01);
+        process(&mut node, input, 0);
+
+        // The input is delayed by `len` frames, all of which come out of
+        // the following silent blocks, in stereo
+        let mut drained = 0;
+        for block in 1..=len.div_ceil(FRAMES) as u64 {
+            let mut output = process(&mut node, Block::silence(FRAMES), block);
+            assert_eq!(output.chan_count(), 2);
+            let left = output.data_chan_mut(0).to_vec();
+            let right = output.data_chan_mut(1).to_vec();
+            for (l, r) in left.iter().zip(&right) {
+                assert_eq!(*l, -*r);
+            }
+            drained += left.iter().filter(|&&l| l > 0.).count();
+        }
+        assert_eq!(drained, FRAMES);
+    }
+
+    /// Feed a constant signal until the look-ahead is full of it, returning
+    /// the last output sample and the reported gain reduction
+    fn compress(options: DynamicsCompressorNodeOptions, level: f32) -> (f32, f32) {
+        let mut node = DynamicsCompressorNode::new(options, ChannelInfo::default(), 44100.);
+        let blocks = node.look_ahead[0].len().div_ceil(FRAMES) as u64 + 1;
+        let mut output = Block::silence(FRAMES);
+        for block in 0..blocks {
+            let mut input = Block::for_channels_explicit(1, FRAMES);
+            input.data_chan_mut(0).fill(level);
+            output = process(&mut node, input, block);
+        }
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        node.message(
+            AudioNodeMessage::DynamicsCompressionNode(DynamicsCompressorNodeMessage::GetReduction(
+                tx,
+            )),
+            44100.,
+        );
+        (output.data_chan(0)[FRAMES - 1], rx.recv().unwrap())
+    }
+
+    /// Instant attack and release, so that the gain follows the curve
+    fn options(threshold: f32, knee: f32, ratio: f32) -> DynamicsCompressorNodeOptions {
+        DynamicsCompressorNodeOptions {
+            threshold,
+            knee,
+            ratio,
+            attack: 0.,
+            release: 0.,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3 * expected.abs().max(1.),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn signals_below_the_threshold_only_get_the_makeup_gain() {
+        // -40dB in, with a makeup gain of 0.6 * 22.5dB
+        let (output, reduction) = compress(options(-30., 0., 4.), 0.01);
+        assert_eq!(reduction, 0.);
+        assert_close(output, 0.01 * db_to_linear(13.5));
+    }
+
+    #[test]
+    fn signals_above_the_knee_are_reduced_by_the_ratio() {
+        // 4dB of output for every 16dB of input over -30dB
+        let level = linear_to_db(0.5);
+        let expected = -30. + (level + 30.) / 4. - level;
+        let (output, reduction) = compress(options(-30., 0., 4.), 0.5);
+        assert_close(reduction, expected);
+        assert_close(output, 0.5 * db_to_linear(expected + 13.5));
+    }
+
+    #[test]
+    fn signals_in_the_knee_are_reduced_gradually() {
+        // The curve goes from a ratio of 1 to 12 over the 30dB knee, by
+        // the slope over the threshold
+        let level = linear_to_db(0.5);
+        let over = level + 24.;
+        let expected = (1. / 12. - 1.) * over * over / 60.;
+        let (output, reduction) = compress(options(-24., 30., 12.), 0.5);
+        assert!(reduction < 0. && reduction > -over);
+        assert_close(reduction, expected);
+        // A full-scale signal is in the knee too, and gets made up for
+        // 0.6 of its reduction
+        let makeup = -0.6 * (1. / 12. - 1.) * 24. * 24. / 60.;
+        assert_close(output, 0.5 * db_to_linear(expected + makeup));
+    }
+}
@@ -0,0 +1,714 @@
+//! Writing the audio rendered by an offline context to WAV or FLAC files.
+//!
+//! The audio is encoded chunk by chunk as it is rendered, so that long
+//! renderings don't need to be held in memory. Channels are written in
+//! the Web Audio order, which is also the WAV and FLAC one for the
+//! standard speaker layouts.
+
+use crate::offline_sink::{OfflineStreamCallback, RenderedChunk};
+use std::io::{self, Write};
+
+/// The number of sample-frames of a FLAC frame
+const FLAC_BLOCK_SIZE: usize = 4096;
+/// The highest order of the FLAC fixed predictors
+const FLAC_MAX_FIXED_ORDER: usize = 4;
+/// The highest Rice parameter, with 5-bit parameters
+const FLAC_MAX_RICE_PARAMETER: u32 = 30;
+
+/// The format and sample encoding of an audio file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioFileFormat {
+    /// WAV, 16-bit integer samples
+    Wav16,
+    /// WAV, 24-bit integer samples
+    Wav24,
+    /// WAV, 32-bit float samples
+    WavFloat32,
+    /// FLAC, 16-bit samples
+    Flac16,
+    /// FLAC, 24-bit samples
+    Flac24,
+}
+
+impl AudioFileFormat {
+    fn bits_per_sample(self) -> u32 {
+        match self {
+            AudioFileFormat::Wav16 | AudioFileFormat::Flac16 => 16,
+            AudioFileFormat::Wav24 | AudioFileFormat::Flac24 => 24,
+            AudioFileFormat::WavFloat32 => 32,
+        }
+    }
+
+    fn is_flac(self) -> bool {
+        matches!(self, AudioFileFormat::Flac16 | AudioFileFormat::Flac24)
+    }
+}
+
+/// Encodes rendered chunks to an audio file.
+///
+/// The header is written with the first chunk, since the channel count
+/// and length of the rendering are known by then. The writer is flushed
+/// after the last chunk.
+pub struct AudioFileWriter<W: Write> {
+    writer: W,
+    format: AudioFileFormat,
+    sample_rate: u32,
+    header_written: bool,
+    /// Interleaved samples of the chunk being written as WAV
+    interleaved: Vec<f32>,
+    /// Encoded bytes of the chunk or FLAC frame being written
+    bytes: Vec<u8>,
+    /// Samples not encoded as a FLAC frame yet, for each channel
+    pending: Vec<Vec<i32>>,
+    /// The number of FLAC frames written
+    flac_frames: u32,
+    /// The WAV data chunk needs a padding byte
+    pad: bool,
+}
+
+impl<W: Write> AudioFileWriter<W> {
+    pub fn new(writer: W, format: AudioFileFormat, sample_rate: u32) -> Self {
+        AudioFileWriter {
+            writer,
+            format,
+            sample_rate,
+            header_written: false,
+            interleaved: Vec::new(),
+            bytes: Vec::new(),
+            pending: Vec::new(),
+            flac_frames: 0,
+            pad: false,
+        }
+    }
+
+    pub fn write_chunk(&mut self, chunk: &RenderedChunk) -> io::Result<()> {
+        if !self.header_written {
+            if self.format.is_flac() {
+                self.write_flac_header(chunk.channel_count(), chunk.length())?;
+                self.pending = vec![Vec::with_capacity(FLAC_BLOCK_SIZE); chunk.channel_count()];
+            } else {
+                self.write_wav_header(chunk.channel_count(), chunk.length())?;
+            }
+            self.header_written = true;
+        }
+
+        if self.format.is_flac() {
+            self.write_flac_chunk(chunk)?;
+        } else {
+            self.write_wav_chunk(chunk)?;
+        }
+
+        if chunk.is_last() {
+            if self.pad {
+                self.writer.write_all(&[0])?;
+            }
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the rendered audio with this writer, as it is produced
+    pub fn into_stream_callback(mut self) -> OfflineStreamCallback
+    where
+        W: Send + 'static,
+    {
+        Box::new(move |chunk| self.write_chunk(chunk))
+    }
+
+    /// http://www-mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/WAVE.html
+    fn write_wav_header(&mut self, channels: usize, length: usize) -> io::Result<()> {
+        let bits = self.format.bits_per_sample();
+        let block_align = channels as u64 * bits as u64 / 8;
+        let data_size = length as u64 * block_align;
+        let float = self.format == AudioFileFormat::WavFloat32;
+        // Recommended for more than two channels or 16 bits, so always
+        // used for float samples
+        let extensible = channels > 2 || bits > 16;
+        let fmt_size: u32 = if extensible { 40 } else { 16 };
+        let fact_size = if float { 12 } else { 0 };
+        self.pad = data_size % 2 == 1;
+        let riff_size = 4 + 8 + fmt_size as u64 + fact_size + 8 + data_size + self.pad as u64;
+        if riff_size > u32::MAX as u64 {
+            return Err(invalid_input("The rendering is too long for a WAV file"));
+        }
+        let format_tag: u16 = if extensible { 0xFFFE } else { 1 };
+
+        let header = &mut self.bytes;
+        header.clear();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(riff_size as u32).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&fmt_size.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&(channels as u16).to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&((self.sample_rate as u64 * block_align) as u32).to_le_bytes());
+        header.extend_from_slice(&(block_align as u16).to_le_bytes());
+        header.extend_from_slice(&(bits as u16).to_le_bytes());
+        if extensible {
+            header.extend_from_slice(&22u16.to_le_bytes());
+            header.extend_from_slice(&(bits as u16).to_le_bytes());
+            header.extend_from_slice(&wav_channel_mask(channels).to_le_bytes());
+            // KSDATAFORMAT_SUBTYPE_PCM or KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+            header.extend_from_slice(&(if float { 3u32 } else { 1 }).to_le_bytes());
+            header.extend_from_slice(&[
+                0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+            ]);
+        }
+        if float {
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            header.extend_from_slice(&(length as u32).to_le_bytes());
+        }
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&(data_size as u32).to_le_bytes());
+        self.writer.write_all(header)
+    }
+
+    fn write_wav_chunk(&mut self, chunk: &RenderedChunk) -> io::Result<()> {
+        self.interleaved
+            .resize(chunk.frames() * chunk.channel_count(), 0.);
+        chunk.interleave(&mut self.interleaved);
+
+        self.bytes.clear();
+        match self.format {
+            AudioFileFormat::Wav16 => {
+                for sample in &self.interleaved {
+                    let sample = quantize(*sample, 16) as i16;
+                    self.bytes.extend_from_slice(&sample.to_le_bytes());
+                }
+            },
+            AudioFileFormat::Wav24 => {
+                for sample in &self.interleaved {
+                    let sample = quantize(*sample, 24);
+                    self.bytes.extend_from_slice(&sample.to_le_bytes()[0..3]);
+                }
+            },
+            _ => {
+                for sample in &self.interleaved {
+                    self.bytes.extend_from_slice(&sample.to_le_bytes());
+                }
+            },
+        }
+        self.writer.write_all(&self.bytes)
+    }
+
+    /// https://www.rfc-editor.org/rfc/rfc9639.html#name-streaminfo
+    fn write_flac_header(&mut self, channels: usize, length: usize) -> io::Result<()> {
+        if channels > 8 {
+            return Err(invalid_input("FLAC supports at most 8 channels"));
+        }
+        if self.sample_rate == 0 || self.sample_rate >= 1 << 20 {
+            return Err(invalid_input("Unsupported sample rate for FLAC"));
+        }
+        if length as u64 >= 1 << 36 {
+            return Err(invalid_input("The rendering is too long for a FLAC file"));
+        }
+        let mut header = BitWriter::new(&mut self.bytes);
+        header.write(u32::from_be_bytes(*b"fLaC") as u64, 32);
+        // Last metadata block, of type STREAMINFO
+        header.write(1, 1);
+        header.write(0, 7);
+        header.write(34, 24);
+        header.write(FLAC_BLOCK_SIZE as u64, 16);
+        header.write(FLAC_BLOCK_SIZE as u64, 16);
+        // Unknown minimum and maximum frame sizes
+        header.write(0, 24);
+        header.write(0, 24);
+        header.write(self.sample_rate as u64, 20);
+        header.write(channels as u64 - 1, 3);
+        header.write(self.format.bits_per_sample() as u64 - 1, 5);
+        // 36 bits, in two writes
+        header.write(length as u64 >> 32, 4);
+        header.write(length as u64, 32);
+        // Unknown MD5 signature
+        for _ in 0..4 {
+            header.write(0, 32);
+        }
+        header.finish();
+        self.writer.write_all(&self.bytes)
+    }
+
+    fn write_flac_chunk(&mut self, chunk: &RenderedChunk) -> io::Result<()> {
+        let bits = self.format.bits_per_sample();
+        let mut offset = 0;
+        while offset < chunk.frames() {
+            let len = (FLAC_BLOCK_SIZE - self.pending[0].len()).min(chunk.frames() - offset);
+            for (channel, pending) in self.pending.iter_mut().enumerate() {
+                let data = &chunk.channel(channel)[offset..offset + len];
+                pending.extend(data.iter().map(|sample| quantize(*sample, bits)));
+            }
+            offset += len;
+            if self.pending[0].len() == FLAC_BLOCK_SIZE {
+                self.write_flac_frame()?;
+            }
+        }
+        if chunk.is_last() && !self.pending[0].is_empty() {
+            self.write_flac_frame()?;
+        }
+        Ok(())
+    }
+
+    /// https://www.rfc-editor.org/rfc/rfc9639.html#name-frame-structure
+    fn write_flac_frame(&mut self) -> io::Result<()> {
+        let bits = self.format.bits_per_sample();
+        let block_size = self.pending[0].len();
+        let mut frame = BitWriter::new(&mut self.bytes);
+        // Sync code, fixed block size
+        frame.write(0b11111111111110, 14);
+        frame.write(0, 1);
+        frame.write(0, 1);
+        // 16-bit block size at the end of the header, sample rate
+        // from STREAMINFO
+        frame.write(0b0111, 4);
+        frame.write(0b0000, 4);
+        // Independent channels
+        frame.write(self.pending.len() as u64 - 1, 4);
+        frame.write(if bits == 16 { 0b100 } else { 0b110 }, 3);
+        frame.write(0, 1);
+        frame.write_utf8(self.flac_frames);
+        frame.write(block_size as u64 - 1, 16);
+        let crc = crc8(frame.bytes());
+        frame.write(crc as u64, 8);
+
+        for samples in &self.pending {
+            write_flac_subframe(&mut frame, samples, bits);
+        }
+        frame.finish();
+        let crc = crc16(frame.bytes());
+        frame.write(crc as u64, 16);
+        frame.finish();
+
+        self.flac_frames += 1;
+        for pending in &mut self.pending {
+            pending.clear();
+        }
+        self.writer.write_all(&self.bytes)
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// The speakers of the Web Audio channel layouts, for WAVE_FORMAT_EXTENSIBLE.
+/// Other channel counts are discrete, and not assigned speakers.
+///
+/// https://webaudio.github.io/web-audio-api/#ChannelLayouts
+fn wav_channel_mask(channels: usize) -> u32 {
+    match channels {
+        // Center
+        1 => 0x4,
+        // Left, right
+        2 => 0x3,
+        // Left, right, back left, back right
+        4 => 0x33,
+        // Left, right, center, LFE, back left, back right
+        6 => 0x3F,
+        _ => 0,
+    }
+}
+
+/// Convert a sample to an integer of `bits` bits, clipping it
+fn quantize(sample: f32, bits: u32) -> i32 {
+    let scale = (1 << (bits - 1)) as f32;
+    (sample * scale).round().clamp(-scale, scale - 1.) as i32
+}
+
+/// Encode a channel with the fixed predictor giving the smallest residual,
+/// or verbatim if that is smaller.
+///
+/// https://www.rfc-editor.org/rfc/rfc9639.html#name-fixed-predictor-subframe
+fn write_flac_subframe(frame: &mut BitWriter, samples: &[i32], bits: u32) {
+    let max_order = FLAC_MAX_FIXED_ORDER.min(samples.len() - 1);
+    let order = (0..=max_order)
+        .min_by_key(|order| {
+            fixed_residual(samples, *order)
+                .map(|residual| residual.unsigned_abs())
+                .sum::<u64>()
+        })
+        .unwrap_or(0);
+    let (parameter, residual_bits) = rice_parameter(samples, order);
+    let fixed_bits = order as u64 * bits as u64 + 6 + 5 + residual_bits;
+    let verbatim_bits = samples.len() as u64 * bits as u64;
+
+    // Zero padding bit, and no wasted bits after the type
+    frame.write(0, 1);
+    if verbatim_bits <= fixed_bits {
+        frame.write(0b000001, 6);
+        frame.write(0, 1);
+        for sample in samples {
+            frame.write_signed(*sample as i64, bits);
+        }
+        return;
+    }
+    frame.write(0b001000 | order as u64, 6);
+    frame.write(0, 1);
+    for sample in &samples[0..order] {
+        frame.write_signed(*sample as i64, bits);
+    }
+    // Rice coding with 5-bit parameters, in a single partition
+    frame.write(0b01, 2);
+    frame.write(0, 4);
+    frame.write(parameter as u64, 5);
+    for residual in fixed_residual(samples, order) {
+        let value = zigzag(residual);
+        frame.write_unary(value >> parameter);
+        frame.write(value & ((1 << parameter) - 1), parameter);
+    }
+}
+
+/// The residual of the fixed predictor of the given order
+fn fixed_residual(samples: &[i32], order: usize) -> impl Iterator<Item = i64> + '_ {
+    samples.windows(order + 1).map(move |window| {
+        let s = |i: usize| window[order - i] as i64;
+        match order {
+            0 => s(0),
+            1 => s(0) - s(1),
+            2 => s(0) - 2 * s(1) + s(2),
+            3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+            _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+        }
+    })
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// The Rice parameter coding the residual in the fewest bits, around the
+/// one suggested by its mean, and that number of bits
+fn rice_parameter(samples: &[i32], order: usize) -> (u32, u64) {
+    let count = (samples.len() - order) as u64;
+    let sum: u64 = fixed_residual(samples, order).map(zigzag).sum();
+    let estimate = (sum / count.max(1)).checked_ilog2().unwrap_or(0);
+    (estimate.saturating_sub(1)..=(estimate + 1).min(FLAC_MAX_RICE_PARAMETER))
+        .map(|parameter| {
+            let quotients: u64 = fixed_residual(samples, order)
+                .map(|residual| zigzag(residual) >> parameter)
+                .sum();
+            (parameter, quotients + count * (parameter as u64 + 1))
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+/// Writes bits to a byte buffer, most significant bit first
+struct BitWriter<'a> {
+    bytes: &'a mut Vec<u8>,
+    accumulator: u64,
+    /// The number of bits in `accumulator`, less than 8 between writes
+    bits: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(bytes: &'a mut Vec<u8>) -> Self {
+        bytes.clear();
+        BitWriter {
+            bytes,
+            accumulator: 0,
+            bits: 0,
+        }
+    }
+
+    /// Write the `bits` lowest bits of `value`, with `bits` at most 32
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `value` zeros followed by a one
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as u32 + 1);
+    }
+
+    /// Write a number the way UTF-8 encodes code points, for frame numbers
+    fn write_utf8(&mut self, value: u32) {
+        if value < 0x80 {
+            self.write(value as u64, 8);
+            return;
+        }
+        let continuation_bytes = match value {
+            0..0x800 => 1,
+            0x800..0x10000 => 2,
+            0x10000..0x200000 => 3,
+            0x200000..0x4000000 => 4,
+            _ => 5,
+        };
+        let leading_ones = ((0xFF00u32 >> (continuation_bytes + 1)) & 0xFF) as u64;
+        self.write(leading_ones | (value >> (6 * continuation_bytes)) as u64, 8);
+        for byte in (0..continuation_bytes).rev() {
+            self.write(0x80 | ((value >> (6 * byte)) & 0x3F) as u64, 8);
+        }
+    }
+
+    /// Pad the last byte with zeros
+    fn finish(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+
+    /// The complete bytes written so far
+    fn bytes(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, Chunk};
+    use crate::offline_sink::OfflineAudioSink;
+    use crate::sink::AudioSink;
+    use smallvec::smallvec;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    const SAMPLE_RATE: u32 = 44100;
+    const FRAMES: usize = 128;
+
+    /// A file written on the render thread, read back by the test
+    #[derive(Clone, Default)]
+    struct SharedFile(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn signal(frame: usize, channel: usize) -> f32 {
+        0.8 * (frame as f32 * 0.01 * (channel + 1) as f32).sin()
+    }
+
+    /// Render `length` frames of `signal` to a file, the way an offline
+    /// context does
+    fn render(format: AudioFileFormat, channels: usize, length: usize) -> Vec<u8> {
+        let file = SharedFile::default();
+        let sink = OfflineAudioSink::new(channels, length);
+        let writer = AudioFileWriter::new(file.clone(), format, SAMPLE_RATE);
+        sink.set_stream_callback(1000, writer.into_stream_callback())
+            .unwrap();
+        sink.play().unwrap();
+        let mut frame = 0;
+        while !sink.has_enough_data() {
+            let mut block = Block::for_channels_explicit(channels as u8, FRAMES);
+            for channel in 0..channels {
+                let data = block.data_chan_mut(channel as u8);
+                for (i, sample) in data.iter_mut().enumerate() {
+                    *sample = signal(frame + i, channel);
+                }
+            }
+            sink.push_data(Chunk {
+                blocks: smallvec![block],
+            })
+            .unwrap();
+            frame += FRAMES;
+        }
+        file.0.lock().unwrap().clone()
+    }
+
+    /// The GUID of the WAVE_FORMAT_EXTENSIBLE subformat, after its tag
+    const GUID_TAIL: [u8; 12] = [
+        0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+    ];
+
+    #[test]
+    fn wav_16_bit() {
+        let bytes = render(AudioFileFormat::Wav16, 2, 1000);
+        let header = [
+            &b"RIFF"[..],
+            &4036u32.to_le_bytes(),
+            b"WAVEfmt ",
+            &16u32.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &2u16.to_le_bytes(),
+            &44100u32.to_le_bytes(),
+            &176400u32.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &16u16.to_le_bytes(),
+            b"data",
+            &4000u32.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(bytes[..44], header[..]);
+        assert_eq!(bytes.len(), 44 + 4000);
+        for (i, sample) in bytes[44..].chunks(2).enumerate() {
+            let expected = quantize(signal(i / 2, i % 2), 16) as i16;
+            assert_eq!(i16::from_le_bytes([sample[0], sample[1]]), expected);
+        }
+    }
+
+    #[test]
+    fn wav_24_bit_with_padding() {
+        // 3003 bytes of mono data, padded to an even size
+        let bytes = render(AudioFileFormat::Wav24, 1, 1001);
+        let header = [
+            &b"RIFF"[..],
+            &3064u32.to_le_bytes(),
+            b"WAVEfmt ",
+            &40u32.to_le_bytes(),
+            &0xFFFEu16.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &44100u32.to_le_bytes(),
+            &132300u32.to_le_bytes(),
+            &3u16.to_le_bytes(),
+            &24u16.to_le_bytes(),
+            &22u16.to_le_bytes(),
+            &24u16.to_le_bytes(),
+            &4u32.to_le_bytes(),
+            &1u32.to_le_bytes(),
+            &GUID_TAIL,
+            b"data",
+            &3003u32.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(bytes[..68], header[..]);
+        assert_eq!(bytes.len(), 68 + 3003 + 1);
+        for (i, sample) in bytes[68..68 + 3003].chunks(3).enumerate() {
+            let sample = i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8;
+            assert_eq!(sample, quantize(signal(i, 0), 24));
+        }
+    }
+
+    #[test]
+    fn wav_float() {
+        let bytes = render(AudioFileFormat::WavFloat32, 2, 500);
+        let header = [
+            &b"RIFF"[..],
+            &4072u32.to_le_bytes(),
+            b"WAVEfmt ",
+            &40u32.to_le_bytes(),
+            &0xFFFEu16.to_le_bytes(),
+            &2u16.to_le_bytes(),
+            &44100u32.to_le_bytes(),
+            &352800u32.to_le_bytes(),
+            &8u16.to_le_bytes(),
+            &32u16.to_le_bytes(),
+            &22u16.to_le_bytes(),
+            &32u16.to_le_bytes(),
+            &3u32.to_le_bytes(),
+            &3u32.to_le_bytes(),
+            &GUID_TAIL,
+            b"fact",
+            &4u32.to_le_bytes(),
+            &500u32.to_le_bytes(),
+            b"data",
+            &4000u32.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(bytes[..80], header[..]);
+        assert_eq!(bytes.len(), 80 + 4000);
+        for (i, sample) in bytes[80..].chunks(4).enumerate() {
+            let sample = f32::from_le_bytes(sample.try_into().unwrap());
+            assert_eq!(sample, signal(i / 2, i % 2));
+        }
+    }
+
+    /// Decode a FLAC file, checking its stream info, and return its
+    /// samples per channel along with the size of every block
+    fn decode_flac(bytes: Vec<u8>, channels: u32, bits: u32) -> (Vec<Vec<i32>>, Vec<u32>) {
+        let mut reader = claxon::FlacReader::new(Cursor::new(bytes)).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, SAMPLE_RATE);
+        assert_eq!(info.channels, channels);
+        assert_eq!(info.bits_per_sample, bits);
+        let mut samples = vec![Vec::new(); channels as usize];
+        let mut block_sizes = Vec::new();
+        let mut blocks = reader.blocks();
+        let mut buffer = Vec::new();
+        while let Some(block) = blocks.read_next_or_eof(buffer).unwrap() {
+            for (channel, samples) in samples.iter_mut().enumerate() {
+                samples.extend_from_slice(block.channel(channel as u32));
+            }
+            block_sizes.push(block.duration());
+            buffer = block.into_buffer();
+        }
+        (samples, block_sizes)
+    }
+
+    fn flac_round_trip(format: AudioFileFormat, channels: usize) {
+        let bits = format.bits_per_sample();
+        // Two whole FLAC frames, and a shorter last one
+        let length = 2 * FLAC_BLOCK_SIZE + 1808;
+        let bytes = render(format, channels, length);
+        let (samples, block_sizes) = decode_flac(bytes, channels as u32, bits);
+        assert_eq!(block_sizes, [4096, 4096, 1808]);
+        for (channel, samples) in samples.iter().enumerate() {
+            let expected: Vec<_> = (0..length)
+                .map(|frame| quantize(signal(frame, channel), bits))
+                .collect();
+            assert_eq!(*samples, expected);
+        }
+    }
+
+    #[test]
+    fn flac_16_bit_round_trip() {
+        flac_round_trip(AudioFileFormat::Flac16, 2);
+    }
+
+    #[test]
+    fn flac_24_bit_round_trip() {
+        flac_round_trip(AudioFileFormat::Flac24, 3);
+    }
+
+    #[test]
+    fn empty_rendering() {
+        let bytes = render(AudioFileFormat::Wav16, 2, 0);
+        assert_eq!(bytes.len(), 44);
+        assert_eq!(bytes[4..8], 36u32.to_le_bytes());
+        assert_eq!(bytes[40..44], 0u32.to_le_bytes());
+
+        let bytes = render(AudioFileFormat::Flac16, 1, 0);
+        let (samples, block_sizes) = decode_flac(bytes, 1, 16);
+        assert!(samples[0].is_empty());
+        assert!(block_sizes.is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use num_complex::Complex32;
+use std::f32::consts::PI;
+
+/// An in-place, iterative radix-2 FFT of a fixed power-of-two size.
+///
+/// The twiddle factors and the bit-reversal permutation are computed
+/// once on construction, so that `forward`/`inverse` never allocate.
+pub struct Fft {
+    size: usize,
+    /// exp(-2πik/size) for k in 0..size/2
+    twiddles: Vec<Complex32>,
+    /// The bit-reversed index of every index in 0..size
+    bit_reverse: Vec<usize>,
+    /// Scratch space for real-valued transforms
+    scratch: Vec<Complex32>,
+}
+
+impl Fft {
+    pub fn new(size: usize) -> Self {
+        assert!(size >= 2 && size.is_power_of_two());
+        let bits = size.trailing_zeros();
+        let twiddles = (0..size / 2)
+            .map(|k| Complex32::from_polar(&1., &(-2. * PI * k as f32 / size as f32)))
+            .collect();
+        let bit_reverse = (0..size)
+            .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+            .collect();
+        Fft {
+            size,
+            twiddles,
+            bit_reverse,
+            scratch: vec![Complex32::new(0., 0.); size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bins returned by `forward_real`, i.e. the
+    /// non-redundant half of the spectrum of a real signal
+    pub fn real_bins(&self) -> usize {
+        self.size / 2 + 1
+    }
+
+    /// Forward transform, in place
+    pub fn forward(&self, data: &mut [Complex32]) {
+        self.transform(data, false);
+    }
+
+    /// Unnormalized inverse transform, in place
+    ///
+    /// The result must be divided by `size()` to obtain the original signal
+    pub fn inverse(&self, data: &mut [Complex32]) {
+        self.transform(data, true);
+    }
+
+    /// Transform a real signal of length `size()` into its `real_bins()`
+    /// non-redundant frequency bins
+    pub fn forward_real(&mut self, input: &[f32], output: &mut [Complex32]) {
+        debug_assert!(input.len() == self.size);
+        debug_assert!(output.len() == self.real_bins());
+        let mut scratch = std::mem::take(&mut self.scratch);
+        for (s, i) in scratch.iter_mut().zip(input) {
+            *s = Complex32::new(*i, 0.);
+        }
+        self.forward(&mut scratch);
+        output.copy_from_slice(&scratch[..self.real_bins()]);
+        self.scratch = scratch;
+    }
+
+    /// Transform `real_bins()` frequency bins of a real signal back into
+    /// the (normalized) signal of length `size()`
+    pub fn inverse_real(&mut self, input: &[Complex32], output: &mut [f32]) {
+        debug_assert!(input.len() == self.real_bins());
+        debug_assert!(output.len() == self.size);
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let half = self.size / 2;
+        scratch[..=half].copy_from_slice(input);
+        // the spectrum of a real signal is conjugate-symmetric
+        for k in 1..half {
+            scratch[self.size - k] = input[k].conj();
+        }
+        self.inverse(&mut scratch);
+        let scale = 1. / self.size as f32;
+        for (o, s) in output.iter_mut().zip(scratch.iter()) {
+            *o = s.re * scale;
+        }
+        self.scratch = scratch;
+    }
+
+    fn transform(&self, data: &mut [Complex32], inverse: bool) {
+        debug_assert!(data.len() == self.size);
+        for (i, &j) in self.bit_reverse.iter().enumerate() {
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let half = len / 2;
+            let stride = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let twiddle = self.twiddles[k * stride];
+                    let twiddle = if inverse { twiddle.conj() } else { twiddle };
+                    let a = data[start + k];
+                    let b = data[start + k + half] * twiddle;
+                    data[start + k] = a + b;
+                    data[start + k + half] = a - b;
+                }
+            }
+            len <<= 1;
+        }
+    }
+}
@@ -20,13 +20,20 @@ impl Default for GainNodeOptions {
 pub(crate) struct GainNode {
     channel_info: ChannelInfo,
     gain: Param,
+    /// Scratch space for the gain of every frame of a block
+    gains: Vec<f32>,
 }
 
 impl GainNode {
-    pub fn new(options: GainNodeOptions, channel_info: ChannelInfo) -> Self {
+    pub fn new(
+        options: GainNodeOptions,
+        channel_info: ChannelInfo,
+        frames_per_block: usize,
+    ) -> Self {
         Self {
             channel_info,
             gain: Param::new(options.gain),
+            gains: vec![0.; frames_per_block],
         }
     }
 
@@ -47,16 +54,19 @@ impl AudioNodeEngine for GainNode {
             return inputs;
         }
 
-        {
-            let mut iter = inputs.blocks[0].iter();
-            let mut gain = self.gain.value();
-
-            while let Some(mut frame) = iter.next() {
-                if self.update_parameters(info, frame.tick()) {
-                    gain = self.gain.value();
-                }
-                frame.mutate_with(|sample, _| *sample = *sample * gain);
+        let mut gain = self.gain.value();
+        let mut varies = false;
+        for frame in 0..self.gains.len() {
+            if self.update_parameters(info, Tick(frame as u64)) {
+                gain = self.gain.value();
+                varies |= frame > 0;
             }
+            self.gains[frame] = gain;
+        }
+        if varies {
+            inputs.blocks[0].apply_gains(&self.gains);
+        } else {
+            inputs.blocks[0].apply_gain(gain);
         }
         inputs
     }
@@ -67,4 +77,8 @@ impl AudioNodeEngine for GainNode {
             _ => panic!("Unknown param {:?} for GainNode", id),
         }
     }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::Gain]
+    }
 }
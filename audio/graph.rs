@@ -1,23 +1,43 @@
-use crate::block::{Block, Chunk};
+use crate::block::{Block, Chunk, Tick};
 use crate::destination_node::DestinationNode;
 use crate::listener::AudioListenerNode;
-use crate::node::{AudioNodeEngine, BlockInfo, ChannelCountMode, ChannelInterpretation};
+use crate::node::{
+    AudioNodeEngine, AudioNodeMessage, AudioNodeType, BlockInfo, ChannelCountMode,
+    ChannelInterpretation, Garbage,
+};
 use crate::param::ParamType;
+use crate::profiling::{NodeProfile, NodeTiming};
+use crate::queue::{Producer, queue};
+use crate::workers::WorkerPool;
 use petgraph::Direction;
 use petgraph::graph::DefaultIx;
 use petgraph::stable_graph::NodeIndex;
 use petgraph::stable_graph::StableGraph;
-use petgraph::visit::{DfsPostOrder, EdgeRef, Reversed};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeIndexable};
 use smallvec::SmallVec;
-use std::cell::{RefCell, RefMut};
-use std::{cmp, fmt, hash};
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::thread::{self, Builder};
+use std::time::Instant;
+use std::{cmp, fmt, hash, mem};
 
 #[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Debug)]
 /// A unique identifier for nodes in the graph. Stable
 /// under graph mutation.
-pub struct NodeId(NodeIndex<DefaultIx>);
+///
+/// Ids are allocated on the control thread, so that nodes can be
+/// referred to before the render thread adds them to the graph.
+pub struct NodeId(u32);
 
 impl NodeId {
+    /// The id to give the node created after this one. The graph's own
+    /// nodes have the lowest ids.
+    pub(crate) fn next(self) -> NodeId {
+        NodeId(self.0 + 1)
+    }
     pub fn input(self, port: u32) -> PortId<InputPort> {
         PortId(self, PortIndex::Port(port))
     }
@@ -93,13 +113,419 @@ impl PortKind for OutputPort {
 
 pub struct AudioGraph {
     graph: StableGraph<Node, Edge>,
+    /// Where each node is in `graph`
+    indices: HashMap<NodeId, NodeIndex<DefaultIx>>,
+    /// Takes the engines of removed nodes off the render thread
+    disposer: Disposer,
     dest_id: NodeId,
     dests: Vec<NodeId>,
     listener_id: NodeId,
+    /// The order in which nodes are processed, recomputed lazily once
+    /// the topology of the graph changes
+    order: Schedule,
+    /// Whether `order` needs recomputing
+    order_stale: bool,
+    /// What computing the order works in
+    scratch: OrderScratch,
+    /// Threads processing independent branches of the graph alongside
+    /// the render thread, if any
+    workers: Option<WorkerPool>,
+    /// The number of frames in a block
+    frames_per_block: usize,
+    /// The frame of the next block from which connection changes take effect
+    mutation_offset: usize,
+    /// Whether some connections only carry part of the next block
+    gated: bool,
+    /// Time spent processing each node, if profiling
+    timings: Option<HashMap<NodeIndex<DefaultIx>, Cell<NodeTiming>>>,
+    /// Nodes the control thread doesn't refer to anymore, to be dropped
+    /// once they can't be heard
+    released: HashMap<NodeIndex<DefaultIx>, Lifetime>,
+    /// Released nodes which can't be heard anymore, only filled while
+    /// dropping them
+    finished: Vec<NodeIndex<DefaultIx>>,
+    /// Whether idle nodes are skipped, which only tests turn off
+    skip_idle: bool,
+}
+
+/// Frees memory the render thread is done with on a thread of its own,
+/// as freeing memory on the render thread could make it miss its deadline
+struct Disposer {
+    queue: Producer<Garbage>,
+    /// Garbage waiting for room in the queue, if the disposer thread is
+    /// lagging behind. It only allocates once it holds `CAPACITY` items.
+    pending: Vec<Garbage>,
+}
+
+impl Disposer {
+    /// How many items can wait to be freed, in the queue and in `pending`
+    /// each
+    const CAPACITY: usize = 256;
+
+    fn spawn() -> Self {
+        let (mut queue, garbage) = queue(Self::CAPACITY);
+        let join_handle = Builder::new()
+            .name("AudioNodeDisposer".to_owned())
+            .spawn(move || {
+                loop {
+                    // Checked first, so that nothing pushed before the
+                    // graph is dropped gets missed
+                    let closed = garbage.is_closed();
+                    while garbage.pop().is_some() {}
+                    if closed {
+                        break;
+                    }
+                    // Woken up by a push, or once the graph is dropped
+                    thread::park();
+                }
+            })
+            .expect("Failed to spawn AudioNodeDisposer");
+        queue.set_consumer_thread(join_handle.thread().clone());
+        Disposer {
+            queue,
+            pending: Vec::with_capacity(Self::CAPACITY),
+        }
+    }
+
+    fn dispose(&mut self, garbage: Garbage) {
+        self.flush();
+        if !self.pending.is_empty() {
+            self.pending.push(garbage);
+        } else if let Err(garbage) = self.queue.push(garbage) {
+            self.pending.push(garbage);
+        }
+    }
+
+    /// Move pending garbage to the queue, as far as there is room
+    fn flush(&mut self) {
+        while let Some(garbage) = self.pending.pop() {
+            if let Err(garbage) = self.queue.push(garbage) {
+                self.pending.push(garbage);
+                break;
+            }
+        }
+    }
+}
+
+/// How many nodes and edges the graph has room for from the start. Room
+/// for more is made on the render thread, when adding nodes or edges.
+const NODE_CAPACITY: usize = 1024;
+const EDGE_CAPACITY: usize = 4 * NODE_CAPACITY;
+
+/// Amplitude below which output is considered silent, about -100 dBFS
+const INAUDIBLE: f32 = 1e-5;
+
+/// What is known of a released node's activity
+#[derive(Default)]
+struct Lifetime {
+    /// The frame from which the node had no inputs
+    inputless_since: Option<Tick>,
+    /// Whether the node produced audible output in the current block
+    audible: Cell<bool>,
+}
+
+/// A single step of processing a block
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Step {
+    /// Process a node as usual
+    Process(NodeIndex<DefaultIx>),
+    /// Produce the output of a cycle-breaking node, before its input
+    /// for this block is available
+    Read(NodeIndex<DefaultIx>),
+    /// Feed the input for this block to a cycle-breaking node
+    Write(NodeIndex<DefaultIx>),
+    /// Output silence for a node in a cycle without a cycle-breaking node
+    Mute(NodeIndex<DefaultIx>),
+}
+
+impl Step {
+    fn node(self) -> NodeIndex<DefaultIx> {
+        match self {
+            Step::Process(ix) | Step::Read(ix) | Step::Write(ix) | Step::Mute(ix) => ix,
+        }
+    }
+}
+
+/// The steps of processing a block, grouped for parallel rendering
+///
+/// Steps are split into branches: chains of steps which only depend on
+/// each other, up to the points where several branches are mixed
+/// together or one branch fans out. Branches are then grouped into
+/// stages, each stage only depending on the previous ones, so that the
+/// branches of a stage can be processed in parallel. As every step is
+/// still processed by a single thread, and inputs are always mixed in the
+/// same order, the result doesn't depend on how branches get scheduled.
+#[derive(Default)]
+struct Schedule {
+    /// Every step, in an order in which they can be processed serially
+    steps: Vec<Step>,
+    /// The steps of each branch, as ranges of `steps`
+    branches: Vec<Range<usize>>,
+    /// The branches of each stage, as ranges of `branches`
+    stages: Vec<Range<usize>>,
+}
+
+impl Schedule {
+    fn with_capacity(nodes: usize) -> Self {
+        Schedule {
+            // Nodes breaking cycles take two steps
+            steps: Vec::with_capacity(2 * nodes),
+            branches: Vec::with_capacity(nodes),
+            stages: Vec::with_capacity(nodes),
+        }
+    }
+}
+
+/// The buffers computing the order works in, kept from one topology
+/// change to the next so that it only allocates while the graph grows
+#[derive(Default)]
+struct OrderScratch {
+    /// Whether a destination depends on each node, by node index
+    reachable: Vec<bool>,
+    /// Nodes whose dependencies are left to visit
+    stack: Vec<NodeIndex<DefaultIx>>,
+    /// Whether each node is split into a read and a write step
+    split: Vec<bool>,
+    steps: Vec<Step>,
+    /// The first step of each node in `steps`, by node index
+    first_step: Vec<usize>,
+    /// Connections between nodes, then between steps
+    edges: Adjacency,
+    sccs: StronglyConnected,
+    /// Which component of the steps each step is in
+    scc_of: Vec<usize>,
+    /// The components each component of the steps depends on
+    preds: Adjacency,
+    /// How many components depend on each component
+    succ_counts: Vec<usize>,
+    /// Which branch each component is in
+    branch_of: Vec<usize>,
+    /// Which stage each branch is in
+    stage_of: Vec<usize>,
+    /// Each step with its stage, its branch and the order it was added in
+    placed: Vec<(usize, usize, usize, Step)>,
+}
+
+impl OrderScratch {
+    fn with_capacity(nodes: usize, edges: usize) -> Self {
+        let steps = 2 * nodes;
+        OrderScratch {
+            reachable: Vec::with_capacity(nodes),
+            stack: Vec::with_capacity(nodes),
+            split: Vec::with_capacity(nodes),
+            steps: Vec::with_capacity(steps),
+            first_step: Vec::with_capacity(nodes),
+            edges: Adjacency::with_capacity(steps, edges),
+            sccs: StronglyConnected::with_capacity(steps),
+            scc_of: Vec::with_capacity(steps),
+            preds: Adjacency::with_capacity(steps, edges),
+            succ_counts: Vec::with_capacity(steps),
+            branch_of: Vec::with_capacity(steps),
+            stage_of: Vec::with_capacity(steps),
+            placed: Vec::with_capacity(steps),
+        }
+    }
+}
+
+/// Edges between vertices numbered from 0, the targets of each vertex
+/// stored next to each other
+#[derive(Default)]
+struct Adjacency {
+    /// Edges as they are added, before `build` sorts them
+    added: Vec<(usize, usize)>,
+    /// Where the targets of each vertex start in `targets`, followed by
+    /// the number of targets
+    starts: Vec<usize>,
+    targets: Vec<usize>,
+}
+
+impl Adjacency {
+    fn with_capacity(vertices: usize, edges: usize) -> Self {
+        Adjacency {
+            added: Vec::with_capacity(edges),
+            starts: Vec::with_capacity(vertices + 1),
+            targets: Vec::with_capacity(edges),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.added.clear();
+    }
+
+    fn add(&mut self, from: usize, to: usize) {
+        self.added.push((from, to));
+    }
+
+    /// Index the edges added since the last `clear`, dropping duplicates
+    fn build(&mut self, vertices: usize) {
+        self.added.sort_unstable();
+        self.added.dedup();
+        self.starts.clear();
+        self.targets.clear();
+        let mut edges = self.added.iter().peekable();
+        for vertex in 0..vertices {
+            self.starts.push(self.targets.len());
+            while let Some(&(_, to)) = edges.next_if(|&&(from, _)| from == vertex) {
+                self.targets.push(to);
+            }
+        }
+        self.starts.push(self.targets.len());
+    }
+
+    fn vertices(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    fn targets(&self, vertex: usize) -> &[usize] {
+        &self.targets[self.starts[vertex]..self.starts[vertex + 1]]
+    }
+
+    /// Whether a strongly connected component is a cycle
+    fn is_cyclic(&self, scc: &[usize]) -> bool {
+        scc.len() > 1 || self.targets(scc[0]).contains(&scc[0])
+    }
+}
+
+/// Finds strongly connected components with Tarjan's algorithm
+///
+/// https://en.wikipedia.org/wiki/Tarjan%27s_strongly_connected_components_algorithm
+#[derive(Default)]
+struct StronglyConnected {
+    /// The order each vertex was visited in, `usize::MAX` until it is
+    index: Vec<usize>,
+    /// The lowest index reachable from each vertex
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    /// Visited vertices not yet assigned to a component
+    stack: Vec<usize>,
+    /// The vertices being visited, with how many of their targets were
+    calls: Vec<(usize, usize)>,
+    /// The vertices of every component, one component after the other
+    vertices: Vec<usize>,
+    /// Where each component ends in `vertices`
+    ends: Vec<usize>,
+}
+
+impl StronglyConnected {
+    fn with_capacity(vertices: usize) -> Self {
+        StronglyConnected {
+            index: Vec::with_capacity(vertices),
+            lowlink: Vec::with_capacity(vertices),
+            on_stack: Vec::with_capacity(vertices),
+            stack: Vec::with_capacity(vertices),
+            calls: Vec::with_capacity(vertices),
+            vertices: Vec::with_capacity(vertices),
+            ends: Vec::with_capacity(vertices),
+        }
+    }
+
+    /// Find the components of a graph, which come out in reverse
+    /// topological order
+    fn run(&mut self, graph: &Adjacency) {
+        let StronglyConnected {
+            index,
+            lowlink,
+            on_stack,
+            stack,
+            calls,
+            vertices,
+            ends,
+        } = self;
+        let count = graph.vertices();
+        index.clear();
+        index.resize(count, usize::MAX);
+        lowlink.clear();
+        lowlink.resize(count, 0);
+        on_stack.clear();
+        on_stack.resize(count, false);
+        vertices.clear();
+        ends.clear();
+        let mut next = 0;
+        for root in 0..count {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            calls.push((root, 0));
+            while let Some(&mut (vertex, ref mut visited)) = calls.last_mut() {
+                if index[vertex] == usize::MAX {
+                    index[vertex] = next;
+                    lowlink[vertex] = next;
+                    next += 1;
+                    on_stack[vertex] = true;
+                    stack.push(vertex);
+                }
+                if let Some(&target) = graph.targets(vertex).get(*visited) {
+                    *visited += 1;
+                    if index[target] == usize::MAX {
+                        calls.push((target, 0));
+                    } else if on_stack[target] {
+                        lowlink[vertex] = lowlink[vertex].min(index[target]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some(&(caller, _)) = calls.last() {
+                    lowlink[caller] = lowlink[caller].min(lowlink[vertex]);
+                }
+                if lowlink[vertex] == index[vertex] {
+                    loop {
+                        let member = stack.pop().expect("The vertex is on the stack");
+                        on_stack[member] = false;
+                        vertices.push(member);
+                        if member == vertex {
+                            break;
+                        }
+                    }
+                    ends.push(vertices.len());
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn components(&self) -> impl DoubleEndedIterator<Item = &[usize]> {
+        (0..self.ends.len()).map(move |i| {
+            let start = if i == 0 { 0 } else { self.ends[i - 1] };
+            &self.vertices[start..self.ends[i]]
+        })
+    }
+}
+
+/// Gives the render workers access to the graph while processing a block
+///
+/// The `RefCell`s and `Cell`s of nodes and connections make the graph
+/// unfit for sharing in general. Branches of the same stage have no node
+/// in common, and only share connections with branches of other stages,
+/// so they can be processed at the same time nonetheless.
+struct SharedGraph<'a>(&'a AudioGraph);
+
+unsafe impl Sync for SharedGraph<'_> {}
+
+impl SharedGraph<'_> {
+    fn process_step(&self, step: Step, info: &BlockInfo) {
+        self.0.process_step(step, info)
+    }
+}
+
+/// Which inputs of a node to collect
+#[derive(Clone, Copy)]
+enum Gather {
+    All,
+    Ports,
+    Params,
 }
 
 pub(crate) struct Node {
+    id: NodeId,
     node: RefCell<Box<dyn AudioNodeEngine>>,
+    /// The frame from which all of the inputs have been silent
+    silent_since: Cell<Option<Tick>>,
+    /// Whether the last output was inaudible, only tracked while the
+    /// inputs are silent
+    quiet: Cell<bool>,
 }
 
 /// An edge in the graph
@@ -113,37 +539,6 @@ pub(crate) struct Edge {
     connections: SmallVec<[Connection; 1]>,
 }
 
-impl Edge {
-    /// Find if there are connections between two given ports, return the index
-    fn has_between(
-        &self,
-        output_idx: PortIndex<OutputPort>,
-        input_idx: PortIndex<InputPort>,
-    ) -> bool {
-        self.connections
-            .iter()
-            .find(|e| e.input_idx == input_idx && e.output_idx == output_idx)
-            .is_some()
-    }
-
-    fn remove_by_output(&mut self, output_idx: PortIndex<OutputPort>) {
-        self.connections.retain(|i| i.output_idx != output_idx)
-    }
-
-    fn remove_by_input(&mut self, input_idx: PortIndex<InputPort>) {
-        self.connections.retain(|i| i.input_idx != input_idx)
-    }
-
-    fn remove_by_pair(
-        &mut self,
-        output_idx: PortIndex<OutputPort>,
-        input_idx: PortIndex<InputPort>,
-    ) {
-        self.connections
-            .retain(|i| i.output_idx != output_idx || i.input_idx != input_idx)
-    }
-}
-
 /// A single connection between ports
 struct Connection {
     /// The index of the port on the input node
@@ -155,50 +550,99 @@ struct Connection {
     /// When the from node finishes processing, it will push
     /// its data into this cache for the input node to read
     cache: RefCell<Option<Block>>,
+    /// The frames of the current block this connection carries, for
+    /// connections made or removed in the middle of a block
+    frames: Range<usize>,
+    /// Whether this connection goes away after the current block
+    closing: bool,
 }
 
 impl AudioGraph {
-    pub fn new(channel_count: u8) -> Self {
-        let mut graph = StableGraph::new();
-        let dest_id =
-            NodeId(graph.add_node(Node::new(Box::new(DestinationNode::new(channel_count)))));
-        let listener_id = NodeId(graph.add_node(Node::new(Box::new(AudioListenerNode::new()))));
-        AudioGraph {
-            graph,
+    /// Create a graph rendering blocks of `frames_per_block` frames,
+    /// processing independent branches on up to `render_workers` threads
+    /// on top of the render thread, as long as there are cores for them
+    pub fn new(channel_count: u8, frames_per_block: usize, render_workers: usize) -> Self {
+        let dest_id = NodeId(0);
+        let listener_id = dest_id.next();
+        let mut graph = AudioGraph {
+            graph: StableGraph::with_capacity(NODE_CAPACITY, EDGE_CAPACITY),
+            indices: HashMap::with_capacity(NODE_CAPACITY),
+            disposer: Disposer::spawn(),
             dest_id,
             dests: vec![dest_id],
             listener_id,
-        }
+            order: Schedule::with_capacity(NODE_CAPACITY),
+            order_stale: true,
+            scratch: OrderScratch::with_capacity(NODE_CAPACITY, EDGE_CAPACITY),
+            workers: {
+                // More threads than cores would only get in each other's way
+                let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+                let count = render_workers.min(cores - 1);
+                (count > 0).then(|| WorkerPool::new(count))
+            },
+            frames_per_block,
+            mutation_offset: 0,
+            gated: false,
+            timings: None,
+            released: HashMap::with_capacity(NODE_CAPACITY),
+            finished: Vec::with_capacity(NODE_CAPACITY),
+            skip_idle: true,
+        };
+        graph.add_node(dest_id, Box::new(DestinationNode::new(channel_count)));
+        graph.add_node(listener_id, Box::new(AudioListenerNode::new()));
+        graph
+    }
+
+    /// Add a node, with an id allocated by the control thread
+    pub(crate) fn add_node(&mut self, id: NodeId, node: Box<dyn AudioNodeEngine>) {
+        self.order_stale = true;
+        let ix = self.graph.add_node(Node::new(id, node));
+        self.indices.insert(id, ix);
     }
 
-    /// Create a node, obtain its id
-    pub(crate) fn add_node(&mut self, node: Box<dyn AudioNodeEngine>) -> NodeId {
-        NodeId(self.graph.add_node(Node::new(node)))
+    /// Where a node is in the underlying graph
+    fn index(&self, id: NodeId) -> NodeIndex<DefaultIx> {
+        *self
+            .indices
+            .get(&id)
+            .unwrap_or_else(|| panic!("Unknown node {:?}", id))
     }
 
     /// Connect an output port to an input port
     ///
     /// The edge goes *from* the output port *to* the input port, connecting two nodes
     pub fn add_edge(&mut self, out: PortId<OutputPort>, inp: PortId<InputPort>) {
+        self.order_stale = true;
+        let offset = self.mutation_offset;
+        let (from, to) = (self.index(out.node()), self.index(inp.node()));
         let edge = self
             .graph
-            .edges(out.node().0)
-            .find(|e| e.target() == inp.node().0)
+            .edges(from)
+            .find(|e| e.target() == to)
             .map(|e| e.id());
+        let connection = Connection::new(inp.1, out.1, offset..self.frames_per_block);
+        self.gated |= offset > 0;
         if let Some(e) = edge {
-            // .find(|e| e.weight().has_between(out.1, inp.1));
             let w = self
                 .graph
                 .edge_weight_mut(e)
                 .expect("This edge is known to exist");
-            if w.has_between(out.1, inp.1) {
+            if let Some(existing) = w
+                .connections
+                .iter_mut()
+                .find(|c| c.input_idx == inp.1 && c.output_idx == out.1)
+            {
+                // Reconnecting in the block it gets disconnected in
+                if existing.closing {
+                    existing.closing = false;
+                    existing.frames.end = self.frames_per_block;
+                }
                 return;
             }
-            w.connections.push(Connection::new(inp.1, out.1))
+            w.connections.push(connection)
         } else {
             // add a new edge
-            self.graph
-                .add_edge(out.node().0, inp.node().0, Edge::new(inp.1, out.1));
+            self.graph.add_edge(from, to, Edge::new(connection));
         }
     }
 
@@ -206,66 +650,28 @@ impl AudioGraph {
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect
     pub fn disconnect_all_from(&mut self, node: NodeId) {
-        let edges = self.graph.edges(node.0).map(|e| e.id()).collect::<Vec<_>>();
-        for edge in edges {
-            self.graph.remove_edge(edge);
-        }
+        self.remove_connections(node, None, |_| true);
     }
 
     // /// Disconnect all outgoing connections from a node's output
     // ///
     // /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-output
     pub fn disconnect_output(&mut self, out: PortId<OutputPort>) {
-        let candidates: Vec<_> = self
-            .graph
-            .edges(out.node().0)
-            .map(|e| (e.id(), e.target()))
-            .collect();
-        for (edge, to) in candidates {
-            let mut e = self
-                .graph
-                .remove_edge(edge)
-                .expect("Edge index is known to exist");
-            e.remove_by_output(out.1);
-            if !e.connections.is_empty() {
-                self.graph.add_edge(out.node().0, to, e);
-            }
-        }
+        self.remove_connections(out.node(), None, |c| c.output_idx == out.1);
     }
 
     /// Disconnect connections from a node to another node
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode
     pub fn disconnect_between(&mut self, from: NodeId, to: NodeId) {
-        let edge = self
-            .graph
-            .edges(from.0)
-            .find(|e| e.target() == to.0)
-            .map(|e| e.id());
-        if let Some(i) = edge {
-            self.graph.remove_edge(i);
-        }
+        self.remove_connections(from, Some(to), |_| true);
     }
 
     /// Disconnect all outgoing connections from a node's output to another node
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationnode-output
     pub fn disconnect_output_between(&mut self, out: PortId<OutputPort>, to: NodeId) {
-        let edge = self
-            .graph
-            .edges(out.node().0)
-            .find(|e| e.target() == to.0)
-            .map(|e| e.id());
-        if let Some(edge) = edge {
-            let mut e = self
-                .graph
-                .remove_edge(edge)
-                .expect("Edge index is known to exist");
-            e.remove_by_output(out.1);
-            if !e.connections.is_empty() {
-                self.graph.add_edge(out.node().0, to.0, e);
-            }
-        }
+        self.remove_connections(out.node(), Some(to), |c| c.output_idx == out.1);
     }
 
     /// Disconnect all outgoing connections from a node to another node's input
@@ -274,21 +680,7 @@ impl AudioGraph {
     ///
     /// https://webaudio.github.io/web-audio-api/#dom-audionode-disconnect-destinationparam
     pub fn disconnect_to(&mut self, node: NodeId, inp: PortId<InputPort>) {
-        let edge = self
-            .graph
-            .edges(node.0)
-            .find(|e| e.target() == inp.node().0)
-            .map(|e| e.id());
-        if let Some(edge) = edge {
-            let mut e = self
-                .graph
-                .remove_edge(edge)
-                .expect("Edge index is known to exist");
-            e.remove_by_input(inp.1);
-            if !e.connections.is_empty() {
-                self.graph.add_edge(node.0, inp.node().0, e);
-            }
-        }
+        self.remove_connections(node, Some(inp.node()), |c| c.input_idx == inp.1);
     }
 
     /// Disconnect all outgoing connections from a node's output to another node's input
@@ -300,19 +692,76 @@ impl AudioGraph {
         out: PortId<OutputPort>,
         inp: PortId<InputPort>,
     ) {
-        let edge = self
+        self.remove_connections(out.node(), Some(inp.node()), |c| {
+            c.output_idx == out.1 && c.input_idx == inp.1
+        });
+    }
+
+    /// Remove the matching connections going from a node, to any node
+    /// or to the given one
+    fn remove_connections(
+        &mut self,
+        from: NodeId,
+        to: Option<NodeId>,
+        matches: impl Fn(&Connection) -> bool,
+    ) {
+        let offset = self.mutation_offset;
+        let to = to.map(|to| self.index(to));
+        let edges: Vec<_> = self
             .graph
-            .edges(out.node().0)
-            .find(|e| e.target() == inp.node().0)
-            .map(|e| e.id());
-        if let Some(edge) = edge {
-            let mut e = self
+            .edges(self.index(from))
+            .filter(|e| to.is_none_or(|to| e.target() == to))
+            .map(|e| e.id())
+            .collect();
+        for edge in edges {
+            let e = self
                 .graph
-                .remove_edge(edge)
+                .edge_weight_mut(edge)
                 .expect("Edge index is known to exist");
-            e.remove_by_pair(out.1, inp.1);
-            if !e.connections.is_empty() {
-                self.graph.add_edge(out.node().0, inp.node().0, e);
+            if offset == 0 {
+                e.connections.retain(|c| !matches(c));
+                if e.connections.is_empty() {
+                    self.graph.remove_edge(edge);
+                }
+                self.order_stale = true;
+            } else {
+                // Keep the connection until the end of the next block,
+                // only carrying the frames before the offset
+                for c in e.connections.iter_mut().filter(|c| matches(c)) {
+                    c.frames.end = cmp::min(c.frames.end, offset);
+                    c.closing = true;
+                    self.gated = true;
+                }
+            }
+        }
+    }
+
+    /// Make the connection changes made until the next block is processed
+    /// only take effect from the given frame of that block
+    pub(crate) fn set_mutation_offset(&mut self, offset: usize) {
+        debug_assert!(offset < self.frames_per_block);
+        self.mutation_offset = offset;
+    }
+
+    /// Remove the connections that were disconnected in the middle of the
+    /// block just processed, and let the others carry whole blocks again
+    fn ungate(&mut self) {
+        if !self.gated {
+            return;
+        }
+        self.gated = false;
+        let edges: Vec<_> = self.graph.edge_indices().collect();
+        for edge in edges {
+            let e = &mut self.graph[edge];
+            if e.connections.iter().any(|c| c.closing) {
+                e.connections.retain(|c| !c.closing);
+                self.order_stale = true;
+            }
+            for c in e.connections.iter_mut() {
+                c.frames = 0..self.frames_per_block;
+            }
+            if e.connections.is_empty() {
+                self.graph.remove_edge(edge);
             }
         }
     }
@@ -326,6 +775,7 @@ impl AudioGraph {
 
     /// Add additional terminator nodes
     pub fn add_extra_dest(&mut self, dest: NodeId) {
+        self.order_stale = true;
         self.dests.push(dest);
     }
 
@@ -340,192 +790,1246 @@ impl AudioGraph {
         self.listener_id
     }
 
-    /// For a given block, process all the data on this graph
-    pub fn process(&mut self, info: &BlockInfo) -> Chunk {
-        // DFS post order: Children are processed before their parent,
-        // which is exactly what we need since the parent depends on the
-        // children's output
-        //
-        // This will only visit each node once
-        let reversed = Reversed(&self.graph);
-
-        let mut blocks: SmallVec<[SmallVec<[Block; 1]>; 1]> = SmallVec::new();
-        let mut output_counts: SmallVec<[u32; 1]> = SmallVec::new();
-
-        let mut visit = DfsPostOrder::empty(reversed);
+    /// Compute the order in which nodes are processed for a block
+    ///
+    /// This is a topological sort of all the nodes the destinations depend
+    /// on. Cycles are broken at DelayNodes, which are split into a step that
+    /// produces their output before anything else in the cycle is processed,
+    /// and a step that consumes their input after everything else has been.
+    /// Cycles without a DelayNode are muted.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#rendering-loop
+    /// https://webaudio.github.io/web-audio-api/#cycles
+    fn compute_order(&mut self) {
+        let mut scratch = mem::take(&mut self.scratch);
+        let OrderScratch {
+            reachable,
+            stack,
+            split,
+            steps,
+            first_step,
+            edges,
+            sccs,
+            scc_of,
+            preds,
+            succ_counts,
+            branch_of,
+            stage_of,
+            placed,
+        } = &mut scratch;
+        let bound = self.graph.node_bound();
 
+        // Find everything the destinations depend on
+        reachable.clear();
+        reachable.resize(bound, false);
         for dest in &self.dests {
-            visit.move_to(dest.0);
-
-            while let Some(ix) = visit.next(reversed) {
-                let mut curr = self.graph[ix].node.borrow_mut();
-
-                let mut chunk = Chunk::default();
-                chunk
-                    .blocks
-                    .resize(curr.input_count() as usize, Default::default());
-
-                // if we have inputs, collect all the computed blocks
-                // and construct a Chunk
-
-                // set up scratch space to store all the blocks
-                blocks.clear();
-                blocks.resize(curr.input_count() as usize, Default::default());
-
-                let mode = curr.channel_count_mode();
-                let count = curr.channel_count();
-                let interpretation = curr.channel_interpretation();
-
-                // all edges to this node are from its dependencies
-                for edge in self.graph.edges_directed(ix, Direction::Incoming) {
-                    let edge = edge.weight();
-                    for connection in &edge.connections {
-                        let mut block = connection
-                            .cache
-                            .borrow_mut()
-                            .take()
-                            .expect("Cache should have been filled from traversal");
-
-                        match connection.input_idx {
-                            PortIndex::Port(idx) => {
-                                blocks[idx as usize].push(block);
-                            },
-                            PortIndex::Param(param) => {
-                                // param inputs are downmixed to mono
-                                // https://webaudio.github.io/web-audio-api/#dom-audionode-connect-destinationparam-output
-                                block.mix(1, ChannelInterpretation::Speakers);
-                                curr.get_param(param).add_block(block)
-                            },
-                            PortIndex::Listener(_) => curr.set_listenerdata(block),
-                        }
-                    }
+            let ix = self.index(*dest);
+            if !mem::replace(&mut reachable[ix.index()], true) {
+                stack.push(ix);
+            }
+        }
+        while let Some(ix) = stack.pop() {
+            for pred in self.graph.neighbors_directed(ix, Direction::Incoming) {
+                if !mem::replace(&mut reachable[pred.index()], true) {
+                    stack.push(pred);
                 }
+            }
+        }
 
-                for (i, mut blocks) in blocks.drain(..).enumerate() {
-                    if blocks.len() == 0 {
-                        if mode == ChannelCountMode::Explicit {
-                            // It's silence, but mix it anyway
-                            chunk.blocks[i].mix(count, interpretation);
-                        }
-                    } else if blocks.len() == 1 {
-                        chunk.blocks[i] = blocks.pop().expect("`blocks` had length 1");
-                        match mode {
-                            ChannelCountMode::Explicit => {
-                                chunk.blocks[i].mix(count, interpretation);
-                            },
-                            ChannelCountMode::ClampedMax => {
-                                if chunk.blocks[i].chan_count() > count {
-                                    chunk.blocks[i].mix(count, interpretation);
-                                }
-                            },
-                            // It's one channel, it maxes itself
-                            ChannelCountMode::Max => (),
-                        }
-                    } else {
-                        let mix_count = match mode {
-                            ChannelCountMode::Explicit => count,
-                            _ => {
-                                let mut max = 0; // max channel count
-                                for block in &blocks {
-                                    max = cmp::max(max, block.chan_count());
-                                }
-                                if mode == ChannelCountMode::ClampedMax {
-                                    max = cmp::min(max, count);
-                                }
-                                max
-                            },
-                        };
-                        let block = blocks.into_iter().fold(Block::default(), |acc, mut block| {
-                            block.mix(mix_count, interpretation);
-                            acc.sum(block)
-                        });
-                        chunk.blocks[i] = block;
-                    }
+        // Find the cycle-breaking nodes which are actually part of a cycle
+        edges.clear();
+        for ix in self.graph.node_indices() {
+            for target in self.graph.neighbors(ix) {
+                edges.add(ix.index(), target.index());
+            }
+        }
+        edges.build(bound);
+        sccs.run(edges);
+        split.clear();
+        split.resize(bound, false);
+        for scc in sccs.components() {
+            if !edges.is_cyclic(scc) {
+                continue;
+            }
+            for &i in scc {
+                let ix = NodeIndex::new(i);
+                if reachable[i] && self.graph[ix].node.borrow().breaks_cycles() {
+                    split[i] = true;
                 }
+            }
+        }
 
-                // actually run the node engine
-                let mut out = curr.process(chunk, info);
-
-                assert_eq!(out.len(), curr.output_count() as usize);
-                if curr.output_count() == 0 {
+        // Build a graph of processing steps, where split nodes have their
+        // output and input handled by separate steps, the output first.
+        // Param inputs are needed to compute the output, so they stay
+        // with the output step.
+        steps.clear();
+        first_step.clear();
+        first_step.resize(bound, 0);
+        for ix in self.graph.node_indices() {
+            if !reachable[ix.index()] {
+                continue;
+            }
+            first_step[ix.index()] = steps.len();
+            if split[ix.index()] {
+                steps.extend([Step::Read(ix), Step::Write(ix)]);
+            } else {
+                steps.push(Step::Process(ix));
+            }
+        }
+        edges.clear();
+        for ix in self.graph.node_indices() {
+            if !reachable[ix.index()] {
+                continue;
+            }
+            let from = first_step[ix.index()];
+            for edge in self.graph.edges(ix) {
+                let target = edge.target().index();
+                if !reachable[target] {
                     continue;
                 }
+                for connection in &edge.weight().connections {
+                    let to = match connection.input_idx {
+                        PortIndex::Port(_) if split[target] => first_step[target] + 1,
+                        _ => first_step[target],
+                    };
+                    edges.add(from, to);
+                }
+            }
+        }
+        edges.build(steps.len());
 
-                // Count how many output connections fan out from each port
-                // This is so that we don't have to needlessly clone audio buffers
-                //
-                // If this is inefficient, we can instead maintain this data
-                // cached on the node
-                output_counts.clear();
-                output_counts.resize(curr.output_count() as usize, 0);
-                for edge in self.graph.edges(ix) {
-                    let edge = edge.weight();
-                    for conn in &edge.connections {
-                        if let PortIndex::Port(idx) = conn.output_idx {
-                            output_counts[idx as usize] += 1;
-                        } else {
-                            unreachable!()
-                        }
-                    }
+        // The strongly connected components come out in reverse
+        // topological order. Anything still in a cycle gets muted, and
+        // kept in the same branch.
+        sccs.run(edges);
+        let scc_count = sccs.len();
+        scc_of.clear();
+        scc_of.resize(steps.len(), 0);
+        for (i, scc) in sccs.components().rev().enumerate() {
+            for &step in scc {
+                scc_of[step] = i;
+            }
+        }
+        preds.clear();
+        for step in 0..steps.len() {
+            for &target in edges.targets(step) {
+                let (from, to) = (scc_of[step], scc_of[target]);
+                if from != to {
+                    preds.add(to, from);
                 }
+            }
+        }
+        preds.build(scc_count);
+        succ_counts.clear();
+        succ_counts.resize(scc_count, 0);
+        for scc in 0..scc_count {
+            for &pred in preds.targets(scc) {
+                succ_counts[pred] += 1;
+            }
+        }
 
-                // all the edges from this node go to nodes which depend on it,
-                // i.e. the nodes it outputs to. Store the blocks for retrieval.
-                for edge in self.graph.edges(ix) {
-                    let edge = edge.weight();
-                    for conn in &edge.connections {
-                        if let PortIndex::Port(idx) = conn.output_idx {
-                            output_counts[idx as usize] -= 1;
-                            // if there are no consumers left after this, take the data
-                            let block = if output_counts[idx as usize] == 0 {
-                                out[conn.output_idx].take()
-                            } else {
-                                out[conn.output_idx].clone()
-                            };
-                            *conn.cache.borrow_mut() = Some(block);
-                        } else {
-                            unreachable!()
+        // Extend the branch of the only step a step depends on, when
+        // nothing else depends on that one. Otherwise, start a branch
+        // in the stage following those of its dependencies.
+        branch_of.clear();
+        stage_of.clear();
+        placed.clear();
+        for (i, scc) in sccs.components().rev().enumerate() {
+            let branch = match *preds.targets(i) {
+                [pred] if succ_counts[pred] == 1 => branch_of[pred],
+                ref preds => {
+                    let stage = preds
+                        .iter()
+                        .map(|&pred| stage_of[branch_of[pred]] + 1)
+                        .max()
+                        .unwrap_or(0);
+                    stage_of.push(stage);
+                    stage_of.len() - 1
+                },
+            };
+            branch_of.push(branch);
+            let cyclic = edges.is_cyclic(scc);
+            for &step in scc {
+                let step = match steps[step] {
+                    step if cyclic => Step::Mute(step.node()),
+                    step => step,
+                };
+                placed.push((stage_of[branch], branch, placed.len(), step));
+            }
+        }
+
+        // Branches were created in topological order, and their steps
+        // added in it, which sorting by stage preserves
+        placed.sort_unstable_by_key(|&(stage, branch, i, _)| (stage, branch, i));
+        let schedule = &mut self.order;
+        schedule.steps.clear();
+        schedule.branches.clear();
+        schedule.stages.clear();
+        for (i, &(stage, branch, _, step)) in placed.iter().enumerate() {
+            if i == 0 || placed[i - 1].0 != stage {
+                let next = schedule.branches.len();
+                schedule.stages.push(next..next);
+            }
+            if i == 0 || placed[i - 1].1 != branch {
+                schedule.branches.push(i..i);
+                schedule.stages.last_mut().unwrap().end += 1;
+            }
+            schedule.branches.last_mut().unwrap().end += 1;
+            schedule.steps.push(step);
+        }
+        self.order_stale = false;
+        self.scratch = scratch;
+    }
+
+    /// For a given block, process all the data on this graph
+    pub fn process(&mut self, info: &BlockInfo) -> Chunk {
+        self.disposer.flush();
+        if self.order_stale {
+            self.compute_order();
+        }
+        let schedule = mem::take(&mut self.order);
+        if let Some(ref mut timings) = self.timings {
+            for step in &schedule.steps {
+                timings.entry(step.node()).or_default();
+            }
+        }
+
+        match self.workers {
+            Some(ref workers) => {
+                let graph = SharedGraph(self);
+                for stage in &schedule.stages {
+                    let branches = &schedule.branches[stage.clone()];
+                    workers.run(branches.len(), |i| {
+                        for &step in &schedule.steps[branches[i].clone()] {
+                            graph.process_step(step, info);
                         }
-                    }
+                    });
                 }
-            }
+            },
+            None => {
+                for &step in &schedule.steps {
+                    self.process_step(step, info);
+                }
+            },
         }
+        self.order = schedule;
+        self.ungate();
+        self.drop_finished_nodes(info.next_frame());
+
         // The destination node stores its output on itself, extract it.
-        self.graph[self.dest_id.0]
+        self.graph[self.index(self.dest_id)]
             .node
             .borrow_mut()
             .destination_data()
             .expect("Destination node should have data cached")
     }
 
+    /// Process a single step, only touching the node it is about and
+    /// the connections from and to it
+    fn process_step(&self, step: Step, info: &BlockInfo) {
+        let start = self.timings.is_some().then(Instant::now);
+        let mut idle = false;
+        match step {
+            Step::Process(ix) => {
+                let mut curr = self.graph[ix].node.borrow_mut();
+                let chunk = self.gather_inputs(ix, &mut **curr, Gather::All, info);
+                idle = self.skip_idle && self.is_idle(ix, &mut **curr, &chunk, info.frame);
+                let out = if idle {
+                    let mut out = Chunk::default();
+                    out.blocks.resize(
+                        curr.output_count() as usize,
+                        Block::silence(info.frames_per_block),
+                    );
+                    out
+                } else {
+                    // actually run the node engine
+                    let out = curr.process(chunk, info);
+                    let node = &self.graph[ix];
+                    if node.silent_since.get().is_some() {
+                        node.quiet
+                            .set(out.blocks.iter().all(|b| b.is_quieter_than(INAUDIBLE)));
+                    }
+                    out
+                };
+                self.scatter_outputs(ix, &**curr, out);
+            },
+            Step::Read(ix) => {
+                let mut curr = self.graph[ix].node.borrow_mut();
+                self.gather_inputs(ix, &mut **curr, Gather::Params, info);
+                let out = curr.read_output(info);
+                self.scatter_outputs(ix, &**curr, out);
+            },
+            Step::Write(ix) => {
+                let mut curr = self.graph[ix].node.borrow_mut();
+                let chunk = self.gather_inputs(ix, &mut **curr, Gather::Ports, info);
+                curr.write_input(chunk, info);
+            },
+            Step::Mute(ix) => {
+                let curr = self.graph[ix].node.borrow();
+                let mut out = Chunk::default();
+                out.blocks.resize(
+                    curr.output_count() as usize,
+                    Block::silence(info.frames_per_block),
+                );
+                self.scatter_outputs(ix, &**curr, out);
+            },
+        }
+        // Skipped nodes aren't accounted for
+        if let (Some(start), Some(timings)) = (start, self.timings.as_ref())
+            && !idle
+        {
+            let timing = &timings[&step.node()];
+            let mut recorded = timing.get();
+            recorded.record(start.elapsed(), !matches!(step, Step::Write(_)));
+            timing.set(recorded);
+        }
+    }
+
+    /// Whether processing a node can be skipped, as it would only output
+    /// silence: its inputs are silent, it has finished producing sound
+    /// on its own, its tail time has elapsed since its input went silent
+    /// and its params aren't changing
+    ///
+    /// https://webaudio.github.io/web-audio-api/#actively-processing
+    fn is_idle(
+        &self,
+        ix: NodeIndex<DefaultIx>,
+        curr: &mut dyn AudioNodeEngine,
+        inputs: &Chunk,
+        frame: Tick,
+    ) -> bool {
+        let node = &self.graph[ix];
+        if !inputs.blocks.iter().all(|b| b.is_silence()) {
+            node.silent_since.set(None);
+            node.quiet.set(false);
+            return false;
+        }
+        let silent_since = *node.silent_since.get().get_or_insert(frame);
+        node.silent_since.set(Some(silent_since));
+
+        if !node.quiet.get()
+            || !curr.has_finished(frame)
+            || frame < silent_since + curr.tail_time()
+            || node.id == self.listener_id
+            || self.dests.contains(&node.id)
+        {
+            return false;
+        }
+        let has_param_inputs = self
+            .graph
+            .edges_directed(ix, Direction::Incoming)
+            .flat_map(|edge| edge.weight().connections.iter())
+            .any(|c| matches!(c.input_idx, PortIndex::Param(_)));
+        !has_param_inputs && !curr.has_automation()
+    }
+
+    /// Collect the blocks computed for the inputs of a node, and
+    /// construct its input Chunk, up/down-mixing as necessary
+    ///
+    /// Param and listener inputs are handed directly to the node
+    fn gather_inputs(
+        &self,
+        ix: NodeIndex<DefaultIx>,
+        curr: &mut dyn AudioNodeEngine,
+        gather: Gather,
+        info: &BlockInfo,
+    ) -> Chunk {
+        let mut chunk = Chunk::default();
+        chunk.blocks.resize(
+            curr.input_count() as usize,
+            Block::silence(info.frames_per_block),
+        );
+
+        // set up scratch space to store all the blocks
+        let mut blocks: SmallVec<[SmallVec<[Block; 1]>; 1]> = SmallVec::new();
+        blocks.resize(curr.input_count() as usize, Default::default());
+
+        let mode = curr.channel_count_mode();
+        let count = curr.channel_count();
+        let interpretation = curr.channel_interpretation();
+
+        // all edges to this node are from its dependencies
+        for edge in self.graph.edges_directed(ix, Direction::Incoming) {
+            let edge = edge.weight();
+            for connection in &edge.connections {
+                let is_port = matches!(connection.input_idx, PortIndex::Port(_));
+                match gather {
+                    Gather::Ports if !is_port => continue,
+                    Gather::Params if is_port => continue,
+                    _ => (),
+                }
+                let mut block = connection
+                    .cache
+                    .borrow_mut()
+                    .take()
+                    .expect("Cache should have been filled from traversal");
+                if connection.frames != (0..info.frames_per_block) {
+                    block.silence_outside(connection.frames.clone());
+                }
+
+                match connection.input_idx {
+                    PortIndex::Port(idx) => {
+                        blocks[idx as usize].push(block);
+                    },
+                    PortIndex::Param(param) => {
+                        // param inputs are downmixed to mono
+                        // https://webaudio.github.io/web-audio-api/#dom-audionode-connect-destinationparam-output
+                        block.mix(1, ChannelInterpretation::Speakers);
+                        curr.get_param(param).add_block(block)
+                    },
+                    PortIndex::Listener(_) => curr.set_listenerdata(block),
+                }
+            }
+        }
+
+        if let Gather::Params = gather {
+            return chunk;
+        }
+
+        for (i, mut blocks) in blocks.drain(..).enumerate() {
+            if blocks.len() == 0 {
+                if mode == ChannelCountMode::Explicit {
+                    // It's silence, but mix it anyway
+                    chunk.blocks[i].mix(count, interpretation);
+                }
+            } else if blocks.len() == 1 {
+                chunk.blocks[i] = blocks.pop().expect("`blocks` had length 1");
+                match mode {
+                    ChannelCountMode::Explicit => {
+                        chunk.blocks[i].mix(count, interpretation);
+                    },
+                    ChannelCountMode::ClampedMax => {
+                        if chunk.blocks[i].chan_count() > count {
+                            chunk.blocks[i].mix(count, interpretation);
+                        }
+                    },
+                    // It's one channel, it maxes itself
+                    ChannelCountMode::Max => (),
+                }
+            } else {
+                let mix_count = match mode {
+                    ChannelCountMode::Explicit => count,
+                    _ => {
+                        let mut max = 0; // max channel count
+                        for block in &blocks {
+                            max = cmp::max(max, block.chan_count());
+                        }
+                        if mode == ChannelCountMode::ClampedMax {
+                            max = cmp::min(max, count);
+                        }
+                        max
+                    },
+                };
+                let block = blocks.into_iter().fold(
+                    Block::silence(info.frames_per_block),
+                    |acc, mut block| {
+                        block.mix(mix_count, interpretation);
+                        acc.sum(block)
+                    },
+                );
+                chunk.blocks[i] = block;
+            }
+        }
+        chunk
+    }
+
+    /// Store the output blocks of a node in the connections to the
+    /// nodes which depend on it
+    fn scatter_outputs(
+        &self,
+        ix: NodeIndex<DefaultIx>,
+        curr: &dyn AudioNodeEngine,
+        mut out: Chunk,
+    ) {
+        assert_eq!(out.len(), curr.output_count() as usize);
+        if let Some(lifetime) = self.released.get(&ix)
+            && out.blocks.iter().any(|b| !b.is_quieter_than(INAUDIBLE))
+        {
+            lifetime.audible.set(true);
+        }
+        if curr.output_count() == 0 {
+            return;
+        }
+
+        // Count how many output connections fan out from each port
+        // This is so that we don't have to needlessly clone audio buffers
+        //
+        // If this is inefficient, we can instead maintain this data
+        // cached on the node
+        let mut output_counts: SmallVec<[u32; 1]> = SmallVec::new();
+        output_counts.resize(curr.output_count() as usize, 0);
+        for edge in self.graph.edges(ix) {
+            let edge = edge.weight();
+            for conn in &edge.connections {
+                if let PortIndex::Port(idx) = conn.output_idx {
+                    output_counts[idx as usize] += 1;
+                } else {
+                    unreachable!()
+                }
+            }
+        }
+
+        // all the edges from this node go to nodes which depend on it,
+        // i.e. the nodes it outputs to. Store the blocks for retrieval.
+        for edge in self.graph.edges(ix) {
+            let edge = edge.weight();
+            for conn in &edge.connections {
+                if let PortIndex::Port(idx) = conn.output_idx {
+                    output_counts[idx as usize] -= 1;
+                    // if there are no consumers left after this, take the data
+                    let block = if output_counts[idx as usize] == 0 {
+                        out[conn.output_idx].take()
+                    } else {
+                        out[conn.output_idx].clone()
+                    };
+                    *conn.cache.borrow_mut() = Some(block);
+                } else {
+                    unreachable!()
+                }
+            }
+        }
+    }
+
+    /// Pass a message to a node, freeing what it replaces off the
+    /// render thread
+    pub(crate) fn message_node(&mut self, id: NodeId, msg: AudioNodeMessage, sample_rate: f32) {
+        let garbage = {
+            let mut node = self.node_mut(id);
+            node.message(msg, sample_rate);
+            node.take_garbage()
+        };
+        if let Some(garbage) = garbage {
+            self.disposer.dispose(garbage);
+        }
+    }
+
+    /// Free memory off the render thread
+    pub(crate) fn dispose(&mut self, garbage: Garbage) {
+        self.disposer.dispose(garbage);
+    }
+
     /// Obtain a mutable reference to a node
     pub(crate) fn node_mut(&self, ix: NodeId) -> RefMut<'_, Box<dyn AudioNodeEngine>> {
-        self.graph[ix.0].node.borrow_mut()
+        self.graph[self.index(ix)].node.borrow_mut()
+    }
+
+    /// Let the graph drop a node once it has no inputs, has finished and
+    /// its output can't be heard anymore. The node must not be referred
+    /// to by the control thread afterwards.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#lifetime-AudioNode
+    pub fn release_node(&mut self, node: NodeId) {
+        if node == self.listener_id || self.dests.contains(&node) {
+            return;
+        }
+        self.released.insert(self.index(node), Lifetime::default());
+    }
+
+    /// Drop the released nodes which can't be heard anymore, given the
+    /// frame the next block starts at
+    fn drop_finished_nodes(&mut self, frame: Tick) {
+        let mut finished = mem::take(&mut self.finished);
+        for (&ix, lifetime) in self.released.iter_mut() {
+            let audible = lifetime.audible.replace(false);
+            if self
+                .graph
+                .edges_directed(ix, Direction::Incoming)
+                .next()
+                .is_some()
+            {
+                lifetime.inputless_since = None;
+                continue;
+            }
+            let inputless_since = *lifetime.inputless_since.get_or_insert(frame);
+            let node = self.graph[ix].node.borrow();
+            if !audible && node.has_finished(frame) && frame >= inputless_since + node.tail_time() {
+                finished.push(ix);
+            }
+        }
+        for ix in finished.drain(..) {
+            self.released.remove(&ix);
+            if let Some(ref mut timings) = self.timings {
+                timings.remove(&ix);
+            }
+            if let Some(node) = self.graph.remove_node(ix) {
+                self.indices.remove(&node.id);
+                self.disposer
+                    .dispose(Garbage::Engine(node.node.into_inner()));
+            }
+            self.order_stale = true;
+        }
+        self.finished = finished;
+    }
+
+    /// Start or stop recording the time spent processing each node.
+    /// Stopping discards what was recorded.
+    pub(crate) fn set_profiling(&mut self, enabled: bool) {
+        if enabled != self.timings.is_some() {
+            self.timings = enabled.then(HashMap::new);
+        }
+    }
+
+    /// The time spent processing each node since profiling started, from
+    /// the most expensive node on
+    pub fn node_profiles(&self) -> Vec<NodeProfile> {
+        let Some(ref timings) = self.timings else {
+            return Vec::new();
+        };
+        let mut profiles: Vec<_> = timings
+            .iter()
+            .map(|(&ix, timing)| NodeProfile {
+                id: self.graph[ix].id,
+                node_type: self.graph[ix].node.borrow().node_type(),
+                blocks: timing.get().blocks,
+                total_time: timing.get().total_time,
+                peak_time: timing.get().peak_time,
+            })
+            .collect();
+        profiles.sort_by_key(|profile| cmp::Reverse(profile.total_time));
+        profiles
+    }
+
+    /// Describe every node and connection of the graph as it is now
+    pub fn snapshot(&mut self) -> GraphSnapshot {
+        if self.order_stale {
+            self.compute_order();
+        }
+        let active: HashSet<_> = self
+            .order
+            .steps
+            .iter()
+            .filter(|step| !matches!(step, Step::Mute(_)))
+            .map(|step| step.node())
+            .collect();
+
+        let nodes = self
+            .graph
+            .node_indices()
+            .map(|ix| {
+                let mut node = self.graph[ix].node.borrow_mut();
+                let params = node
+                    .param_types()
+                    .to_vec()
+                    .into_iter()
+                    .map(|param| (param, node.get_param(param).value()))
+                    .collect();
+                NodeSnapshot {
+                    id: self.graph[ix].id,
+                    node_type: node.node_type(),
+                    channel_count: node.channel_count(),
+                    channel_count_mode: node.channel_count_mode(),
+                    channel_interpretation: node.channel_interpretation(),
+                    input_count: node.input_count(),
+                    output_count: node.output_count(),
+                    params,
+                    active: active.contains(&ix),
+                }
+            })
+            .collect();
+
+        let connections = self
+            .graph
+            .edge_references()
+            .flat_map(|edge| {
+                let (from, to) = (self.graph[edge.source()].id, self.graph[edge.target()].id);
+                edge.weight()
+                    .connections
+                    .iter()
+                    .map(move |c| (PortId(from, c.output_idx), PortId(to, c.input_idx)))
+            })
+            .collect();
+
+        GraphSnapshot { nodes, connections }
+    }
+}
+
+/// The state of a node at the time of a `GraphSnapshot`
+#[derive(Clone, Debug)]
+pub struct NodeSnapshot {
+    pub id: NodeId,
+    pub node_type: AudioNodeType,
+    pub channel_count: u8,
+    pub channel_count_mode: ChannelCountMode,
+    pub channel_interpretation: ChannelInterpretation,
+    pub input_count: u32,
+    pub output_count: u32,
+    /// The current value of each param
+    pub params: Vec<(ParamType, f32)>,
+    /// Whether the node gets processed, i.e. it is connected to a
+    /// destination and not muted as part of a cycle
+    pub active: bool,
+}
+
+/// The nodes and connections of an audio graph
+#[derive(Clone, Debug)]
+pub struct GraphSnapshot {
+    pub nodes: Vec<NodeSnapshot>,
+    pub connections: Vec<(PortId<OutputPort>, PortId<InputPort>)>,
+}
+
+impl GraphSnapshot {
+    /// Describe the graph in the Graphviz DOT language. Inactive nodes
+    /// are dashed, as are connections to params and to the listener.
+    ///
+    /// https://graphviz.org/doc/info/lang.html
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph AudioGraph {\n    node [shape=record];\n");
+        for node in &self.nodes {
+            let mut label = format!(
+                "{:?} #{}|{} ch, {:?}, {:?}|{} in, {} out",
+                node.node_type,
+                node.id.0,
+                node.channel_count,
+                node.channel_count_mode,
+                node.channel_interpretation,
+                node.input_count,
+                node.output_count,
+            );
+            if !node.params.is_empty() {
+                label.push('|');
+                for (param, value) in &node.params {
+                    let _ = write!(label, "{:?}: {}\\l", param, value);
+                }
+            }
+            let style = if node.active { "solid" } else { "dashed" };
+            let _ = writeln!(
+                dot,
+                "    n{} [label=\"{{{}}}\", style={}];",
+                node.id.0, label, style
+            );
+        }
+        for (from, to) in &self.connections {
+            let output = match from.1 {
+                PortIndex::Port(port) => port,
+                PortIndex::Param(void) | PortIndex::Listener(void) => match void {},
+            };
+            let (input, style) = match to.1 {
+                PortIndex::Port(port) => (port.to_string(), "solid"),
+                PortIndex::Param(param) => (format!("{:?}", param), "dashed"),
+                PortIndex::Listener(()) => ("listener".to_owned(), "dashed"),
+            };
+            let _ = writeln!(
+                dot,
+                "    n{} -> n{} [label=\"{} -> {}\", style={}];",
+                from.node().0,
+                to.node().0,
+                output,
+                input,
+                style
+            );
+        }
+        dot.push_str("}\n");
+        dot
     }
 }
 
 impl Node {
-    pub fn new(node: Box<dyn AudioNodeEngine>) -> Self {
+    pub fn new(id: NodeId, node: Box<dyn AudioNodeEngine>) -> Self {
         Node {
+            id,
             node: RefCell::new(node),
+            silent_since: Cell::new(None),
+            quiet: Cell::new(false),
         }
     }
 }
 
 impl Edge {
-    pub fn new(input_idx: PortIndex<InputPort>, output_idx: PortIndex<OutputPort>) -> Self {
+    fn new(connection: Connection) -> Self {
         Edge {
-            connections: SmallVec::from_buf([Connection::new(input_idx, output_idx)]),
+            connections: SmallVec::from_buf([connection]),
         }
     }
 }
 
 impl Connection {
-    pub fn new(input_idx: PortIndex<InputPort>, output_idx: PortIndex<OutputPort>) -> Self {
+    pub fn new(
+        input_idx: PortIndex<InputPort>,
+        output_idx: PortIndex<OutputPort>,
+        frames: Range<usize>,
+    ) -> Self {
         Connection {
             input_idx,
             output_idx,
             cache: RefCell::new(None),
+            frames,
+            closing: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delay_node::{DelayNode, DelayNodeOptions};
+    use crate::dynamics_compressor_node::DynamicsCompressorNode;
+    use crate::gain_node::{GainNode, GainNodeOptions};
+    use crate::node::{AudioScheduledSourceNodeMessage, ChannelInfo};
+    use crate::oscillator_node::{OscillatorNode, OscillatorNodeOptions};
+    use crate::panner_node::{PannerNode, PannerNodeOptions, PanningModel};
+    use crate::param::{RampKind, UserAutomationEvent};
+    use crate::worklet_node::{AudioWorkletNode, AudioWorkletNodeOptions, AudioWorkletProcessor};
+
+    const SAMPLE_RATE: f32 = 44100.;
+    const FRAMES: usize = 128;
+
+    /// Ids of the nodes added by `build`
+    struct Nodes {
+        oscillators: Vec<NodeId>,
+        gains: Vec<NodeId>,
+        delay: NodeId,
+    }
+
+    /// Build a graph with sources that stop at different times, a node
+    /// with a tail, and automation running after the sources stopped:
+    ///
+    /// osc0 -> gain0 -> delay -> dest
+    /// osc1 -> gain1 -> dest, and gain1 -> gain0
+    /// osc2 -> gain2.gain
+    fn build(graph: &mut AudioGraph) -> Nodes {
+        let mut next_id = graph.listener_id();
+        let mut add = |graph: &mut AudioGraph, node: Box<dyn AudioNodeEngine>| {
+            next_id = next_id.next();
+            graph.add_node(next_id, node);
+            next_id
+        };
+        let oscillators: Vec<_> = [220., 330., 5.]
+            .into_iter()
+            .map(|freq| {
+                let options = OscillatorNodeOptions {
+                    freq,
+                    ..Default::default()
+                };
+                add(
+                    graph,
+                    Box::new(OscillatorNode::new(options, ChannelInfo::default())),
+                )
+            })
+            .collect();
+        let gains: Vec<_> = (0..3)
+            .map(|_| {
+                let options = GainNodeOptions { gain: 0.5 };
+                add(
+                    graph,
+                    Box::new(GainNode::new(options, ChannelInfo::default(), FRAMES)),
+                )
+            })
+            .collect();
+        let options = DelayNodeOptions {
+            max_delay_time: 0.05,
+            delay_time: 0.03,
+        };
+        let delay = add(
+            graph,
+            Box::new(DelayNode::new(
+                options,
+                ChannelInfo::default(),
+                SAMPLE_RATE,
+                FRAMES,
+            )),
+        );
+
+        let dest = graph.dest_id();
+        graph.add_edge(oscillators[0].output(0), gains[0].input(0));
+        graph.add_edge(gains[0].output(0), delay.input(0));
+        graph.add_edge(delay.output(0), dest.input(0));
+        graph.add_edge(oscillators[1].output(0), gains[1].input(0));
+        graph.add_edge(gains[1].output(0), dest.input(0));
+        graph.add_edge(gains[1].output(0), gains[0].input(0));
+        graph.add_edge(oscillators[2].output(0), gains[2].param(ParamType::Gain));
+        graph.add_edge(gains[2].output(0), dest.input(0));
+
+        let block = FRAMES as f64 / SAMPLE_RATE as f64;
+        for (&osc, stop) in oscillators.iter().zip([4.5, 9., 20.]) {
+            for msg in [
+                AudioScheduledSourceNodeMessage::Start(0.),
+                AudioScheduledSourceNodeMessage::Stop(stop * block),
+            ] {
+                graph.message_node(
+                    osc,
+                    AudioNodeMessage::AudioScheduledSourceNode(msg),
+                    SAMPLE_RATE,
+                );
+            }
+        }
+        // automation running long after everything went silent
+        graph.message_node(
+            gains[1],
+            AudioNodeMessage::SetParam(
+                ParamType::Gain,
+                UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 1., 40. * block),
+            ),
+            SAMPLE_RATE,
+        );
+        Nodes {
+            oscillators,
+            gains,
+            delay,
+        }
+    }
+
+    fn render(graph: &mut AudioGraph, blocks: usize) -> Vec<f32> {
+        let mut output = Vec::new();
+        for block in 0..blocks {
+            let frame = (block * FRAMES) as u64;
+            let info = BlockInfo {
+                sample_rate: SAMPLE_RATE,
+                frame: Tick(frame),
+                time: frame as f64 / SAMPLE_RATE as f64,
+                frames_per_block: FRAMES,
+            };
+            let mut chunk = graph.process(&info);
+            let block = &mut chunk.blocks[0];
+            block.explicit_repeat();
+            output.extend_from_slice(block.data_mut());
+        }
+        output
+    }
+
+    fn blocks_processed(graph: &AudioGraph, id: NodeId) -> u64 {
+        graph
+            .node_profiles()
+            .into_iter()
+            .find(|profile| profile.id == id)
+            .map_or(0, |profile| profile.blocks)
+    }
+
+    #[test]
+    fn skipping_idle_nodes_keeps_the_output() {
+        const BLOCKS: usize = 64;
+        let mut reference = AudioGraph::new(2, FRAMES, 0);
+        reference.skip_idle = false;
+        build(&mut reference);
+        let expected = render(&mut reference, BLOCKS);
+
+        let mut graph = AudioGraph::new(2, FRAMES, 0);
+        graph.set_profiling(true);
+        let nodes = build(&mut graph);
+        let output = render(&mut graph, BLOCKS);
+        assert_eq!(output, expected);
+        assert!(expected.iter().any(|&s| s != 0.));
+
+        // Stopped sources, and nodes whose inputs went silent, were
+        // skipped. The delay was processed until its tail ended, and the
+        // automated gain as long as its automation ran. Nodes with param
+        // inputs are always processed.
+        for &osc in &nodes.oscillators {
+            assert!(blocks_processed(&graph, osc) < BLOCKS as u64);
+        }
+        assert!(blocks_processed(&graph, nodes.gains[0]) < blocks_processed(&graph, nodes.delay));
+        assert!(blocks_processed(&graph, nodes.delay) < BLOCKS as u64);
+        assert!(blocks_processed(&graph, nodes.gains[1]) >= 40);
+        assert_eq!(blocks_processed(&graph, nodes.gains[2]), BLOCKS as u64);
+    }
+
+    /// Build a graph with a burst of sound arriving after a silent
+    /// stretch, going through nodes that hold on to it for a while:
+    ///
+    /// osc -> compressor -> dest
+    /// osc -> panner (HRTF, with the hrtf feature) -> dest
+    fn build_with_tails(graph: &mut AudioGraph) -> [NodeId; 2] {
+        let osc = graph.listener_id().next();
+        let options = OscillatorNodeOptions::default();
+        graph.add_node(
+            osc,
+            Box::new(OscillatorNode::new(options, ChannelInfo::default())),
+        );
+        let compressor = osc.next();
+        graph.add_node(
+            compressor,
+            Box::new(DynamicsCompressorNode::new(
+                Default::default(),
+                ChannelInfo::default(),
+                SAMPLE_RATE,
+            )),
+        );
+        let panner = compressor.next();
+        let options = PannerNodeOptions {
+            panning_model: PanningModel::HRTF,
+            position_x: 1.,
+            ..Default::default()
+        };
+        graph.add_node(
+            panner,
+            Box::new(PannerNode::new(
+                options,
+                ChannelInfo::default(),
+                SAMPLE_RATE,
+                FRAMES,
+            )),
+        );
+        let dest = graph.dest_id();
+        graph.add_edge(osc.output(0), compressor.input(0));
+        graph.add_edge(compressor.output(0), dest.input(0));
+        graph.add_edge(osc.output(0), panner.input(0));
+        graph.add_edge(panner.output(0), dest.input(0));
+        graph.add_edge(graph.listener_id().output(0), panner.listener());
+
+        // Shorter than the compressor's look-ahead
+        let block = FRAMES as f64 / SAMPLE_RATE as f64;
+        for msg in [
+            AudioScheduledSourceNodeMessage::Start(10. * block),
+            AudioScheduledSourceNodeMessage::Stop(10.5 * block),
+        ] {
+            graph.message_node(
+                osc,
+                AudioNodeMessage::AudioScheduledSourceNode(msg),
+                SAMPLE_RATE,
+            );
+        }
+        [compressor, panner]
+    }
+
+    #[test]
+    fn skipping_idle_nodes_keeps_their_tail() {
+        const BLOCKS: usize = 32;
+        let mut reference = AudioGraph::new(2, FRAMES, 0);
+        reference.skip_idle = false;
+        build_with_tails(&mut reference);
+        let expected = render(&mut reference, BLOCKS);
+
+        let mut graph = AudioGraph::new(2, FRAMES, 0);
+        graph.set_profiling(true);
+        let nodes = build_with_tails(&mut graph);
+        let output = render(&mut graph, BLOCKS);
+        assert_eq!(output, expected);
+
+        // The burst comes out of both nodes after it has ended
+        assert!(expected[11 * FRAMES * 2..].iter().any(|&s| s != 0.));
+        for id in nodes {
+            assert!(blocks_processed(&graph, id) < BLOCKS as u64);
+        }
+    }
+
+    /// Outputs a constant for a number of blocks, then lets itself go
+    struct Countdown(usize);
+
+    impl AudioWorkletProcessor for Countdown {
+        fn process(
+            &mut self,
+            _: &[Block],
+            outputs: &mut [Block],
+            _: &[Vec<f32>],
+            _: &BlockInfo,
+        ) -> bool {
+            self.0 = self.0.saturating_sub(1);
+            if self.0 > 0 {
+                outputs[0].data_mut().fill(0.5);
+            }
+            self.0 > 0
         }
     }
+
+    #[test]
+    fn released_sources_are_removed_once_finished() {
+        let mut graph = AudioGraph::new(2, FRAMES, 0);
+        let osc = graph.listener_id().next();
+        let options = OscillatorNodeOptions::default();
+        graph.add_node(
+            osc,
+            Box::new(OscillatorNode::new(options, ChannelInfo::default())),
+        );
+        let worklet = osc.next();
+        let options = AudioWorkletNodeOptions::new(Box::new(Countdown(8)));
+        graph.add_node(
+            worklet,
+            Box::new(AudioWorkletNode::new(
+                options,
+                ChannelInfo::default(),
+                FRAMES,
+            )),
+        );
+        let dest = graph.dest_id();
+        graph.add_edge(osc.output(0), dest.input(0));
+        graph.add_edge(worklet.output(0), dest.input(0));
+        let block = FRAMES as f64 / SAMPLE_RATE as f64;
+        for msg in [
+            AudioScheduledSourceNodeMessage::Start(0.),
+            AudioScheduledSourceNodeMessage::Stop(4. * block),
+        ] {
+            graph.message_node(
+                osc,
+                AudioNodeMessage::AudioScheduledSourceNode(msg),
+                SAMPLE_RATE,
+            );
+        }
+        graph.release_node(osc);
+        graph.release_node(worklet);
+
+        render(&mut graph, 6);
+        assert!(!graph.indices.contains_key(&osc));
+        assert!(graph.indices.contains_key(&worklet));
+        render(&mut graph, 4);
+        assert!(!graph.indices.contains_key(&worklet));
+        assert_eq!(graph.graph.node_count(), 2);
+    }
+
+    /// Build a graph with fan-out, fan-in and a delay cycle, and
+    /// independent branches for workers to process:
+    ///
+    /// osc0 -> gain0, gain1
+    /// osc1 -> gain1, gain2
+    /// gain0, gain1, gain2 -> mix -> dest
+    /// mix -> delay -> feedback -> mix
+    fn build_with_cycle(graph: &mut AudioGraph) {
+        let mut next_id = graph.listener_id();
+        let mut add = |graph: &mut AudioGraph, node: Box<dyn AudioNodeEngine>| {
+            next_id = next_id.next();
+            graph.add_node(next_id, node);
+            next_id
+        };
+        let gain = |gain| {
+            let options = GainNodeOptions { gain };
+            Box::new(GainNode::new(options, ChannelInfo::default(), FRAMES))
+        };
+        let gains: Vec<_> = [0.3, 0.5, 0.7]
+            .into_iter()
+            .map(|g| add(graph, gain(g)))
+            .collect();
+        let mix = add(graph, gain(0.5));
+        let feedback = add(graph, gain(0.5));
+        let oscillators: Vec<_> = [220., 330.]
+            .into_iter()
+            .map(|freq| {
+                let options = OscillatorNodeOptions {
+                    freq,
+                    ..Default::default()
+                };
+                add(
+                    graph,
+                    Box::new(OscillatorNode::new(options, ChannelInfo::default())),
+                )
+            })
+            .collect();
+        let options = DelayNodeOptions {
+            max_delay_time: 0.05,
+            delay_time: 0.01,
+        };
+        let delay = add(
+            graph,
+            Box::new(DelayNode::new(
+                options,
+                ChannelInfo::default(),
+                SAMPLE_RATE,
+                FRAMES,
+            )),
+        );
+
+        graph.add_edge(oscillators[0].output(0), gains[0].input(0));
+        graph.add_edge(oscillators[0].output(0), gains[1].input(0));
+        graph.add_edge(oscillators[1].output(0), gains[1].input(0));
+        graph.add_edge(oscillators[1].output(0), gains[2].input(0));
+        for &gain in &gains {
+            graph.add_edge(gain.output(0), mix.input(0));
+        }
+        graph.add_edge(mix.output(0), graph.dest_id().input(0));
+        graph.add_edge(mix.output(0), delay.input(0));
+        graph.add_edge(delay.output(0), feedback.input(0));
+        graph.add_edge(feedback.output(0), mix.input(0));
+        for osc in oscillators {
+            graph.message_node(
+                osc,
+                AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(
+                    0.,
+                )),
+                SAMPLE_RATE,
+            );
+        }
+    }
+
+    #[test]
+    fn workers_keep_the_output() {
+        const BLOCKS: usize = 32;
+        let mut reference = AudioGraph::new(2, FRAMES, 0);
+        build_with_cycle(&mut reference);
+        let expected = render(&mut reference, BLOCKS);
+
+        // Skip the limit of one worker per spare core, which would leave
+        // none on a single core
+        let mut graph = AudioGraph::new(2, FRAMES, 0);
+        graph.workers = Some(WorkerPool::new(3));
+        build_with_cycle(&mut graph);
+        let output = render(&mut graph, BLOCKS);
+        let schedule = &graph.order;
+        assert!(schedule.stages.iter().any(|stage| stage.len() > 1));
+        assert!(expected.iter().any(|&s| s != 0.));
+        assert_eq!(output, expected);
+    }
+
+    /// The steps of each branch of each stage, by node id. Branches of a
+    /// stage are sorted, as their order doesn't matter.
+    fn describe(graph: &AudioGraph, schedule: &Schedule) -> Vec<Vec<Vec<(char, NodeId)>>> {
+        schedule
+            .stages
+            .iter()
+            .map(|stage| {
+                let mut branches: Vec<Vec<_>> = schedule.branches[stage.clone()]
+                    .iter()
+                    .map(|branch| {
+                        schedule.steps[branch.clone()]
+                            .iter()
+                            .map(|&step| {
+                                let kind = match step {
+                                    Step::Process(_) => 'p',
+                                    Step::Read(_) => 'r',
+                                    Step::Write(_) => 'w',
+                                    Step::Mute(_) => 'm',
+                                };
+                                (kind, graph.graph[step.node()].id)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                branches.sort();
+                branches
+            })
+            .collect()
+    }
+
+    #[test]
+    fn schedule_splits_delays_and_mutes_other_cycles() {
+        let mut graph = AudioGraph::new(2, FRAMES, 0);
+        build_with_cycle(&mut graph);
+        // osc -> loop0 <-> loop1 -> dest, with loop1 also feeding itself,
+        // and a node nothing hears
+        let gain = || {
+            let options = GainNodeOptions { gain: 0.5 };
+            Box::new(GainNode::new(options, ChannelInfo::default(), FRAMES))
+        };
+        let (loop0, loop1, unheard) = (NodeId(20), NodeId(21), NodeId(22));
+        for id in [loop0, loop1, unheard] {
+            graph.add_node(id, gain());
+        }
+        graph.add_edge(NodeId(7).output(0), loop0.input(0));
+        graph.add_edge(loop0.output(0), loop1.input(0));
+        graph.add_edge(loop1.output(0), loop0.param(ParamType::Gain));
+        graph.add_edge(loop1.output(0), loop1.input(0));
+        graph.add_edge(loop1.output(0), graph.dest_id().input(0));
+        graph.add_edge(NodeId(7).output(0), unheard.input(0));
+
+        graph.compute_order();
+        let schedule = &graph.order;
+        let step = |kind, id| (kind, NodeId(id));
+        assert_eq!(
+            describe(&graph, schedule),
+            [
+                // Sources, and the delayed feedback
+                vec![
+                    vec![step('p', 7)],
+                    vec![step('p', 8)],
+                    vec![step('r', 9), step('p', 6)],
+                ],
+                // Everything depending on a single source
+                vec![
+                    vec![step('m', 21), step('m', 20)],
+                    vec![step('p', 2)],
+                    vec![step('p', 3)],
+                    vec![step('p', 4)],
+                ],
+                vec![vec![step('p', 5)]],
+                vec![vec![step('p', 0)], vec![step('w', 9)]],
+            ]
+        );
+    }
 }
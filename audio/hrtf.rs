@@ -0,0 +1,460 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Measured HRIRs of subject 1003 of the IRCAM LISTEN database, diffuse-field
+/// compensated, in the "HRIR sphere" format: a header, a triangulation of
+/// the measured directions that isn't used here, then for every direction
+/// its position on the unit sphere and the left and right responses.
+/// See resources/README.md.
+///
+/// Only embedded with the `hrtf` feature, empty otherwise.
+#[cfg(feature = "hrtf")]
+static IRCAM_1003: &[u8] = include_bytes!("resources/IRC_1003_C.bin");
+#[cfg(not(feature = "hrtf"))]
+static IRCAM_1003: &[u8] = &[];
+
+/// The length of the HRIR kernels at the sample rate of the measurements, in frames
+const KERNEL_LEN: usize = 256;
+/// How many frames to keep before the onset of a response
+const PRE_ROLL: usize = 4;
+/// How many frames at the end of a kernel are faded out
+const FADE_OUT_LEN: usize = 32;
+/// The fraction of its peak at which a response is taken to start
+const ONSET_THRESHOLD: f32 = 0.3;
+/// The longest interaural delay looked for, in seconds
+const MAX_INTERAURAL_DELAY: f32 = 0.001;
+/// The half-width of the resampling filter, in zero crossings
+const RESAMPLE_ZEROS: f32 = 16.;
+
+/// The directions the database has HRIRs for, in degrees.
+/// Azimuths cover the full circle, elevations go from below
+/// the horizon to straight up. This is the grid of the LISTEN
+/// measurements, which are sparser above 45 degrees.
+const AZIMUTH_STEP: f32 = 15.;
+const AZIMUTH_COUNT: usize = 24;
+const ELEVATION_MIN: f32 = -45.;
+const ELEVATION_STEP: f32 = 15.;
+const ELEVATION_COUNT: usize = 10;
+
+/// The head-related impulse response for a single direction: a kernel for
+/// each ear, and the time taken for sound to reach each ear, in frames.
+///
+/// The delays are kept separate from the kernels so that HRIRs can be
+/// interpolated without comb filtering.
+#[derive(Clone)]
+pub(crate) struct Hrir {
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+    pub left_delay: f32,
+    pub right_delay: f32,
+}
+
+impl Hrir {
+    pub fn new(len: usize) -> Self {
+        Hrir {
+            left: vec![0.; len],
+            right: vec![0.; len],
+            left_delay: 0.,
+            right_delay: 0.,
+        }
+    }
+
+    /// Add `other` scaled by `weight`
+    fn accumulate(&mut self, other: &Hrir, weight: f32) {
+        for (o, h) in self.left.iter_mut().zip(&other.left) {
+            *o += h * weight;
+        }
+        for (o, h) in self.right.iter_mut().zip(&other.right) {
+            *o += h * weight;
+        }
+        self.left_delay += other.left_delay * weight;
+        self.right_delay += other.right_delay * weight;
+    }
+}
+
+/// A set of HRIRs on a grid of azimuths and elevations, at a given sample rate.
+///
+/// The HRIRs come from the measurements of the IRCAM LISTEN database. The
+/// onset of every measured response is taken out as a delay, the responses
+/// are resampled to the sample rate of the context, and the directions that
+/// weren't measured are interpolated along the azimuth.
+pub(crate) struct HrtfDatabase {
+    /// HRIRs for every elevation, then every azimuth
+    hrirs: Vec<Hrir>,
+    kernel_len: usize,
+    max_delay: f32,
+}
+
+impl HrtfDatabase {
+    /// The database for the given sample rate, loaded the first time it is
+    /// asked for and shared afterwards. Loading is slow, and has to happen
+    /// on the control thread. None if the crate was built without the
+    /// `hrtf` feature.
+    pub fn for_sample_rate(sample_rate: f32) -> Option<Arc<HrtfDatabase>> {
+        static DATABASES: Mutex<Vec<(u32, Arc<HrtfDatabase>)>> = Mutex::new(Vec::new());
+        if IRCAM_1003.is_empty() {
+            return None;
+        }
+        let mut databases = DATABASES.lock().unwrap();
+        let key = sample_rate.to_bits();
+        if let Some((_, database)) = databases.iter().find(|(k, _)| *k == key) {
+            return Some(database.clone());
+        }
+        let database = Arc::new(HrtfDatabase::new(sample_rate));
+        databases.push((key, database.clone()));
+        Some(database)
+    }
+
+    fn new(sample_rate: f32) -> Self {
+        let (measured_rate, measurements) = parse(IRCAM_1003);
+        let measurements: Vec<_> = measurements
+            .into_iter()
+            .map(|(azimuth, elevation, left, right)| {
+                (azimuth, elevation, extract(&left, &right, measured_rate))
+            })
+            .collect();
+
+        let mut hrirs = Vec::with_capacity(ELEVATION_COUNT * AZIMUTH_COUNT);
+        for e in 0..ELEVATION_COUNT {
+            let elevation = ELEVATION_MIN + e as f32 * ELEVATION_STEP;
+            // the measured azimuths at this elevation, in increasing order
+            let mut ring: Vec<_> = measurements
+                .iter()
+                .filter(|(_, el, _)| (el - elevation).abs() < 1.)
+                .map(|(az, _, hrir)| (*az, hrir))
+                .collect();
+            assert!(
+                !ring.is_empty(),
+                "no HRIR measured at elevation {}",
+                elevation
+            );
+            ring.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for a in 0..AZIMUTH_COUNT {
+                hrirs.push(interpolate_ring(&ring, a as f32 * AZIMUTH_STEP));
+            }
+        }
+
+        // Only the difference between the delays matters, drop the rest.
+        // Then scale the kernels to carry as much energy on average as the
+        // equal-power model, which has a total gain of one over both ears.
+        let min_delay = hrirs
+            .iter()
+            .map(|h| h.left_delay.min(h.right_delay))
+            .fold(f32::INFINITY, f32::min);
+        let energy: f32 = hrirs
+            .iter()
+            .flat_map(|h| h.left.iter().chain(&h.right))
+            .map(|s| s * s)
+            .sum();
+        let scale = (hrirs.len() as f32 / energy).sqrt();
+
+        let ratio = sample_rate / measured_rate;
+        let kernel_len = ((KERNEL_LEN as f32 * ratio).round() as usize).max(1);
+        let hrirs: Vec<_> = hrirs
+            .into_iter()
+            .map(|hrir| Hrir {
+                left: resample(&hrir.left, ratio, scale, kernel_len),
+                right: resample(&hrir.right, ratio, scale, kernel_len),
+                left_delay: (hrir.left_delay - min_delay) * ratio,
+                right_delay: (hrir.right_delay - min_delay) * ratio,
+            })
+            .collect();
+
+        let max_delay = hrirs
+            .iter()
+            .map(|h| h.left_delay.max(h.right_delay))
+            .fold(0., f32::max);
+        HrtfDatabase {
+            hrirs,
+            kernel_len,
+            max_delay,
+        }
+    }
+
+    /// The length of every HRIR kernel, in frames
+    pub fn kernel_len(&self) -> usize {
+        self.kernel_len
+    }
+
+    /// The longest delay of any HRIR, in frames
+    pub fn max_delay(&self) -> f32 {
+        self.max_delay
+    }
+
+    fn get(&self, azimuth: usize, elevation: usize) -> &Hrir {
+        &self.hrirs[elevation * AZIMUTH_COUNT + azimuth % AZIMUTH_COUNT]
+    }
+
+    /// Bilinearly interpolate the HRIR for the given direction, in degrees
+    /// (azimuth clockwise from the front, elevation up from the horizon)
+    pub fn interpolate(&self, azimuth: f32, elevation: f32, out: &mut Hrir) {
+        let azimuth = azimuth.rem_euclid(360.) / AZIMUTH_STEP;
+        let elevation = (elevation.clamp(ELEVATION_MIN, 90.) - ELEVATION_MIN) / ELEVATION_STEP;
+        let a = (azimuth.floor() as usize).min(AZIMUTH_COUNT - 1);
+        let e = (elevation.floor() as usize).min(ELEVATION_COUNT - 2);
+        let a_frac = azimuth - a as f32;
+        let e_frac = elevation - e as f32;
+
+        out.left.fill(0.);
+        out.right.fill(0.);
+        out.left_delay = 0.;
+        out.right_delay = 0.;
+        out.accumulate(self.get(a, e), (1. - a_frac) * (1. - e_frac));
+        out.accumulate(self.get(a + 1, e), a_frac * (1. - e_frac));
+        out.accumulate(self.get(a, e + 1), (1. - a_frac) * e_frac);
+        out.accumulate(self.get(a + 1, e + 1), a_frac * e_frac);
+    }
+}
+
+/// A measured direction, azimuth and elevation, with the left and right responses
+type Measurement = (f32, f32, Vec<f32>, Vec<f32>);
+
+/// Read an HRIR sphere: its sample rate, then the direction of every
+/// measurement in degrees (azimuth clockwise from the front, elevation up
+/// from the horizon) with its left and right responses
+fn parse(data: &[u8]) -> (f32, Vec<Measurement>) {
+    assert_eq!(&data[0..4], b"HRIR", "not an HRIR sphere");
+    let mut words = data[4..].chunks_exact(4).map(|w| [w[0], w[1], w[2], w[3]]);
+    let mut u32 = || u32::from_le_bytes(words.next().unwrap()) as usize;
+    let sample_rate = u32() as f32;
+    let len = u32();
+    let count = u32();
+    let index_count = u32();
+
+    let floats = &data[4 * (5 + index_count)..];
+    let mut floats = floats
+        .chunks_exact(4)
+        .map(|w| f32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+    let measurements = (0..count)
+        .map(|_| {
+            // right-handed, with y up and the front along -z
+            let (x, y, z) = (
+                floats.next().unwrap(),
+                floats.next().unwrap(),
+                floats.next().unwrap(),
+            );
+            let left: Vec<f32> = floats.by_ref().take(len).collect();
+            let right: Vec<f32> = floats.by_ref().take(len).collect();
+            assert_eq!(right.len(), len, "truncated HRIR sphere");
+            let azimuth = x.atan2(-z).to_degrees().rem_euclid(360.);
+            let elevation = y.clamp(-1., 1.).asin().to_degrees();
+            (azimuth, elevation, left, right)
+        })
+        .collect();
+    (sample_rate, measurements)
+}
+
+/// Split a measured pair of responses into delays and kernels
+fn extract(left: &[f32], right: &[f32], sample_rate: f32) -> Hrir {
+    // The onset of the ear facing the source is clear, the other ear is
+    // much quieter, so its onset is found from the interaural delay.
+    let energy = |h: &[f32]| h.iter().map(|s| s * s).sum::<f32>();
+    let left_is_near = energy(left) >= energy(right);
+    let (near, far) = if left_is_near {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    let peak = near.iter().fold(0., |m: f32, s| m.max(s.abs()));
+    let near_onset = near
+        .iter()
+        .position(|s| s.abs() >= ONSET_THRESHOLD * peak)
+        .unwrap_or(0);
+    let max_lag = (MAX_INTERAURAL_DELAY * sample_rate) as isize;
+    let far_onset = (near_onset as isize + interaural_delay(near, far, max_lag)).max(0) as usize;
+
+    let kernel = |response: &[f32], onset: usize| {
+        let start = onset.saturating_sub(PRE_ROLL);
+        let mut kernel = vec![0.; KERNEL_LEN];
+        let end = (start + KERNEL_LEN).min(response.len());
+        kernel[..end - start].copy_from_slice(&response[start..end]);
+        for (i, sample) in kernel[KERNEL_LEN - FADE_OUT_LEN..].iter_mut().enumerate() {
+            *sample *= 0.5 + 0.5 * (PI * (i + 1) as f32 / FADE_OUT_LEN as f32).cos();
+        }
+        (kernel, start as f32)
+    };
+    let (near, near_delay) = kernel(near, near_onset);
+    let (far, far_delay) = kernel(far, far_onset);
+    if left_is_near {
+        Hrir {
+            left: near,
+            right: far,
+            left_delay: near_delay,
+            right_delay: far_delay,
+        }
+    } else {
+        Hrir {
+            left: far,
+            right: near,
+            left_delay: far_delay,
+            right_delay: near_delay,
+        }
+    }
+}
+
+/// How many frames `far` lags behind `near`, the lag at which they
+/// correlate best, within `max_lag`
+fn interaural_delay(near: &[f32], far: &[f32], max_lag: isize) -> isize {
+    let correlation = |lag: isize| -> f32 {
+        near.iter()
+            .enumerate()
+            .filter_map(|(n, x)| {
+                let m = n as isize + lag;
+                (m >= 0 && (m as usize) < far.len()).then(|| x * far[m as usize])
+            })
+            .sum()
+    };
+    (-max_lag..=max_lag)
+        .map(|lag| (lag, correlation(lag)))
+        .fold(
+            (0, f32::NEG_INFINITY),
+            |best, c| if c.1 > best.1 { c } else { best },
+        )
+        .0
+}
+
+/// Resample an impulse response by `ratio` with a windowed sinc filter,
+/// keeping its frequency response, and scale it
+fn resample(input: &[f32], ratio: f32, scale: f32, len: usize) -> Vec<f32> {
+    if ratio == 1. {
+        return input.iter().map(|s| s * scale).collect();
+    }
+    // below the input or the output Nyquist frequency, whichever is lower
+    let cutoff = ratio.min(1.);
+    let half_width = RESAMPLE_ZEROS / cutoff;
+    (0..len)
+        .map(|m| {
+            let t = m as f32 / ratio;
+            let first = (t - half_width).ceil().max(0.) as usize;
+            let last = ((t + half_width).floor() as usize).min(input.len() - 1);
+            let sum: f32 = (first..=last)
+                .map(|k| {
+                    let x = t - k as f32;
+                    let window = 0.42
+                        + 0.5 * (PI * x / half_width).cos()
+                        + 0.08 * (2. * PI * x / half_width).cos();
+                    input[k] * cutoff * sinc(cutoff * x) * window
+                })
+                .sum();
+            sum * scale / ratio
+        })
+        .collect()
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0. {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The HRIR at `azimuth` from the measurements of a single elevation,
+/// sorted by azimuth, interpolating between the nearest two
+fn interpolate_ring(ring: &[(f32, &Hrir)], azimuth: f32) -> Hrir {
+    let mut out = Hrir::new(KERNEL_LEN);
+    if ring.len() == 1 {
+        out.accumulate(ring[0].1, 1.);
+        return out;
+    }
+    let next = ring
+        .iter()
+        .position(|(az, _)| *az >= azimuth - 0.01)
+        .unwrap_or(ring.len());
+    let (before, after) = (
+        ring[(next + ring.len() - 1) % ring.len()],
+        ring[next % ring.len()],
+    );
+    let span = (after.0 - before.0).rem_euclid(360.);
+    let t = if span == 0. {
+        1.
+    } else {
+        ((azimuth - before.0).rem_euclid(360.) / span).min(1.)
+    };
+    out.accumulate(before.1, 1. - t);
+    out.accumulate(after.1, t);
+    out
+}
+
+#[cfg(all(test, feature = "hrtf"))]
+mod tests {
+    use super::*;
+
+    /// The magnitude of the frequency response of a kernel, the frequency
+    /// being relative to the sample rate
+    fn magnitude(kernel: &[f32], frequency: f32) -> f32 {
+        let (re, im) = kernel
+            .iter()
+            .enumerate()
+            .fold((0., 0.), |(re, im), (n, h)| {
+                let phase = 2. * PI * frequency * n as f32;
+                (re + h * phase.cos(), im - h * phase.sin())
+            });
+        (re * re + im * im).sqrt()
+    }
+
+    fn energy(kernel: &[f32]) -> f32 {
+        kernel.iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn measured_set_is_complete() {
+        let (sample_rate, measurements) = parse(IRCAM_1003);
+        assert_eq!(sample_rate, 44100.);
+        assert_eq!(measurements.len(), 187);
+        for e in 0..ELEVATION_COUNT {
+            let elevation = ELEVATION_MIN + e as f32 * ELEVATION_STEP;
+            assert!(measurements.iter().any(|m| (m.1 - elevation).abs() < 1.));
+        }
+    }
+
+    #[test]
+    fn sources_on_a_side_are_louder_and_earlier_in_that_ear() {
+        let database = HrtfDatabase::new(44100.);
+        let mut hrir = Hrir::new(database.kernel_len());
+        database.interpolate(90., 0., &mut hrir);
+        assert!(energy(&hrir.right) > 4. * energy(&hrir.left));
+        // about 0.6ms for a human head
+        let delay = (hrir.left_delay - hrir.right_delay) / 44100.;
+        assert!((0.0004..0.0009).contains(&delay), "{}", delay);
+
+        database.interpolate(-90., 0., &mut hrir);
+        assert!(energy(&hrir.left) > 4. * energy(&hrir.right));
+        assert!(hrir.right_delay > hrir.left_delay);
+
+        database.interpolate(0., 0., &mut hrir);
+        assert!((hrir.left_delay - hrir.right_delay).abs() < 3.);
+    }
+
+    #[test]
+    fn resampling_keeps_the_response() {
+        let reference = HrtfDatabase::new(44100.);
+        let mut expected = Hrir::new(reference.kernel_len());
+        reference.interpolate(30., 15., &mut expected);
+        for sample_rate in [22050., 48000., 96000.] {
+            let database = HrtfDatabase::for_sample_rate(sample_rate).unwrap();
+            let ratio = sample_rate / 44100.;
+            assert_eq!(
+                database.kernel_len(),
+                (KERNEL_LEN as f32 * ratio).round() as usize
+            );
+            let mut hrir = Hrir::new(database.kernel_len());
+            database.interpolate(30., 15., &mut hrir);
+            let delay = hrir.left_delay - hrir.right_delay;
+            let expected_delay = (expected.left_delay - expected.right_delay) * ratio;
+            assert!((delay - expected_delay).abs() < 0.01, "{}", sample_rate);
+            for frequency in [200., 1000., 4000., 8000.] {
+                let gain = magnitude(&hrir.right, frequency / sample_rate);
+                let expected = magnitude(&expected.right, frequency / 44100.);
+                let db = 20. * (gain / expected).log10();
+                assert!(
+                    db.abs() < 0.5,
+                    "{}Hz at {}: {}dB",
+                    frequency,
+                    sample_rate,
+                    db
+                );
+            }
+        }
+    }
+}
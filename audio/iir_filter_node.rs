@@ -13,6 +13,54 @@ pub struct IIRFilterNodeOptions {
     pub feedback: Arc<Vec<f64>>,
 }
 
+impl IIRFilterNodeOptions {
+    /// The response of the filter at each frequency, in Hz. The
+    /// coefficients of an IIR filter never change, so this is computed
+    /// from the options, on the calling thread.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-iirfilternode-getfrequencyresponse
+    pub fn frequency_response(&self, frequency_hz: &[f32], sample_rate: f32) -> FrequencyResponse {
+        FrequencyResponse::new(&self.feedforward, &self.feedback, frequency_hz, sample_rate)
+    }
+}
+
+/// The response of a filter at a list of frequencies.
+///
+/// https://webaudio.github.io/web-audio-api/#dom-biquadfilternode-getfrequencyresponse
+#[derive(Clone, Debug, Default)]
+pub struct FrequencyResponse {
+    /// The linear gain at each frequency
+    pub magnitude: Vec<f32>,
+    /// The phase shift at each frequency, in radians
+    pub phase: Vec<f32>,
+}
+
+impl FrequencyResponse {
+    /// The response of the filter with the given coefficients at each
+    /// frequency, in Hz. Frequencies outside of [0, nyquist] give NaN.
+    pub fn new(
+        feedforward: &[f64],
+        feedback: &[f64],
+        frequency_hz: &[f32],
+        sample_rate: f32,
+    ) -> Self {
+        let nyquist = sample_rate / 2.;
+        let normalized: Vec<f32> = frequency_hz.iter().map(|f| f / nyquist).collect();
+        let mut response = FrequencyResponse {
+            magnitude: vec![0.; normalized.len()],
+            phase: vec![0.; normalized.len()],
+        };
+        IIRFilterNode::get_frequency_response(
+            feedforward,
+            feedback,
+            &normalized,
+            &mut response.magnitude,
+            &mut response.phase,
+        );
+        response
+    }
+}
+
 #[derive(Clone)]
 struct IIRFilter {
     feedforward: Arc<Vec<f64>>,
@@ -120,6 +168,8 @@ impl IIRFilterNode {
         }
     }
 
+    /// Compute the response at the given normalized frequencies,
+    /// 1 being the nyquist frequency
     pub fn get_frequency_response(
         feedforward: &[f64],
         feedback: &[f64],
@@ -133,7 +183,7 @@ impl IIRFilterNode {
         );
 
         frequency_hz.iter().enumerate().for_each(|(idx, &f)| {
-            if f < 0.0 || f >= 1.0 {
+            if !(0.0..=1.0).contains(&f) {
                 mag_response[idx] = std::f32::NAN;
                 phase_response[idx] = std::f32::NAN;
             } else {
@@ -149,10 +199,14 @@ impl IIRFilterNode {
         });
     }
 
+    /// Evaluate the polynomial sum(coeffs[k] * z^k)
     fn sum(coeffs: &[f64], z: Complex64) -> Complex64 {
-        coeffs.iter().fold(Complex64::new(0.0, 0.0), |acc, &coeff| {
-            acc * z + Complex64::new(coeff, 0.0)
-        })
+        coeffs
+            .iter()
+            .rev()
+            .fold(Complex64::new(0.0, 0.0), |acc, &coeff| {
+                acc * z + Complex64::new(coeff, 0.0)
+            })
     }
 }
 
@@ -161,11 +215,11 @@ impl AudioNodeEngine for IIRFilterNode {
         AudioNodeType::IIRFilterNode
     }
 
-    fn process(&mut self, inputs: Chunk, _info: &BlockInfo) -> Chunk {
+    fn process(&mut self, inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 1);
 
         let mut inputs = if inputs.blocks[0].is_silence() {
-            Chunk::explicit_silence()
+            Chunk::explicit_silence(info.frames_per_block)
         } else {
             inputs
         };
@@ -180,3 +234,50 @@ impl AudioNodeEngine for IIRFilterNode {
         inputs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 44100.;
+
+    fn options(feedforward: Vec<f64>, feedback: Vec<f64>) -> IIRFilterNodeOptions {
+        IIRFilterNodeOptions {
+            feedforward: Arc::new(feedforward),
+            feedback: Arc::new(feedback),
+        }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn delay_response() {
+        // y[n] = x[n - 1]: unit gain, and a phase of -ω
+        let options = options(vec![0., 1.], vec![1.]);
+        let nyquist = SAMPLE_RATE / 2.;
+        let frequencies = [0., nyquist / 4., nyquist / 2., nyquist * 3. / 4.];
+        let response = options.frequency_response(&frequencies, SAMPLE_RATE);
+        assert_close(&response.magnitude, &[1.; 4]);
+        assert_close(&response.phase, &[0., -PI / 4., -PI / 2., -3. * PI / 4.]);
+    }
+
+    #[test]
+    fn one_pole_response() {
+        // y[n] = x[n] + y[n - 1] / 2, so H = 1 / (1 - e^-iω / 2)
+        let options = options(vec![1.], vec![1., -0.5]);
+        let nyquist = SAMPLE_RATE / 2.;
+        let response = options.frequency_response(&[0., nyquist / 2., nyquist], SAMPLE_RATE);
+        assert_close(&response.magnitude, &[2., 1. / 1.25f32.sqrt(), 2. / 3.]);
+        assert_close(&response.phase, &[0., -0.5f32.atan(), 0.]);
+
+        // Outside of [0, nyquist]
+        let response = options.frequency_response(&[-1., nyquist + 1.], SAMPLE_RATE);
+        assert!(response.magnitude.iter().all(|m| m.is_nan()));
+        assert!(response.phase.iter().all(|p| p.is_nan()));
+    }
+}
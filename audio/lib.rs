@@ -26,32 +26,50 @@ pub mod buffer_source_node;
 pub mod channel_node;
 pub mod constant_source_node;
 pub mod context;
+pub mod convolver_node;
 pub mod decoder;
+pub mod delay_node;
 pub mod destination_node;
+pub mod dynamics_compressor_node;
+pub mod export;
+pub mod fft;
 pub mod gain_node;
 pub mod graph;
+pub mod hrtf;
 pub mod iir_filter_node;
 pub mod listener;
 pub mod media_element_source_node;
 pub mod media_stream_destination_node;
 pub mod media_stream_source_node;
 pub mod node;
+pub mod null_sink;
 pub mod offline_sink;
 pub mod oscillator_node;
 pub mod panner_node;
 pub mod param;
+pub mod periodic_wave;
+pub mod profiling;
+pub mod queue;
 pub mod render_thread;
+pub mod script_processor_node;
+pub mod simd;
 pub mod sink;
 pub mod stereo_panner;
+pub mod transaction;
 pub mod wave_shaper_node;
+pub mod workers;
+pub mod worklet_node;
 
 pub trait AudioBackend {
     type Sink: sink::AudioSink + 'static;
     fn make_decoder() -> Box<dyn decoder::AudioDecoder>;
-    fn make_sink() -> Result<Self::Sink, sink::AudioSinkError>;
+    /// Make a sink playing to the audio output device with the given
+    /// `MediaDeviceInfo::device_id`, or to the default device
+    fn make_sink(device_id: Option<&str>) -> Result<Self::Sink, sink::AudioSinkError>;
     fn make_streamreader(
         id: servo_media_streams::MediaStreamId,
         sample_rate: f32,
+        frames_per_block: usize,
     ) -> Box<dyn AudioStreamReader + Send>;
 }
 
@@ -54,7 +54,7 @@ impl AudioNodeEngine for AudioListenerNode {
 
         // XXXManishearth in the common case when all of these are constant,
         // it would be nice to instead send just the constant values down
-        let mut block = Block::for_channels_explicit(9);
+        let mut block = Block::for_channels_explicit(9, info.frames_per_block);
         self.position_x.flush_to_block(info, block.data_chan_mut(0));
         self.position_y.flush_to_block(info, block.data_chan_mut(1));
         self.position_z.flush_to_block(info, block.data_chan_mut(2));
@@ -87,4 +87,18 @@ impl AudioNodeEngine for AudioListenerNode {
             _ => panic!("Unknown param {:?} for AudioListenerNode", id),
         }
     }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[
+            ParamType::Position(ParamDir::X),
+            ParamType::Position(ParamDir::Y),
+            ParamType::Position(ParamDir::Z),
+            ParamType::Forward(ParamDir::X),
+            ParamType::Forward(ParamDir::Y),
+            ParamType::Forward(ParamDir::Z),
+            ParamType::Up(ParamDir::X),
+            ParamType::Up(ParamDir::Y),
+            ParamType::Up(ParamDir::Z),
+        ]
+    }
 }
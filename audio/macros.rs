@@ -18,9 +18,8 @@ macro_rules! make_message_handler(
 macro_rules! make_state_change(
     ($fn_name:ident, $state:ident, $render_msg:ident) => (
         pub fn $fn_name(&self) -> StateChangeResult {
-            self.state.set(ProcessingState::$state);
-            let (tx, rx) = mpsc::channel();
-            let _ = self.sender.send(AudioRenderThreadMsg::$render_msg(tx));
+            let (tx, rx) = mpsc::sync_channel(1);
+            let _ = self.send(AudioRenderThreadMsg::$render_msg(tx));
             rx.recv().unwrap()
         }
     );
@@ -33,8 +32,13 @@ macro_rules! make_render_thread_state_change(
             if self.state == ProcessingState::$state {
                 return Ok(());
             }
-            self.state = ProcessingState::$state;
-            self.sink.$sink_method().map_err(|_| ())
+            if let Err(error) = self.sink.$sink_method() {
+                self.send_event(AudioContextEvent::SinkError(error));
+                return Err(());
+            }
+            self.set_state(ProcessingState::$state);
+            self.send_event(AudioContextEvent::StateChanged(ProcessingState::$state));
+            Ok(())
         }
     );
 );
@@ -1,4 +1,4 @@
-use crate::block::{Block, Chunk, FRAMES_PER_BLOCK};
+use crate::block::{Block, Chunk, Tick};
 use crate::node::{AudioNodeEngine, AudioNodeType, BlockInfo, ChannelInfo};
 use player::audio::AudioRenderer;
 use std::collections::HashMap;
@@ -47,36 +47,41 @@ impl AudioNodeEngine for MediaElementSourceNode {
         AudioNodeType::MediaElementSourceNode
     }
 
-    fn process(&mut self, mut inputs: Chunk, _info: &BlockInfo) -> Chunk {
+    fn has_finished(&self, _: Tick) -> bool {
+        // Plays for as long as the media element does
+        false
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 0);
 
         let buffers = self.buffers.lock().unwrap();
         let chans = buffers.len() as u8;
 
         if chans == 0 {
-            inputs.blocks.push(Default::default());
+            inputs.blocks.push(Block::silence(info.frames_per_block));
             return inputs;
         }
 
         let len = buffers[0].len();
 
-        let frames_per_block = FRAMES_PER_BLOCK.0 as usize;
+        let frames_per_block = info.frames_per_block;
         let samples_to_copy = if self.playback_offset + frames_per_block > len {
             len - self.playback_offset
         } else {
             frames_per_block
         };
         let next_offset = self.playback_offset + samples_to_copy;
-        if samples_to_copy == FRAMES_PER_BLOCK.0 as usize {
+        if samples_to_copy == frames_per_block {
             // copy entire chan
-            let mut block = Block::empty();
+            let mut block = Block::empty(frames_per_block);
             for chan in 0..chans {
                 block.push_chan(&buffers[chan as usize][self.playback_offset..next_offset]);
             }
             inputs.blocks.push(block)
         } else {
             // silent fill and copy
-            let mut block = Block::default();
+            let mut block = Block::silence(frames_per_block);
             block.repeat(chans);
             block.explicit_repeat();
             for chan in 0..chans {
@@ -1,5 +1,5 @@
 use crate::AudioStreamReader;
-use crate::block::Chunk;
+use crate::block::{Chunk, Tick};
 use crate::node::{AudioNodeEngine, BlockInfo};
 use crate::node::{AudioNodeType, ChannelInfo};
 use crate::param::{Param, ParamType};
@@ -26,6 +26,11 @@ impl AudioNodeEngine for MediaStreamSourceNode {
         AudioNodeType::MediaStreamSourceNode
     }
 
+    fn has_finished(&self, _: Tick) -> bool {
+        // Plays for as long as the stream does
+        false
+    }
+
     fn process(&mut self, mut inputs: Chunk, _: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 0);
 
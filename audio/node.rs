@@ -1,32 +1,47 @@
+use crate::analyser_node::{AnalyserNodeMessage, AnalysisEngine};
 use crate::biquad_filter_node::{BiquadFilterNodeMessage, BiquadFilterNodeOptions};
 use crate::block::{Block, Chunk, Tick};
-use crate::buffer_source_node::{AudioBufferSourceNodeMessage, AudioBufferSourceNodeOptions};
+use crate::buffer_source_node::{
+    AudioBuffer, AudioBufferSourceNodeMessage, AudioBufferSourceNodeOptions,
+};
 use crate::channel_node::ChannelNodeOptions;
 use crate::constant_source_node::ConstantSourceNodeOptions;
+use crate::convolver_node::{ConvolverNodeMessage, ConvolverNodeOptions};
+use crate::delay_node::DelayNodeOptions;
+use crate::dynamics_compressor_node::{
+    DynamicsCompressorNodeMessage, DynamicsCompressorNodeOptions,
+};
 use crate::gain_node::GainNodeOptions;
 use crate::iir_filter_node::IIRFilterNodeOptions;
 use crate::media_element_source_node::MediaElementSourceNodeMessage;
 use crate::oscillator_node::{OscillatorNodeMessage, OscillatorNodeOptions};
 use crate::panner_node::{PannerNodeMessage, PannerNodeOptions};
 use crate::param::{Param, ParamRate, ParamType, UserAutomationEvent};
+use crate::periodic_wave::PeriodicWave;
+use crate::script_processor_node::ScriptProcessorNodeOptions;
+use crate::sink::AudioSink;
 use crate::stereo_panner::StereoPannerOptions;
+use crate::transaction::GraphCommand;
 use crate::wave_shaper_node::{WaveShaperNodeMessage, WaveShaperNodeOptions};
+use crate::worklet_node::AudioWorkletNodeOptions;
 use servo_media_streams::{MediaSocket, MediaStreamId};
 use std::cmp::min;
 use std::sync::mpsc::Sender;
 
 /// Information required to construct an audio node
 pub enum AudioNodeInit {
-    AnalyserNode(Box<dyn FnMut(Block) + Send>),
+    /// The node records into the given engine, which is shared
+    /// with all of its clones
+    AnalyserNode(AnalysisEngine),
     BiquadFilterNode(BiquadFilterNodeOptions),
     AudioBuffer,
     AudioBufferSourceNode(AudioBufferSourceNodeOptions),
     ChannelMergerNode(ChannelNodeOptions),
     ChannelSplitterNode,
     ConstantSourceNode(ConstantSourceNodeOptions),
-    ConvolverNode,
-    DelayNode,
-    DynamicsCompressionNode,
+    ConvolverNode(ConvolverNodeOptions),
+    DelayNode(DelayNodeOptions),
+    DynamicsCompressionNode(DynamicsCompressorNodeOptions),
     GainNode(GainNodeOptions),
     IIRFilterNode(IIRFilterNodeOptions),
     MediaElementSourceNode,
@@ -34,10 +49,26 @@ pub enum AudioNodeInit {
     MediaStreamSourceNode(MediaStreamId),
     OscillatorNode(OscillatorNodeOptions),
     PannerNode(PannerNodeOptions),
+    #[deprecated(note = "not a node, see periodic_wave::PeriodicWave")]
     PeriodicWave,
-    ScriptProcessorNode,
+    ScriptProcessorNode(ScriptProcessorNodeOptions),
     StereoPannerNode(StereoPannerOptions),
     WaveShaperNode(WaveShaperNodeOptions),
+    AudioWorkletNode(AudioWorkletNodeOptions),
+}
+
+impl AudioNodeInit {
+    /// Check the options on the control thread, before the node is built
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        match *self {
+            AudioNodeInit::ConvolverNode(ref options) => options.check(),
+            AudioNodeInit::DelayNode(ref options) => options.check(),
+            AudioNodeInit::OscillatorNode(ref options) => options.check(),
+            AudioNodeInit::ScriptProcessorNode(ref options) => options.check(),
+            AudioNodeInit::AudioWorkletNode(ref options) => options.check(),
+            _ => Ok(()),
+        }
+    }
 }
 
 /// Type of AudioNodeEngine.
@@ -63,10 +94,12 @@ pub enum AudioNodeType {
     MediaStreamSourceNode,
     OscillatorNode,
     PannerNode,
+    #[deprecated(note = "not a node, see periodic_wave::PeriodicWave")]
     PeriodicWave,
     ScriptProcessorNode,
     StereoPannerNode,
     WaveShaperNode,
+    AudioWorkletNode,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -87,9 +120,23 @@ pub struct BlockInfo {
     pub sample_rate: f32,
     pub frame: Tick,
     pub time: f64,
+    /// The number of frames in the block, i.e. the render quantum size
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-renderquantumsize
+    pub frames_per_block: usize,
 }
 
 impl BlockInfo {
+    /// The number of frames in the block, as a `Tick`
+    pub fn block_ticks(&self) -> Tick {
+        Tick(self.frames_per_block as u64)
+    }
+
+    /// The frame the next block starts at
+    pub fn next_frame(&self) -> Tick {
+        self.frame + self.block_ticks()
+    }
+
     /// Given the current block, calculate the absolute zero-relative
     /// tick of the given tick
     pub fn absolute_tick(&self, tick: Tick) -> Tick {
@@ -196,15 +243,90 @@ pub(crate) trait AudioNodeEngine: Send + AudioNodeCommon {
         panic!("No params on node {:?}", self.node_type())
     }
 
+    /// How long the node can keep producing output once its inputs
+    /// have gone silent
+    ///
+    /// https://webaudio.github.io/web-audio-api/#tail-time
+    fn tail_time(&self) -> Tick {
+        Tick(0)
+    }
+
+    /// Whether the node won't produce sound on its own from the given
+    /// frame on, without any input. Sources have finished once they have
+    /// stopped, or if they were never started.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#actively-processing
+    fn has_finished(&self, _: Tick) -> bool {
+        true
+    }
+
+    /// The params `get_param` accepts
+    fn param_types(&self) -> &[ParamType] {
+        &[]
+    }
+
+    /// Whether the value of any param may change without new events
+    /// being inserted
+    fn has_automation(&mut self) -> bool {
+        (0..self.param_types().len()).any(|i| {
+            let param = self.param_types()[i];
+            self.get_param(param).is_automated()
+        })
+    }
+
     fn set_listenerdata(&mut self, _: Block) {
         panic!("can't accept listener connections")
     }
+
+    /// Whether this node can break a cycle in the graph, by producing
+    /// its output for a block before it has received that block's input
+    ///
+    /// https://webaudio.github.io/web-audio-api/#cycles
+    fn breaks_cycles(&self) -> bool {
+        false
+    }
+
+    /// For cycle-breaking nodes that are part of a cycle: produce the
+    /// output for this block, before `write_input` is called with its input
+    fn read_output(&mut self, _: &BlockInfo) -> Chunk {
+        panic!("{:?} cannot break cycles", self.node_type())
+    }
+
+    /// For cycle-breaking nodes that are part of a cycle: accept the
+    /// input for this block, after `read_output` has been called
+    fn write_input(&mut self, _: Chunk, _: &BlockInfo) {
+        panic!("{:?} cannot break cycles", self.node_type())
+    }
+
+    /// Hand over what the last message replaced, for it to be freed
+    /// off the render thread
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        None
+    }
+}
+
+/// Memory the render thread is done with, which is freed on another
+/// thread, as freeing it could make rendering miss its deadline
+#[allow(dead_code)] // only held to be dropped
+pub(crate) enum Garbage {
+    Engine(Box<dyn AudioNodeEngine>),
+    Buffer(AudioBuffer),
+    Samples(Vec<f32>),
+    Wave(PeriodicWave),
+    Commands(Vec<GraphCommand>),
+    /// A sink replaced by another one
+    Sink(Box<dyn AudioSink>),
+    /// Anything already boxed
+    Boxed(Box<dyn Send>),
 }
 
 pub enum AudioNodeMessage {
+    AnalyserNode(AnalyserNodeMessage),
     AudioBufferSourceNode(AudioBufferSourceNodeMessage),
     AudioScheduledSourceNode(AudioScheduledSourceNodeMessage),
     BiquadFilterNode(BiquadFilterNodeMessage),
+    ConvolverNode(ConvolverNodeMessage),
+    DynamicsCompressionNode(DynamicsCompressorNodeMessage),
     GetParamValue(ParamType, Sender<f32>),
     MediaElementSourceNode(MediaElementSourceNodeMessage),
     OscillatorNode(OscillatorNodeMessage),
@@ -217,6 +339,21 @@ pub enum AudioNodeMessage {
     WaveShaperNode(WaveShaperNodeMessage),
 }
 
+/// Why a node can't be created, or a message can't be passed to it.
+/// This is checked on the control thread, so that neither the embedder
+/// nor the rendering thread panics. Named after the exceptions the spec
+/// throws.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioNodeError {
+    /// A value is outside of the allowed range
+    IndexSize,
+    /// The node can't take the message in its current state, or the
+    /// context is gone
+    InvalidState,
+    /// The options describe a node that can't be built
+    NotSupported,
+}
+
 pub struct OnEndedCallback(pub Box<dyn FnOnce() + Send + 'static>);
 
 impl OnEndedCallback {
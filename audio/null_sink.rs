@@ -0,0 +1,142 @@
+use crate::block::{Chunk, FRAMES_PER_BLOCK_USIZE};
+use crate::context::LatencyCategory;
+use crate::render_thread::RenderThreadWaker;
+use crate::sink::{AudioSink, AudioSinkError, AudioSinkLatency};
+use servo_media_streams::MediaSocket;
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, Builder};
+use std::time::{Duration, Instant};
+
+/// How far ahead of the wall clock rendering may get
+const AHEAD: Duration = Duration::from_millis(10);
+
+/// An audio sink that plays nothing, while consuming audio at the pace
+/// an audio output device would.
+///
+/// https://webaudio.github.io/web-audio-api/#dom-audiosinktype-none
+pub struct NullAudioSink {
+    sample_rate: Cell<f32>,
+    frames_per_block: Cell<usize>,
+    render_thread: RefCell<Option<RenderThreadWaker>>,
+    /// When playback started, if playing
+    started: Cell<Option<Instant>>,
+    /// Frames pushed since playback started
+    pushed_frames: Cell<u64>,
+    /// Keeps the thread waking up the render thread alive while set
+    ticking: RefCell<Option<Arc<AtomicBool>>>,
+}
+
+impl NullAudioSink {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: Cell::new(44100.),
+            frames_per_block: Cell::new(FRAMES_PER_BLOCK_USIZE),
+            render_thread: RefCell::new(None),
+            started: Cell::new(None),
+            pushed_frames: Cell::new(0),
+            ticking: RefCell::new(None),
+        }
+    }
+
+    fn stop_ticking(&self) {
+        if let Some(ticking) = self.ticking.borrow_mut().take() {
+            ticking.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for NullAudioSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioSink for NullAudioSink {
+    fn init(
+        &self,
+        sample_rate: f32,
+        frames_per_block: usize,
+        _: LatencyCategory,
+        render_thread: RenderThreadWaker,
+    ) -> Result<(), AudioSinkError> {
+        self.sample_rate.set(sample_rate);
+        self.frames_per_block.set(frames_per_block);
+        *self.render_thread.borrow_mut() = Some(render_thread);
+        Ok(())
+    }
+
+    fn init_stream(&self, _: u8, _: f32, _: Box<dyn MediaSocket>) -> Result<(), AudioSinkError> {
+        unreachable!("NullAudioSink should never be used for MediaStreamDestinationNode")
+    }
+
+    fn play(&self) -> Result<(), AudioSinkError> {
+        self.stop_ticking();
+        let render_thread = self
+            .render_thread
+            .borrow()
+            .clone()
+            .ok_or(AudioSinkError::StateChangeFailed)?;
+        let ticking = Arc::new(AtomicBool::new(true));
+        let ticking_ = ticking.clone();
+        Builder::new()
+            .name("NullAudioSink".to_owned())
+            .spawn(move || {
+                // Exits once stopped, or once the sink is dropped
+                while ticking_.load(Ordering::Relaxed) {
+                    render_thread.wake();
+                    thread::sleep(AHEAD);
+                }
+            })
+            .map_err(|_| AudioSinkError::StateChangeFailed)?;
+        *self.ticking.borrow_mut() = Some(ticking);
+        self.started.set(Some(Instant::now()));
+        self.pushed_frames.set(0);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), AudioSinkError> {
+        self.stop_ticking();
+        self.started.set(None);
+        Ok(())
+    }
+
+    fn has_enough_data(&self) -> bool {
+        let Some(started) = self.started.get() else {
+            return true;
+        };
+        let pushed = Duration::from_secs_f64(
+            self.pushed_frames.get() as f64 / self.sample_rate.get() as f64,
+        );
+        pushed >= started.elapsed() + AHEAD
+    }
+
+    fn push_data(&self, _: Chunk) -> Result<(), AudioSinkError> {
+        self.pushed_frames
+            .set(self.pushed_frames.get() + self.frames_per_block.get() as u64);
+        Ok(())
+    }
+
+    fn is_starved(&self) -> bool {
+        let Some(started) = self.started.get() else {
+            return false;
+        };
+        let pushed_frames = self.pushed_frames.get();
+        let pushed = Duration::from_secs_f64(pushed_frames as f64 / self.sample_rate.get() as f64);
+        // The render thread is only woken up every AHEAD, so allow for that
+        pushed_frames > 0 && pushed + AHEAD < started.elapsed()
+    }
+
+    fn latency(&self) -> AudioSinkLatency {
+        AudioSinkLatency::default()
+    }
+
+    fn set_eos_callback(&self, _: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>) {}
+}
+
+impl Drop for NullAudioSink {
+    fn drop(&mut self) {
+        self.stop_ticking();
+    }
+}
@@ -1,9 +1,12 @@
-use crate::block::{Chunk, FRAMES_PER_BLOCK_USIZE};
-use crate::render_thread::AudioRenderThreadMsg;
-use crate::sink::{AudioSink, AudioSinkError};
+use crate::block::{Block, Chunk, FRAMES_PER_BLOCK_USIZE};
+use crate::context::LatencyCategory;
+use crate::render_thread::RenderThreadWaker;
+use crate::simd;
+use crate::sink::{AudioSink, AudioSinkError, AudioSinkLatency};
+use byte_slice_cast::AsByteSlice;
 use servo_media_streams::MediaSocket;
 use std::cell::{Cell, RefCell};
-use std::sync::mpsc::Sender;
+use std::io::{self, Write};
 
 pub struct ProcessedAudio(Box<[f32]>);
 
@@ -13,13 +16,111 @@ impl AsRef<[f32]> for ProcessedAudio {
     }
 }
 
+/// A part of the audio rendered by an offline context, handed over
+/// while the rest is being rendered
+pub struct RenderedChunk<'a> {
+    /// Planar, `frames` sample-frames per channel
+    data: &'a [f32],
+    channel_count: usize,
+    frame: usize,
+    length: usize,
+}
+
+impl<'a> RenderedChunk<'a> {
+    /// The sample-frame this chunk starts at
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// The number of sample-frames in this chunk
+    pub fn frames(&self) -> usize {
+        self.data.len() / self.channel_count
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    pub fn channel(&self, channel: usize) -> &'a [f32] {
+        let frames = self.frames();
+        &self.data[channel * frames..(channel + 1) * frames]
+    }
+
+    /// The number of sample-frames of the whole rendering
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The fraction of the rendering done so far, this chunk included
+    pub fn progress(&self) -> f64 {
+        if self.length == 0 {
+            return 1.;
+        }
+        (self.frame + self.frames()) as f64 / self.length as f64
+    }
+
+    /// Whether this chunk ends the rendering
+    pub fn is_last(&self) -> bool {
+        self.frame + self.frames() == self.length
+    }
+
+    /// Interleave the channels of this chunk into `dst`, which has to
+    /// hold `frames() * channel_count()` samples
+    pub fn interleave(&self, dst: &mut [f32]) {
+        simd::interleave(self.data, self.channel_count, dst);
+    }
+
+    /// Write this chunk as interleaved little-endian f32 samples
+    pub fn write_interleaved(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mut interleaved = vec![0.; self.data.len()];
+        self.interleave(&mut interleaved);
+        if cfg!(target_endian = "big") {
+            for sample in &mut interleaved {
+                *sample = f32::from_bits(sample.to_bits().swap_bytes());
+            }
+        }
+        writer.write_all(interleaved.as_byte_slice())
+    }
+}
+
+/// Receives the audio rendered by an offline context, chunk by chunk.
+/// Rendering stops if it fails.
+pub type OfflineStreamCallback = Box<dyn FnMut(&RenderedChunk) -> io::Result<()> + Send + 'static>;
+
+/// Stream the rendered audio to `writer`, as interleaved little-endian
+/// f32 samples
+pub fn stream_to_writer<W: Write + Send + 'static>(mut writer: W) -> OfflineStreamCallback {
+    Box::new(move |chunk| {
+        chunk.write_interleaved(&mut writer)?;
+        if chunk.is_last() {
+            writer.flush()?;
+        }
+        Ok(())
+    })
+}
+
+/// Streaming state, used instead of buffering the whole rendering
+struct OfflineStream {
+    callback: OfflineStreamCallback,
+    /// A whole number of blocks
+    chunk_frames: usize,
+    /// Planar, `chunk_frames` sample-frames per channel
+    buffer: Vec<f32>,
+    /// The number of sample-frames in `buffer`
+    filled: usize,
+}
+
 pub struct OfflineAudioSink {
     buffer: RefCell<Option<Vec<f32>>>,
     channel_count: usize,
     has_enough_data: Cell<bool>,
     length: usize,
     rendered_blocks: Cell<usize>,
+    frames_per_block: Cell<usize>,
     eos_callback: RefCell<Option<Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>>>,
+    stream: RefCell<Option<OfflineStream>>,
+    /// Set once streaming failed, which stops the rendering
+    failed: Cell<bool>,
 }
 
 impl OfflineAudioSink {
@@ -30,13 +131,111 @@ impl OfflineAudioSink {
             has_enough_data: Cell::new(false),
             length,
             rendered_blocks: Cell::new(0),
+            frames_per_block: Cell::new(FRAMES_PER_BLOCK_USIZE),
             eos_callback: RefCell::new(None),
+            stream: RefCell::new(None),
+            failed: Cell::new(false),
         }
     }
+
+    /// The number of frames to render
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Hand the rendered audio over to `callback` in chunks of
+    /// `chunk_frames` sample-frames, rounded up to a whole number of
+    /// render quanta, instead of buffering all of it for the EOS callback.
+    /// The EOS callback then gets no audio. Fails once rendering started.
+    pub fn set_stream_callback(
+        &self,
+        chunk_frames: usize,
+        callback: OfflineStreamCallback,
+    ) -> Result<(), AudioSinkError> {
+        if self.rendered_blocks.get() > 0 {
+            return Err(AudioSinkError::StateChangeFailed);
+        }
+        let frames_per_block = self.frames_per_block.get();
+        let chunk_frames = chunk_frames.max(1).div_ceil(frames_per_block) * frames_per_block;
+        *self.stream.borrow_mut() = Some(OfflineStream {
+            callback,
+            chunk_frames,
+            buffer: vec![0.; self.channel_count * chunk_frames],
+            filled: 0,
+        });
+        Ok(())
+    }
+
+    /// Copy the first `len` frames of a block into `stream`, handing the
+    /// chunk over once it is full or the rendering is done
+    fn stream_block(
+        &self,
+        stream: &mut OfflineStream,
+        block: &Block,
+        len: usize,
+        last: bool,
+    ) -> io::Result<()> {
+        let chunk_frames = stream.chunk_frames;
+        for channel in 0..self.channel_count {
+            let start = channel * chunk_frames + stream.filled;
+            stream.buffer[start..start + len]
+                .copy_from_slice(&block.data_chan(channel as u8)[0..len]);
+        }
+        stream.filled += len;
+        if stream.filled < chunk_frames && !last {
+            return Ok(());
+        }
+
+        let frames = stream.filled;
+        if frames < chunk_frames {
+            // Make the channels of a partial chunk contiguous
+            for channel in 1..self.channel_count {
+                let start = channel * chunk_frames;
+                stream
+                    .buffer
+                    .copy_within(start..start + frames, channel * frames);
+            }
+        }
+        stream.filled = 0;
+        let chunk = RenderedChunk {
+            data: &stream.buffer[0..self.channel_count * frames],
+            channel_count: self.channel_count,
+            frame: self.rendered_blocks.get() * self.frames_per_block.get() + len - frames,
+            length: self.length,
+        };
+        (stream.callback)(&chunk)
+    }
+
+    /// Hand over an empty rendering, for which no block is pushed
+    fn end_empty_rendering(&self) -> Result<(), AudioSinkError> {
+        if let Some(mut stream) = self.stream.borrow_mut().take() {
+            let chunk = RenderedChunk {
+                data: &[],
+                channel_count: self.channel_count,
+                frame: 0,
+                length: 0,
+            };
+            if let Err(error) = (stream.callback)(&chunk) {
+                self.failed.set(true);
+                return Err(AudioSinkError::Backend(error.to_string()));
+            }
+        }
+        if let Some(callback) = self.eos_callback.borrow_mut().take() {
+            callback(Box::new(ProcessedAudio(Box::new([]))));
+        }
+        Ok(())
+    }
 }
 
 impl AudioSink for OfflineAudioSink {
-    fn init(&self, _: f32, _: Sender<AudioRenderThreadMsg>) -> Result<(), AudioSinkError> {
+    fn init(
+        &self,
+        _: f32,
+        frames_per_block: usize,
+        _: LatencyCategory,
+        _: RenderThreadWaker,
+    ) -> Result<(), AudioSinkError> {
+        self.frames_per_block.set(frames_per_block);
         Ok(())
     }
     fn init_stream(&self, _: u8, _: f32, _: Box<dyn MediaSocket>) -> Result<(), AudioSinkError> {
@@ -44,6 +243,9 @@ impl AudioSink for OfflineAudioSink {
     }
     fn play(&self) -> Result<(), AudioSinkError> {
         self.has_enough_data.set(false);
+        if self.length == 0 {
+            return self.end_empty_rendering();
+        }
         Ok(())
     }
 
@@ -54,39 +256,46 @@ impl AudioSink for OfflineAudioSink {
 
     fn has_enough_data(&self) -> bool {
         self.has_enough_data.get()
-            || (self.rendered_blocks.get() * FRAMES_PER_BLOCK_USIZE >= self.length)
+            || self.failed.get()
+            || (self.rendered_blocks.get() * self.frames_per_block.get() >= self.length)
     }
 
     fn push_data(&self, mut chunk: Chunk) -> Result<(), AudioSinkError> {
-        let offset = self.rendered_blocks.get() * FRAMES_PER_BLOCK_USIZE;
-        let (last, copy_len) = if self.length - offset <= FRAMES_PER_BLOCK_USIZE {
+        let frames_per_block = self.frames_per_block.get();
+        let offset = self.rendered_blocks.get() * frames_per_block;
+        let (last, copy_len) = if self.length - offset <= frames_per_block {
             (true, self.length - offset)
         } else {
-            (false, FRAMES_PER_BLOCK_USIZE)
+            (false, frames_per_block)
         };
-        let mut buffer = self.buffer.borrow_mut();
-        if buffer.is_none() {
-            *buffer = Some(vec![0.; self.channel_count * self.length]);
-        }
         if chunk.len() == 0 {
-            chunk.blocks.push(Default::default());
+            chunk.blocks.push(Block::silence(frames_per_block));
         }
         if chunk.blocks[0].is_empty() {
             chunk.blocks[0].explicit_silence();
         }
-        if let Some(ref mut buffer) = *buffer {
+
+        let mut buffer = self.buffer.borrow_mut();
+        if let Some(ref mut stream) = *self.stream.borrow_mut() {
+            if let Err(error) = self.stream_block(stream, &chunk.blocks[0], copy_len, last) {
+                self.failed.set(true);
+                return Err(AudioSinkError::Backend(error.to_string()));
+            }
+        } else {
+            let buffer = buffer.get_or_insert_with(|| vec![0.; self.channel_count * self.length]);
             for channel_number in 0..self.channel_count {
                 let channel_offset = offset + (channel_number * self.length);
                 let channel_data = &mut buffer[channel_offset..channel_offset + copy_len];
                 channel_data
                     .copy_from_slice(&chunk.blocks[0].data_chan(channel_number as u8)[0..copy_len]);
             }
-        };
+        }
         self.rendered_blocks.set(self.rendered_blocks.get() + 1);
 
         if last {
             if let Some(callback) = self.eos_callback.borrow_mut().take() {
-                let processed_audio = ProcessedAudio(buffer.take().unwrap().into_boxed_slice());
+                let processed_audio =
+                    ProcessedAudio(buffer.take().unwrap_or_default().into_boxed_slice());
                 callback(Box::new(processed_audio));
             }
         }
@@ -94,6 +303,15 @@ impl AudioSink for OfflineAudioSink {
         Ok(())
     }
 
+    fn is_starved(&self) -> bool {
+        false
+    }
+
+    fn latency(&self) -> AudioSinkLatency {
+        // Nothing is played, the rendered audio is immediately available
+        AudioSinkLatency::default()
+    }
+
     fn set_eos_callback(
         &self,
         callback: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>,
@@ -1,13 +1,12 @@
-use crate::block::{Chunk, Tick};
-use crate::node::{AudioNodeEngine, AudioScheduledSourceNodeMessage, BlockInfo, OnEndedCallback};
-use crate::node::{AudioNodeType, ChannelInfo, ShouldPlay};
+use crate::block::{Block, Chunk, Tick};
+use crate::node::OnEndedCallback;
+use crate::node::{AudioNodeEngine, AudioNodeError, AudioScheduledSourceNodeMessage, BlockInfo};
+use crate::node::{AudioNodeType, ChannelInfo, Garbage, ShouldPlay};
 use crate::param::{Param, ParamType};
-use num_traits::cast::NumCast;
+use crate::periodic_wave::PeriodicWave;
+pub use crate::periodic_wave::PeriodicWaveOptions;
+use std::f64::consts::PI;
 
-#[derive(Clone, Debug)]
-pub struct PeriodicWaveOptions {
-    // XXX https://webaudio.github.io/web-audio-api/#dictdef-periodicwaveoptions
-}
 #[derive(Clone, Debug)]
 pub enum OscillatorType {
     Sine,
@@ -22,31 +21,60 @@ pub struct OscillatorNodeOptions {
     pub oscillator_type: OscillatorType,
     pub freq: f32,
     pub detune: f32,
+    /// The wave to play, required for (and only used by) custom oscillators
+    pub periodic_wave: Option<PeriodicWave>,
+    /// Used to build the wave of custom oscillators without `periodic_wave`
+    #[deprecated(note = "build a PeriodicWave and set periodic_wave instead")]
     pub periodic_wave_options: Option<PeriodicWaveOptions>,
 }
 
 impl Default for OscillatorNodeOptions {
+    #[allow(deprecated)]
     fn default() -> Self {
         OscillatorNodeOptions {
             oscillator_type: OscillatorType::Sine,
             freq: 440.,
             detune: 0.,
+            periodic_wave: None,
             periodic_wave_options: None,
         }
     }
 }
 
+impl OscillatorNodeOptions {
+    /// https://webaudio.github.io/web-audio-api/#dom-oscillatornode-oscillatornode
+    #[allow(deprecated)]
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        match self.oscillator_type {
+            OscillatorType::Custom
+                if self.periodic_wave.is_none() && self.periodic_wave_options.is_none() =>
+            {
+                Err(AudioNodeError::InvalidState)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum OscillatorNodeMessage {
+    /// Switch to one of the built-in waves
     SetOscillatorType(OscillatorType),
+    /// Switch to a custom wave
+    SetPeriodicWave(PeriodicWave),
 }
 
 #[derive(AudioScheduledSourceNode, AudioNodeCommon)]
 pub(crate) struct OscillatorNode {
     channel_info: ChannelInfo,
     oscillator_type: OscillatorType,
+    /// The wave being played, None for a sine
+    wave: Option<PeriodicWave>,
+    /// The wave replaced by the last message
+    replaced: Option<PeriodicWave>,
     frequency: Param,
     detune: Param,
+    /// The position in the current period, in [0, 1)
     phase: f64,
     /// Time at which the source should start playing.
     start_at: Option<Tick>,
@@ -56,10 +84,33 @@ pub(crate) struct OscillatorNode {
     onended_callback: Option<OnEndedCallback>,
 }
 
+/// The wave an oscillator of the given type plays
+fn wave_for_type(
+    oscillator_type: &OscillatorType,
+    periodic_wave: Option<PeriodicWave>,
+) -> Option<PeriodicWave> {
+    match *oscillator_type {
+        OscillatorType::Sine => None,
+        OscillatorType::Custom => {
+            Some(periodic_wave.expect("InvalidStateError: custom oscillators need a PeriodicWave"))
+        },
+        ref other => Some(PeriodicWave::for_type(other)),
+    }
+}
+
 impl OscillatorNode {
+    #[allow(deprecated)]
     pub fn new(options: OscillatorNodeOptions, channel_info: ChannelInfo) -> Self {
+        // Nodes are built on the control thread: compute the built-in waves
+        // here rather than on the render thread, when switching types
+        PeriodicWave::for_type(&OscillatorType::Sine);
+        let periodic_wave = options
+            .periodic_wave
+            .or_else(|| options.periodic_wave_options.map(PeriodicWave::new));
         Self {
             channel_info,
+            wave: wave_for_type(&options.oscillator_type, periodic_wave),
+            replaced: None,
             oscillator_type: options.oscillator_type,
             frequency: Param::new(options.freq.into()),
             detune: Param::new(options.detune.into()),
@@ -71,15 +122,38 @@ impl OscillatorNode {
     }
 
     pub fn update_parameters(&mut self, info: &BlockInfo, tick: Tick) -> bool {
-        self.frequency.update(info, tick)
+        let mut changed = self.frequency.update(info, tick);
+        changed |= self.detune.update(info, tick);
+        changed
+    }
+
+    /// https://webaudio.github.io/web-audio-api/#dom-oscillatornode-frequency
+    fn computed_frequency(&self) -> f32 {
+        self.frequency.value() * (self.detune.value() / 1200.).exp2()
+    }
+
+    fn table_for(&self, frequency: f32, sample_rate: f32) -> usize {
+        self.wave
+            .as_ref()
+            .map_or(0, |wave| wave.table_for(frequency, sample_rate))
     }
 
     fn handle_oscillator_message(&mut self, message: OscillatorNodeMessage, _sample_rate: f32) {
-        match message {
+        let wave = match message {
+            // Rejected by `AudioContext::message_node`
+            OscillatorNodeMessage::SetOscillatorType(OscillatorType::Custom) => return,
             OscillatorNodeMessage::SetOscillatorType(o) => {
+                let wave = wave_for_type(&o, None);
                 self.oscillator_type = o;
+                wave
             },
-        }
+            OscillatorNodeMessage::SetPeriodicWave(wave) => {
+                self.oscillator_type = OscillatorType::Custom;
+                Some(wave)
+            },
+        };
+        // A custom wave can be large, free it off the render thread
+        self.replaced = std::mem::replace(&mut self.wave, wave);
     }
 }
 
@@ -88,13 +162,18 @@ impl AudioNodeEngine for OscillatorNode {
         AudioNodeType::OscillatorNode
     }
 
+    fn has_finished(&self, frame: Tick) -> bool {
+        self.has_ended(frame)
+    }
+
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        self.replaced.take().map(Garbage::Wave)
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
-        // XXX Implement this properly and according to self.options
-        // as defined in https://webaudio.github.io/web-audio-api/#oscillatornode
-        use std::f64::consts::PI;
         debug_assert!(inputs.len() == 0);
-        inputs.blocks.push(Default::default());
-        let (start_at, stop_at) = match self.should_play_at(info.frame) {
+        inputs.blocks.push(Block::silence(info.frames_per_block));
+        let (start_at, stop_at) = match self.should_play_at(info) {
             ShouldPlay::No => {
                 return inputs;
             },
@@ -105,17 +184,13 @@ impl AudioNodeEngine for OscillatorNode {
             inputs.blocks[0].explicit_silence();
             let mut iter = inputs.blocks[0].iter();
 
-            // Convert all our parameters to the target type for calculations
-            let vol: f32 = 1.0;
-            let sample_rate = info.sample_rate as f64;
-            let two_pi = 2.0 * PI;
-
-            // We're carrying a phase with up to 2pi around instead of working
+            // We're carrying a phase in [0, 1) around instead of working
             // on the sample offset. High sample offsets cause too much inaccuracy when
             // converted to floating point numbers and then iterated over in 1-steps
             //
             // Also, if the frequency changes the phase should not
-            let mut step = two_pi * self.frequency.value() as f64 / sample_rate;
+            let mut frequency = self.computed_frequency();
+            let mut table = self.table_for(frequency, info.sample_rate);
             while let Some(mut frame) = iter.next() {
                 let tick = frame.tick();
                 if tick < start_at {
@@ -125,50 +200,19 @@ impl AudioNodeEngine for OscillatorNode {
                 }
 
                 if self.update_parameters(info, tick) {
-                    step = two_pi * self.frequency.value() as f64 / sample_rate;
-                }
-                let mut value = vol;
-                match self.oscillator_type {
-                    OscillatorType::Sine => {
-                        value = vol * f32::sin(NumCast::from(self.phase).unwrap());
-                    },
-
-                    OscillatorType::Square => {
-                        if self.phase >= PI && self.phase < two_pi {
-                            value = vol * 1.0;
-                        } else if self.phase > 0.0 && self.phase < PI {
-                            value = vol * (-1.0);
-                        }
-                    },
-
-                    OscillatorType::Sawtooth => {
-                        value = vol * ((self.phase as f64) / (PI)) as f32;
-                    },
-
-                    OscillatorType::Triangle => {
-                        if self.phase >= 0. && self.phase < PI / 2. {
-                            value = vol * 2.0 * ((self.phase as f64) / (PI)) as f32;
-                        } else if self.phase >= PI / 2. && self.phase < PI {
-                            value =
-                                vol * (1. - (((self.phase as f64) - (PI / 2.)) * (2. / PI)) as f32);
-                        } else if self.phase >= PI && self.phase < (3. * PI / 2.) {
-                            value = vol
-                                * -1.
-                                * (1. - (((self.phase as f64) - (PI / 2.)) * (2. / PI)) as f32);
-                        } else if self.phase >= 3. * PI / 2. && self.phase < 2. * PI {
-                            value = vol * (-2.0) * ((self.phase as f64) / (PI)) as f32;
-                        }
-                    },
-
-                    OscillatorType::Custom => {},
+                    frequency = self.computed_frequency();
+                    table = self.table_for(frequency, info.sample_rate);
                 }
+                let value = match self.wave {
+                    // a sine has no partials to band-limit, compute it exactly
+                    None => (2. * PI * self.phase).sin() as f32,
+                    Some(ref wave) => wave.sample(table, self.phase),
+                };
 
                 frame.mutate_with(|sample, _| *sample = value);
 
-                self.phase += step;
-                if self.phase >= two_pi {
-                    self.phase -= two_pi;
-                }
+                self.phase += frequency as f64 / info.sample_rate as f64;
+                self.phase -= self.phase.floor();
             }
         }
         inputs
@@ -185,6 +229,10 @@ impl AudioNodeEngine for OscillatorNode {
             _ => panic!("Unknown param {:?} for OscillatorNode", id),
         }
     }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::Frequency, ParamType::Detune]
+    }
     make_message_handler!(
         AudioScheduledSourceNode: handle_source_node_message,
         OscillatorNode: handle_oscillator_message
@@ -1,9 +1,11 @@
-use crate::block::{Block, Chunk, FRAMES_PER_BLOCK, Tick};
-use crate::node::{AudioNodeEngine, AudioNodeMessage, BlockInfo};
-use crate::node::{AudioNodeType, ChannelInfo};
+use crate::block::{Block, Chunk, Tick};
+use crate::hrtf::{Hrir, HrtfDatabase};
+use crate::node::{AudioNodeEngine, AudioNodeMessage, BlockInfo, Garbage};
+use crate::node::{AudioNodeType, ChannelInfo, ChannelInterpretation};
 use crate::param::{Param, ParamDir, ParamType};
 use euclid::default::Vector3D;
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 // .normalize(), but it takes into account zero vectors
 pub fn normalize_zero(v: Vector3D<f32>) -> Vector3D<f32> {
@@ -43,7 +45,12 @@ pub struct PannerNodeOptions {
 }
 
 pub enum PannerNodeMessage {
+    /// `AudioContext::message_node` turns `HRTF` into `SetHrtfRenderer`,
+    /// or drops it if the crate was built without the `hrtf` feature.
     SetPanningModel(PanningModel),
+    /// Switch to the HRTF panning model, with a renderer built on the
+    /// control thread
+    SetHrtfRenderer(Box<HrtfRenderer>),
     SetDistanceModel(DistanceModel),
     SetRefDistance(f64),
     SetMaxDistance(f64),
@@ -92,16 +99,196 @@ pub(crate) struct PannerNode {
     cone_outer_angle: f64,
     cone_outer_gain: f64,
     listener_data: Option<Block>,
+    /// Built on the control thread once the HRTF panning model is
+    /// selected, as loading the HRIRs is too slow for the rendering thread
+    hrtf: Option<Box<HrtfRenderer>>,
+    /// A renderer the last message made redundant, to be freed off the
+    /// rendering thread
+    replaced: Option<Box<HrtfRenderer>>,
+}
+
+/// The input of a single ear, before it is delayed and convolved
+struct EarLine {
+    /// Ring buffer of raw input, long enough for the longest HRIR delay
+    raw: Vec<f32>,
+    /// The position in `raw` of the next block
+    raw_pos: usize,
+    /// The last `kernel_len - 1` frames of delayed input, followed by
+    /// this block's delayed input
+    delayed: Vec<f32>,
+    kernel_len: usize,
+    frames_per_block: usize,
+}
+
+impl EarLine {
+    fn new(kernel_len: usize, max_delay: f32, frames_per_block: usize) -> Self {
+        EarLine {
+            raw: vec![0.; frames_per_block + max_delay.ceil() as usize + 2],
+            raw_pos: 0,
+            delayed: vec![0.; kernel_len - 1 + frames_per_block],
+            kernel_len,
+            frames_per_block,
+        }
+    }
+
+    /// Push a block of input (None for silence), and delay it by an
+    /// amount going from `from` to `to` frames over the block
+    fn push(&mut self, input: Option<&[f32]>, from: f32, to: f32) {
+        let len = self.raw.len();
+        let frames = self.frames_per_block;
+        for i in 0..frames {
+            self.raw[(self.raw_pos + i) % len] = input.map_or(0., |input| input[i]);
+        }
+        self.delayed.copy_within(frames.., 0);
+        for i in 0..frames {
+            let t = (i + 1) as f32 / frames as f32;
+            // an extra frame of delay keeps the interpolation
+            // from reading past the input
+            let delay = 1. + from + (to - from) * t;
+            let position = (self.raw_pos + i + len) as f32 - delay;
+            let index = position.floor();
+            let frac = position - index;
+            let index = index as usize;
+            self.delayed[self.kernel_len - 1 + i] =
+                self.raw[index % len] * (1. - frac) + self.raw[(index + 1) % len] * frac;
+        }
+        self.raw_pos = (self.raw_pos + frames) % len;
+    }
+
+    /// Convolve this block's delayed input with a kernel
+    fn convolve(&self, kernel: &[f32], frame: usize) -> f32 {
+        let end = self.kernel_len + frame;
+        kernel
+            .iter()
+            .zip(self.delayed[frame..end].iter().rev())
+            .map(|(h, x)| h * x)
+            .sum()
+    }
+}
+
+/// State of the HRTF panning model
+///
+/// The HRIR is computed once per block. When it changes, the output of
+/// the previous and new kernels is crossfaded over the block, and the
+/// delays are ramped, to avoid discontinuities.
+///
+/// https://webaudio.github.io/web-audio-api/#Spatialization-hrtf-panning
+pub struct HrtfRenderer {
+    database: Arc<HrtfDatabase>,
+    current: Hrir,
+    next: Hrir,
+    /// Whether `current` has been computed yet
+    started: bool,
+    ears: [EarLine; 2],
+    /// How many more frames of output there are after the input
+    /// has gone silent
+    tail_frames_left: usize,
+}
+
+impl HrtfRenderer {
+    /// Load the HRIRs for the given sample rate, which is slow the first
+    /// time. None if the crate was built without the `hrtf` feature.
+    pub fn new(sample_rate: f32, frames_per_block: usize) -> Option<Self> {
+        let database = HrtfDatabase::for_sample_rate(sample_rate)?;
+        let (kernel_len, max_delay) = (database.kernel_len(), database.max_delay());
+        Some(HrtfRenderer {
+            database,
+            current: Hrir::new(kernel_len),
+            next: Hrir::new(kernel_len),
+            started: false,
+            ears: [
+                EarLine::new(kernel_len, max_delay, frames_per_block),
+                EarLine::new(kernel_len, max_delay, frames_per_block),
+            ],
+            tail_frames_left: 0,
+        })
+    }
+
+    fn tail_frames(&self) -> usize {
+        self.database.kernel_len() + self.database.max_delay().ceil() as usize + 1
+    }
+
+    /// Spatialize a block, producing a stereo block
+    fn process(&mut self, input: &Block, azimuth: f32, elevation: f32, gain: f32) -> Block {
+        let frames = input.frames();
+        if input.is_silence() {
+            if self.tail_frames_left == 0 {
+                let mut silence = Block::silence(frames);
+                silence.mix(2, ChannelInterpretation::Discrete);
+                return silence;
+            }
+            self.tail_frames_left = self.tail_frames_left.saturating_sub(frames);
+        } else {
+            self.tail_frames_left = self.tail_frames();
+        }
+
+        self.database
+            .interpolate(azimuth, elevation, &mut self.next);
+        if !self.started {
+            self.current.clone_from(&self.next);
+            self.started = true;
+        }
+
+        for (ear, line) in self.ears.iter_mut().enumerate() {
+            let data = if input.is_silence() {
+                None
+            } else {
+                // mono inputs feed both ears, stereo inputs one per ear
+                Some(input.data_chan(ear.min(input.chan_count() as usize - 1) as u8))
+            };
+            let (from, to) = if ear == 0 {
+                (self.current.left_delay, self.next.left_delay)
+            } else {
+                (self.current.right_delay, self.next.right_delay)
+            };
+            line.push(data, from, to);
+        }
+
+        let mut output = Block::for_channels_explicit(2, frames);
+        let crossfade =
+            self.current.left != self.next.left || self.current.right != self.next.right;
+        for (ear, line) in self.ears.iter().enumerate() {
+            let (current, next) = if ear == 0 {
+                (&self.current.left, &self.next.left)
+            } else {
+                (&self.current.right, &self.next.right)
+            };
+            let out = output.data_chan_mut(ear as u8);
+            for (frame, sample) in out.iter_mut().enumerate() {
+                let mut value = line.convolve(next, frame);
+                if crossfade {
+                    let t = (frame + 1) as f32 / frames as f32;
+                    value = line.convolve(current, frame) * (1. - t) + value * t;
+                }
+                *sample = value * gain;
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.next);
+        output
+    }
 }
 
 impl PannerNode {
-    pub fn new(options: PannerNodeOptions, channel_info: ChannelInfo) -> Self {
-        if options.panning_model == PanningModel::HRTF {
-            log::warn!("HRTF requested but not supported")
+    pub fn new(
+        options: PannerNodeOptions,
+        channel_info: ChannelInfo,
+        sample_rate: f32,
+        frames_per_block: usize,
+    ) -> Self {
+        let hrtf = match options.panning_model {
+            PanningModel::HRTF => HrtfRenderer::new(sample_rate, frames_per_block).map(Box::new),
+            PanningModel::EqualPower => None,
+        };
+        if options.panning_model == PanningModel::HRTF && hrtf.is_none() {
+            log::warn!("HRTF panning needs the hrtf feature, using equal-power panning");
         }
         Self {
             channel_info,
-            panning_model: options.panning_model,
+            panning_model: if hrtf.is_some() {
+                PanningModel::HRTF
+            } else {
+                PanningModel::EqualPower
+            },
             distance_model: options.distance_model,
             position_x: Param::new(options.position_x),
             position_y: Param::new(options.position_y),
@@ -116,6 +303,8 @@ impl PannerNode {
             cone_outer_angle: options.cone_outer_angle,
             cone_outer_gain: options.cone_outer_gain,
             listener_data: None,
+            hrtf,
+            replaced: None,
         }
     }
 
@@ -202,7 +391,7 @@ impl PannerNode {
         if source_orientation == Vector3D::zero()
             || (self.cone_inner_angle == 360. && self.cone_outer_angle == 360.)
         {
-            return 0.;
+            return 1.;
         }
 
         let normalized_source_orientation = normalize_zero(source_orientation);
@@ -271,6 +460,13 @@ impl AudioNodeEngine for PannerNode {
         AudioNodeType::PannerNode
     }
 
+    fn tail_time(&self) -> Tick {
+        match (self.panning_model, &self.hrtf) {
+            (PanningModel::HRTF, Some(hrtf)) => Tick(hrtf.tail_frames() as u64),
+            _ => Tick(0),
+        }
+    }
+
     fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
         debug_assert!(inputs.len() == 1);
 
@@ -288,6 +484,22 @@ impl AudioNodeEngine for PannerNode {
                 self.rolloff_factor
             };
 
+        if self.panning_model == PanningModel::HRTF {
+            // The HRIR is only computed once per block, so the
+            // position is taken at the start of the block
+            self.update_parameters(info, Tick(0));
+            let data = listener_data.listener_data(Tick(0));
+            let (azimuth, elevation, distance) = self.azimuth_elevation_distance(data);
+            let distance_gain = self.distance_gain_fn()(self, distance, rolloff_factor);
+            let gain = (distance_gain * self.cone_gain(data)) as f32;
+            let hrtf = self
+                .hrtf
+                .as_mut()
+                .expect("HRTF panning comes with a renderer");
+            inputs.blocks[0] = hrtf.process(&inputs.blocks[0], azimuth, elevation, gain);
+            return inputs;
+        }
+
         {
             let block = &mut inputs.blocks[0];
 
@@ -304,8 +516,8 @@ impl AudioNodeEngine for PannerNode {
             let distance_gain_fn = self.distance_gain_fn();
 
             if self.panning_model == PanningModel::EqualPower {
-                let (l, r) = block.data_mut().split_at_mut(FRAMES_PER_BLOCK.0 as usize);
-                for frame in 0..FRAMES_PER_BLOCK.0 {
+                let (l, r) = block.data_mut().split_at_mut(info.frames_per_block);
+                for frame in 0..info.block_ticks().0 {
                     let frame = Tick(frame);
                     self.update_parameters(info, frame);
                     let data = listener_data.listener_data(frame);
@@ -383,6 +595,17 @@ impl AudioNodeEngine for PannerNode {
         }
     }
 
+    fn param_types(&self) -> &[ParamType] {
+        &[
+            ParamType::Position(ParamDir::X),
+            ParamType::Position(ParamDir::Y),
+            ParamType::Position(ParamDir::Z),
+            ParamType::Orientation(ParamDir::X),
+            ParamType::Orientation(ParamDir::Y),
+            ParamType::Orientation(ParamDir::Z),
+        ]
+    }
+
     fn set_listenerdata(&mut self, data: Block) {
         self.listener_data = Some(data);
     }
@@ -390,11 +613,17 @@ impl AudioNodeEngine for PannerNode {
     fn message_specific(&mut self, message: AudioNodeMessage, _sample_rate: f32) {
         match message {
             AudioNodeMessage::PannerNode(p) => match p {
-                PannerNodeMessage::SetPanningModel(p) => {
-                    if p == PanningModel::HRTF {
-                        log::warn!("HRTF requested but not supported");
+                // Only switching to HRTF panning with a renderer is allowed
+                PannerNodeMessage::SetPanningModel(PanningModel::HRTF) => {},
+                PannerNodeMessage::SetPanningModel(p) => self.panning_model = p,
+                PannerNodeMessage::SetHrtfRenderer(renderer) => {
+                    self.panning_model = PanningModel::HRTF;
+                    // Keep the state of the renderer in use, if any
+                    if self.hrtf.is_some() {
+                        self.replaced = Some(renderer);
+                    } else {
+                        self.hrtf = Some(renderer);
                     }
-                    self.panning_model = p;
                 },
                 PannerNodeMessage::SetDistanceModel(d) => self.distance_model = d,
                 PannerNodeMessage::SetRefDistance(val) => self.ref_distance = val,
@@ -407,4 +636,60 @@ impl AudioNodeEngine for PannerNode {
             _ => (),
         }
     }
+
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        self.replaced
+            .take()
+            .map(|renderer| Garbage::Boxed(renderer as Box<dyn Send>))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panner(panning_model: PanningModel) -> PannerNode {
+        let options = PannerNodeOptions {
+            panning_model,
+            ..Default::default()
+        };
+        PannerNode::new(options, ChannelInfo::default(), 44100., 128)
+    }
+
+    #[test]
+    fn equal_power_panners_do_without_hrirs() {
+        assert!(panner(PanningModel::EqualPower).hrtf.is_none());
+        // Falls back to equal-power panning without the HRIRs
+        let node = panner(PanningModel::HRTF);
+        assert_eq!(node.hrtf.is_some(), cfg!(feature = "hrtf"));
+        assert_eq!(
+            node.panning_model == PanningModel::HRTF,
+            cfg!(feature = "hrtf")
+        );
+    }
+
+    #[cfg(feature = "hrtf")]
+    #[test]
+    fn switching_to_hrtf_keeps_the_renderer_in_use() {
+        let mut node = panner(PanningModel::EqualPower);
+        let switch = || {
+            let renderer = HrtfRenderer::new(44100., 128).unwrap();
+            let msg = PannerNodeMessage::SetHrtfRenderer(Box::new(renderer));
+            AudioNodeMessage::PannerNode(msg)
+        };
+        node.message_specific(switch(), 44100.);
+        assert_eq!(node.panning_model, PanningModel::HRTF);
+        assert!(node.hrtf.is_some() && node.take_garbage().is_none());
+
+        node.message_specific(
+            AudioNodeMessage::PannerNode(PannerNodeMessage::SetPanningModel(
+                PanningModel::EqualPower,
+            )),
+            44100.,
+        );
+        assert_eq!(node.panning_model, PanningModel::EqualPower);
+        node.message_specific(switch(), 44100.);
+        assert_eq!(node.panning_model, PanningModel::HRTF);
+        assert!(node.take_garbage().is_some());
+    }
 }
@@ -1,5 +1,4 @@
 use crate::block::Block;
-use crate::block::FRAMES_PER_BLOCK_USIZE;
 use crate::block::Tick;
 use crate::node::BlockInfo;
 
@@ -16,6 +15,14 @@ pub enum ParamType {
     Up(ParamDir),
     Orientation(ParamDir),
     Offset,
+    DelayTime,
+    Threshold,
+    Knee,
+    Ratio,
+    Attack,
+    Release,
+    /// A param declared by an AudioWorkletProcessor, by index
+    Custom(u32),
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
@@ -189,6 +196,11 @@ impl Param {
         self.val + self.block_mix_val
     }
 
+    /// Whether the value may change without new events being inserted
+    pub(crate) fn is_automated(&self) -> bool {
+        self.current_event < self.events.len() || self.dirty
+    }
+
     pub fn set_rate(&mut self, rate: ParamRate) {
         self.kind = rate;
     }
@@ -246,19 +258,19 @@ impl Param {
     ///
     /// Only for use with AudioListener.
     ///
-    /// Invariant: `block` must be a FRAMES_PER_BLOCK length array filled with silence
+    /// Invariant: `block` must be a block length array filled with silence
     pub(crate) fn flush_to_block(&mut self, info: &BlockInfo, block: &mut [f32]) {
         // common case
         if self.current_event >= self.events.len() && self.blocks.is_empty() {
             if self.val != 0. {
-                for tick in 0..(FRAMES_PER_BLOCK_USIZE) {
+                for tick in 0..block.len() {
                     // ideally this can use some kind of vectorized memset()
                     block[tick] = self.val;
                 }
             }
         // if the value is zero, our buffer is already zeroed
         } else {
-            for tick in 0..(FRAMES_PER_BLOCK_USIZE) {
+            for tick in 0..block.len() {
                 self.update(info, Tick(tick as u64));
                 block[tick] = self.val;
             }
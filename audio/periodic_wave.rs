@@ -0,0 +1,234 @@
+use crate::fft::Fft;
+use crate::oscillator_node::OscillatorType;
+use num_complex::Complex32;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// The number of samples in a single period of every wavetable
+const TABLE_SIZE: usize = 4096;
+
+/// The number of wavetables, each with half the partials of the previous one
+const TABLE_COUNT: usize = 12;
+
+/// This specifies options for constructing a PeriodicWave.
+///
+/// https://webaudio.github.io/web-audio-api/#dictdef-periodicwaveoptions
+#[derive(Clone, Debug, Default)]
+pub struct PeriodicWaveOptions {
+    /// Cosine terms. The first (DC) term is ignored.
+    pub real: Vec<f32>,
+    /// Sine terms. The first term is ignored.
+    pub imag: Vec<f32>,
+    /// If false, the waveform is scaled to have a peak of 1.
+    pub disable_normalization: bool,
+}
+
+/// A waveform for OscillatorNodes to play.
+///
+/// The waveform is stored as a set of wavetables, one for each octave,
+/// so that the oscillator can pick one with no partials above the
+/// Nyquist frequency. Cheap to clone: the tables are shared.
+///
+/// https://webaudio.github.io/web-audio-api/#PeriodicWave
+#[derive(Clone)]
+pub struct PeriodicWave {
+    /// Table `k` contains the first `TABLE_SIZE / 2 >> k` partials, up to
+    /// the last one below `TABLE_SIZE / 2`
+    tables: Arc<Vec<Vec<f32>>>,
+}
+
+impl fmt::Debug for PeriodicWave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PeriodicWave")
+            .field("tables", &self.tables.len())
+            .finish()
+    }
+}
+
+impl PeriodicWave {
+    pub fn new(options: PeriodicWaveOptions) -> Self {
+        let PeriodicWaveOptions {
+            mut real,
+            mut imag,
+            disable_normalization,
+        } = options;
+        if real.is_empty() && imag.is_empty() {
+            // The default wave is a sine
+            real = vec![0., 0.];
+            imag = vec![0., 1.];
+        } else if real.is_empty() {
+            real = vec![0.; imag.len()];
+        } else if imag.is_empty() {
+            imag = vec![0.; real.len()];
+        }
+        assert!(
+            real.len() == imag.len() && real.len() >= 2,
+            "IndexSizeError: real and imag must have the same length, of at least 2"
+        );
+
+        let mut fft = Fft::new(TABLE_SIZE);
+        let mut bins = vec![Complex32::new(0., 0.); fft.real_bins()];
+        let mut tables = Vec::with_capacity(TABLE_COUNT);
+        for k in 0..TABLE_COUNT {
+            // The highest partial of the table. The bin at TABLE_SIZE / 2
+            // is never used, and every table keeps the fundamental.
+            let partials = ((TABLE_SIZE / 2) >> k)
+                .min(TABLE_SIZE / 2 - 1)
+                .min(real.len() - 1);
+            bins.fill(Complex32::new(0., 0.));
+            // bin n of the inverse transform is the partial with n periods
+            // per table
+            let scale = TABLE_SIZE as f32 / 2.;
+            for n in 1..=partials {
+                bins[n] = Complex32::new(real[n], -imag[n]) * scale;
+            }
+            let mut table = vec![0.; TABLE_SIZE];
+            fft.inverse_real(&bins, &mut table);
+            tables.push(table);
+        }
+
+        if !disable_normalization {
+            // normalize with the peak of the full-band waveform
+            let peak = tables[0].iter().fold(0., |max: f32, s| max.max(s.abs()));
+            if peak > 0. {
+                for table in &mut tables {
+                    for sample in table.iter_mut() {
+                        *sample /= peak;
+                    }
+                }
+            }
+        }
+
+        PeriodicWave {
+            tables: Arc::new(tables),
+        }
+    }
+
+    /// The wave for one of the built-in oscillator types. The waves are
+    /// computed once, the first time one of them is needed, and shared.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#oscillator-coefficients
+    pub fn for_type(oscillator_type: &OscillatorType) -> Self {
+        static BUILT_IN: OnceLock<[PeriodicWave; 4]> = OnceLock::new();
+        let [sine, square, sawtooth, triangle] = BUILT_IN.get_or_init(|| {
+            [
+                OscillatorType::Sine,
+                OscillatorType::Square,
+                OscillatorType::Sawtooth,
+                OscillatorType::Triangle,
+            ]
+            .map(|oscillator_type| Self::compute_for_type(&oscillator_type))
+        });
+        match *oscillator_type {
+            OscillatorType::Sine => sine.clone(),
+            OscillatorType::Square => square.clone(),
+            OscillatorType::Sawtooth => sawtooth.clone(),
+            OscillatorType::Triangle => triangle.clone(),
+            OscillatorType::Custom => {
+                panic!("InvalidStateError: custom oscillators need a PeriodicWave")
+            },
+        }
+    }
+
+    fn compute_for_type(oscillator_type: &OscillatorType) -> Self {
+        let partials = TABLE_SIZE / 2;
+        let imag = (0..partials)
+            .map(|n| {
+                if n == 0 {
+                    return 0.;
+                }
+                let n = n as f32;
+                match *oscillator_type {
+                    OscillatorType::Sine => {
+                        if n == 1. {
+                            1.
+                        } else {
+                            0.
+                        }
+                    },
+                    OscillatorType::Square => 2. / (n * PI) * (1. - (-1f32).powf(n)),
+                    OscillatorType::Sawtooth => (-1f32).powf(n + 1.) * 2. / (n * PI),
+                    OscillatorType::Triangle => 8. * (n * PI / 2.).sin() / (n * PI).powi(2),
+                    OscillatorType::Custom => unreachable!(),
+                }
+            })
+            .collect();
+        PeriodicWave::new(PeriodicWaveOptions {
+            real: vec![0.; partials],
+            imag,
+            disable_normalization: false,
+        })
+    }
+
+    /// Pick the wavetable with the most partials that has none above the
+    /// Nyquist frequency when played at `frequency`
+    pub(crate) fn table_for(&self, frequency: f32, sample_rate: f32) -> usize {
+        let nyquist = sample_rate / 2.;
+        let highest = (TABLE_SIZE / 2) as f32 * frequency.abs();
+        if highest <= nyquist {
+            return 0;
+        }
+        ((highest / nyquist).log2().ceil() as usize).min(TABLE_COUNT - 1)
+    }
+
+    /// The value of the wave in table `table`, at `phase` in [0, 1)
+    pub(crate) fn sample(&self, table: usize, phase: f64) -> f32 {
+        let table = &self.tables[table];
+        let position = phase * TABLE_SIZE as f64;
+        let index = position.floor();
+        let frac = (position - index) as f32;
+        let index = index as usize % TABLE_SIZE;
+        let next = (index + 1) % TABLE_SIZE;
+        table[index] * (1. - frac) + table[next] * frac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(wave: &PeriodicWave, table: usize) -> f32 {
+        wave.tables[table]
+            .iter()
+            .fold(0., |max: f32, s| max.max(s.abs()))
+    }
+
+    #[test]
+    fn every_table_keeps_the_fundamental() {
+        for oscillator_type in [
+            OscillatorType::Square,
+            OscillatorType::Sawtooth,
+            OscillatorType::Triangle,
+        ] {
+            let wave = PeriodicWave::for_type(&oscillator_type);
+            for table in 0..TABLE_COUNT {
+                assert!(
+                    peak(&wave, table) > 0.1,
+                    "{oscillator_type:?} table {table}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn high_frequencies_play_a_band_limited_wave() {
+        let wave = PeriodicWave::for_type(&OscillatorType::Sawtooth);
+        let sample_rate = 48000.;
+        for frequency in [13000., 20000., 23000.] {
+            let table = wave.table_for(frequency, sample_rate);
+            assert_eq!(table, TABLE_COUNT - 1);
+            // Only the fundamental is left, below the Nyquist frequency
+            let highest = ((TABLE_SIZE / 2) >> table) as f32 * frequency;
+            assert!(highest < sample_rate / 2.);
+            assert!(peak(&wave, table) > 0.1);
+        }
+    }
+
+    #[test]
+    fn built_in_waves_are_shared() {
+        let a = PeriodicWave::for_type(&OscillatorType::Square);
+        let b = PeriodicWave::for_type(&OscillatorType::Square);
+        assert!(Arc::ptr_eq(&a.tables, &b.tables));
+    }
+}
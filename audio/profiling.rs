@@ -0,0 +1,112 @@
+use crate::graph::NodeId;
+use crate::node::AudioNodeType;
+use std::time::Duration;
+
+/// How much of the real-time budget rendering used over an interval.
+///
+/// https://webaudio.github.io/web-audio-api/#AudioRenderCapacityEvent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderCapacityReport {
+    /// The context time at the end of the interval
+    pub timestamp: f64,
+    /// The average time taken to render a block, relative to its duration
+    pub average_load: f64,
+    /// The highest time taken to render a block, relative to its duration
+    pub peak_load: f64,
+    /// The fraction of blocks which were late: they took longer to render
+    /// than they last, or the sink had run out of audio before them
+    pub underrun_ratio: f64,
+}
+
+/// Time spent processing a node, since profiling was enabled.
+#[derive(Clone, Debug)]
+pub struct NodeProfile {
+    pub id: NodeId,
+    pub node_type: AudioNodeType,
+    /// Number of blocks the node processed
+    pub blocks: u64,
+    /// Time spent processing all of them
+    pub total_time: Duration,
+    /// The longest time spent processing one
+    pub peak_time: Duration,
+}
+
+/// Processing time of a single node
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct NodeTiming {
+    pub blocks: u64,
+    pub total_time: Duration,
+    pub peak_time: Duration,
+}
+
+impl NodeTiming {
+    /// Nodes breaking a cycle are processed in two steps per block,
+    /// only the first one starts a block
+    pub fn record(&mut self, time: Duration, starts_block: bool) {
+        if starts_block {
+            self.blocks += 1;
+        }
+        self.total_time += time;
+        self.peak_time = self.peak_time.max(time);
+    }
+}
+
+/// Aggregates render times into `RenderCapacityReport`s
+///
+/// https://webaudio.github.io/web-audio-api/#AudioRenderCapacity
+pub(crate) struct RenderCapacity {
+    /// Number of blocks between reports
+    update_interval: u64,
+    /// Duration of a block, in seconds
+    block_duration: f64,
+    blocks: u64,
+    total_load: f64,
+    peak_load: f64,
+    underruns: u64,
+}
+
+impl RenderCapacity {
+    pub fn new(update_interval: f64, sample_rate: f32, frames_per_block: usize) -> Self {
+        let block_duration = frames_per_block as f64 / sample_rate as f64;
+        Self {
+            update_interval: ((update_interval / block_duration).round() as u64).max(1),
+            block_duration,
+            blocks: 0,
+            total_load: 0.,
+            peak_load: 0.,
+            underruns: 0,
+        }
+    }
+
+    /// Account for a rendered block, returning a report at the end of
+    /// each interval
+    pub fn record(
+        &mut self,
+        render_time: Duration,
+        starved: bool,
+        timestamp: f64,
+    ) -> Option<RenderCapacityReport> {
+        let load = render_time.as_secs_f64() / self.block_duration;
+        self.blocks += 1;
+        self.total_load += load;
+        self.peak_load = self.peak_load.max(load);
+        if starved || load > 1. {
+            self.underruns += 1;
+        }
+        if self.blocks < self.update_interval {
+            return None;
+        }
+
+        let report = RenderCapacityReport {
+            timestamp,
+            average_load: self.total_load / self.blocks as f64,
+            peak_load: self.peak_load,
+            underrun_ratio: self.underruns as f64 / self.blocks as f64,
+        };
+        self.blocks = 0;
+        self.total_load = 0.;
+        self.peak_load = 0.;
+        self.underruns = 0;
+        Some(report)
+    }
+}
@@ -0,0 +1,238 @@
+//! A bounded, lock-free, single-producer single-consumer queue.
+//!
+//! Pushing and popping never allocate, free memory or take a lock, which
+//! makes the queue usable from the render thread. The storage is allocated
+//! when the queue is created.
+//!
+//! A consumer with nothing to pop can park its thread: the producer
+//! unparks it after each push, and once dropped.
+
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::Thread;
+
+struct Shared<T> {
+    /// A power of two slots, so that positions map to the same slot on
+    /// either side of wrapping around
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// How many values the queue holds at most
+    capacity: usize,
+    /// Position of the next value to pop, only written by the consumer
+    head: AtomicUsize,
+    /// Position of the next value to push, only written by the producer
+    tail: AtomicUsize,
+    /// Set once either end has been dropped
+    closed: AtomicBool,
+}
+
+// The producer and the consumer never access the same slot at once:
+// a slot belongs to the producer until `tail` moves past it, then to the
+// consumer until `head` moves past it.
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position & (self.slots.len() - 1)].get()
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// The sending end of a queue. It can be moved to another thread, but
+/// not shared between threads.
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    /// The thread popping values, if it parks while waiting for them
+    consumer: Option<Thread>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// The receiving end of a queue. It can be moved to another thread, but
+/// not shared between threads.
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// Create a queue holding at most `capacity` values
+pub fn queue<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0);
+    let shared = Arc::new(Shared {
+        slots: (0..capacity.next_power_of_two())
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        capacity,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+            consumer: None,
+            _not_sync: PhantomData,
+        },
+        Consumer {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Producer<T> {
+    /// Unpark the given thread whenever a value is pushed
+    pub fn set_consumer_thread(&mut self, thread: Thread) {
+        self.consumer = Some(thread);
+    }
+
+    /// Append a value, or hand it back if the queue is full or the
+    /// consumer is gone
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        if shared.closed.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == shared.capacity {
+            return Err(value);
+        }
+        unsafe { (*shared.slot(tail)).write(value) };
+        shared.tail.store(tail.wrapping_add(1), Ordering::Release);
+        if let Some(ref consumer) = self.consumer {
+            consumer.unpark();
+        }
+        Ok(())
+    }
+
+    /// Whether the consumer has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Take the oldest value, if any
+    pub fn pop(&self) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { (*shared.slot(head)).assume_init_read() };
+        shared.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Whether the producer has been dropped. Values pushed before that
+    /// can still be popped.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        if let Some(ref consumer) = self.consumer {
+            consumer.unpark();
+        }
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    /// A queue whose positions are about to wrap around
+    fn queue_at<T>(capacity: usize, position: usize) -> (Producer<T>, Consumer<T>) {
+        let (producer, consumer) = queue(capacity);
+        producer.shared.head.store(position, Ordering::Relaxed);
+        producer.shared.tail.store(position, Ordering::Relaxed);
+        (producer, consumer)
+    }
+
+    #[test]
+    fn full_and_empty() {
+        let (producer, consumer) = queue(2);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(producer.push(3), Ok(()));
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn positions_wrap_around() {
+        for capacity in 1..=5 {
+            let (producer, consumer) = queue_at(capacity, usize::MAX - 2);
+            let mut popped = 0;
+            for value in 0..4 * capacity {
+                if producer.push(value).is_err() {
+                    assert_eq!(consumer.pop(), Some(popped));
+                    popped += 1;
+                    assert_eq!(producer.push(value), Ok(()));
+                }
+            }
+            while let Some(value) = consumer.pop() {
+                assert_eq!(value, popped);
+                popped += 1;
+            }
+            assert_eq!(popped, 4 * capacity);
+        }
+    }
+
+    #[test]
+    fn values_left_in_the_queue_are_dropped() {
+        let value = Rc::new(());
+        {
+            let (producer, consumer) = queue_at(3, usize::MAX - 1);
+            for _ in 0..3 {
+                assert!(producer.push(value.clone()).is_ok());
+            }
+            drop(consumer.pop());
+            assert_eq!(Rc::strong_count(&value), 3);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn closed_once_either_end_drops() {
+        let (producer, consumer) = queue::<u8>(1);
+        assert!(!producer.is_closed() && !consumer.is_closed());
+        drop(consumer);
+        assert!(producer.is_closed());
+        assert_eq!(producer.push(1), Err(1));
+
+        let (producer, consumer) = queue(1);
+        assert_eq!(producer.push(1), Ok(()));
+        drop(producer);
+        assert!(consumer.is_closed());
+        assert_eq!(consumer.pop(), Some(1));
+    }
+}
@@ -1,37 +1,75 @@
+use crate::AudioBackend;
 use crate::analyser_node::AnalyserNode;
 use crate::biquad_filter_node::BiquadFilterNode;
-use crate::block::{Chunk, FRAMES_PER_BLOCK, Tick};
+use crate::block::{Chunk, Tick};
 use crate::buffer_source_node::AudioBufferSourceNode;
 use crate::channel_node::{ChannelMergerNode, ChannelSplitterNode};
 use crate::constant_source_node::ConstantSourceNode;
-use crate::context::{AudioContextOptions, ProcessingState, StateChangeResult};
+use crate::context::{
+    AudioContextEvent, AudioContextOptions, AudioSinkId, AudioTimestamp, LatencyCategory,
+    ProcessingState, PublishedState, StateChangeResult, SuspendError,
+};
+use crate::convolver_node::ConvolverNode;
+use crate::delay_node::DelayNode;
+use crate::dynamics_compressor_node::DynamicsCompressorNode;
 use crate::gain_node::GainNode;
-use crate::graph::{AudioGraph, InputPort, NodeId, OutputPort, PortId};
+use crate::graph::{AudioGraph, GraphSnapshot, InputPort, NodeId, OutputPort, PortId};
 use crate::iir_filter_node::IIRFilterNode;
 use crate::media_element_source_node::MediaElementSourceNode;
 use crate::media_stream_destination_node::MediaStreamDestinationNode;
 use crate::media_stream_source_node::MediaStreamSourceNode;
 use crate::node::{AudioNodeEngine, AudioNodeInit, AudioNodeMessage};
-use crate::node::{BlockInfo, ChannelInfo};
-use crate::offline_sink::OfflineAudioSink;
+use crate::node::{BlockInfo, ChannelInfo, Garbage};
+use crate::null_sink::NullAudioSink;
+use crate::offline_sink::{OfflineAudioSink, OfflineStreamCallback};
 use crate::oscillator_node::OscillatorNode;
 use crate::panner_node::PannerNode;
-use crate::sink::{AudioSink, AudioSinkError};
+use crate::param::{AutomationEvent, UserAutomationEvent};
+use crate::profiling::{NodeProfile, RenderCapacity};
+use crate::queue::Consumer;
+use crate::script_processor_node::ScriptProcessorNode;
+use crate::sink::{AudioSink, AudioSinkError, AudioSinkLatency};
 use crate::stereo_panner::StereoPannerNode;
+use crate::transaction::GraphCommand;
 use crate::wave_shaper_node::WaveShaperNode;
-use crate::{AudioBackend, AudioStreamReader};
-use servo_media_streams::{MediaSocket, MediaStreamId};
-use std::sync::mpsc::{Receiver, Sender};
+use crate::worklet_node::AudioWorkletNode;
+use servo_media_streams::MediaSocket;
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Sender, SyncSender};
+use std::thread::{self, Thread};
+use std::time::Instant;
 
+/// How many batches of scheduled commands, and how many suspensions,
+/// can be pending before scheduling more allocates on the render thread
+const SCHEDULED_CAPACITY: usize = 256;
+
+/// Messages from the control thread to the render thread.
+///
+/// They go through a lock-free queue, and the ones expecting a reply
+/// carry a channel with room for it, so that handling them doesn't
+/// allocate on the render thread.
 pub enum AudioRenderThreadMsg {
-    CreateNode(AudioNodeInit, Sender<NodeId>, ChannelInfo),
+    AddNode(NewNode),
     ConnectPorts(PortId<OutputPort>, PortId<InputPort>),
     MessageNode(NodeId, AudioNodeMessage),
-    Resume(Sender<StateChangeResult>),
-    Suspend(Sender<StateChangeResult>),
-    Close(Sender<StateChangeResult>),
-    SinkNeedData,
-    GetCurrentTime(Sender<f64>),
+    Resume(SyncSender<StateChangeResult>),
+    Suspend(SyncSender<StateChangeResult>),
+    SuspendAt(Tick, SyncSender<()>, SyncSender<Result<(), SuspendError>>),
+    Close(SyncSender<StateChangeResult>),
+    GetLatency(SyncSender<AudioSinkLatency>),
+    GetOutputTimestamp(SyncSender<AudioTimestamp>),
+    SetSink(Box<dyn AudioSink>, SyncSender<Result<(), AudioSinkError>>),
+    SetEventSender(Sender<AudioContextEvent>),
+    ScheduleCommands(f64, Vec<GraphCommand>),
+    GetGraphSnapshot(SyncSender<GraphSnapshot>),
+    StartRenderCapacity(f64),
+    StopRenderCapacity,
+    SetNodeProfiling(bool),
+    GetNodeProfiles(SyncSender<Vec<NodeProfile>>),
+    ReleaseNode(NodeId),
 
     DisconnectAllFrom(NodeId),
     DisconnectOutput(PortId<OutputPort>),
@@ -41,10 +79,122 @@ pub enum AudioRenderThreadMsg {
     DisconnectOutputBetweenTo(PortId<OutputPort>, PortId<InputPort>),
 
     SetSinkEosCallback(Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>),
+    SetSinkStreamCallback(
+        usize,
+        OfflineStreamCallback,
+        SyncSender<Result<(), AudioSinkError>>,
+    ),
 
     SetMute(bool),
 }
 
+/// Wakes the render thread up while it waits for control messages, or
+/// for its sink to need more data.
+#[derive(Clone)]
+pub struct RenderThreadWaker(Thread);
+
+impl RenderThreadWaker {
+    pub(crate) fn new(thread: Thread) -> Self {
+        RenderThreadWaker(thread)
+    }
+
+    pub fn wake(&self) {
+        self.0.unpark();
+    }
+}
+
+/// A node built on the control thread, with an id allocated there,
+/// for the render thread to add to the graph
+pub struct NewNode {
+    id: NodeId,
+    engine: Box<dyn AudioNodeEngine>,
+    needs_listener: bool,
+    is_dest: bool,
+}
+
+impl NewNode {
+    pub(crate) fn new<B: AudioBackend>(
+        id: NodeId,
+        node_type: AudioNodeInit,
+        ch: ChannelInfo,
+        sample_rate: f32,
+        frames_per_block: usize,
+        offline: bool,
+    ) -> Self {
+        let mut needs_listener = false;
+        let mut is_dest = false;
+        let engine: Box<dyn AudioNodeEngine> = match node_type {
+            AudioNodeInit::AnalyserNode(engine) => Box::new(AnalyserNode::new(engine, ch)),
+            AudioNodeInit::AudioBufferSourceNode(options) => {
+                Box::new(AudioBufferSourceNode::new(options, ch))
+            },
+            AudioNodeInit::BiquadFilterNode(options) => {
+                Box::new(BiquadFilterNode::new(options, ch, sample_rate))
+            },
+            AudioNodeInit::ConvolverNode(options) => {
+                Box::new(ConvolverNode::new(options, ch, frames_per_block))
+            },
+            AudioNodeInit::DelayNode(options) => {
+                Box::new(DelayNode::new(options, ch, sample_rate, frames_per_block))
+            },
+            AudioNodeInit::DynamicsCompressionNode(options) => {
+                Box::new(DynamicsCompressorNode::new(options, ch, sample_rate))
+            },
+            AudioNodeInit::GainNode(options) => {
+                Box::new(GainNode::new(options, ch, frames_per_block))
+            },
+            AudioNodeInit::StereoPannerNode(options) => {
+                Box::new(StereoPannerNode::new(options, ch))
+            },
+            AudioNodeInit::PannerNode(options) => {
+                needs_listener = true;
+                Box::new(PannerNode::new(options, ch, sample_rate, frames_per_block))
+            },
+            AudioNodeInit::MediaStreamSourceNode(id) => {
+                let reader = B::make_streamreader(id, sample_rate, frames_per_block);
+                Box::new(MediaStreamSourceNode::new(reader, ch))
+            },
+            AudioNodeInit::OscillatorNode(options) => Box::new(OscillatorNode::new(options, ch)),
+            AudioNodeInit::ChannelMergerNode(options) => {
+                Box::new(ChannelMergerNode::new(options, ch))
+            },
+            AudioNodeInit::ConstantSourceNode(options) => {
+                Box::new(ConstantSourceNode::new(options, ch))
+            },
+            AudioNodeInit::MediaStreamDestinationNode(socket) => {
+                is_dest = true;
+                Box::new(MediaStreamDestinationNode::new(
+                    socket,
+                    sample_rate,
+                    Box::new(B::make_sink(None).unwrap()),
+                    ch,
+                ))
+            },
+            AudioNodeInit::ScriptProcessorNode(options) => Box::new(ScriptProcessorNode::new(
+                options,
+                ch,
+                sample_rate,
+                frames_per_block,
+                offline,
+            )),
+            AudioNodeInit::ChannelSplitterNode => Box::new(ChannelSplitterNode::new(ch)),
+            AudioNodeInit::WaveShaperNode(options) => Box::new(WaveShaperNode::new(options, ch)),
+            AudioNodeInit::MediaElementSourceNode => Box::new(MediaElementSourceNode::new(ch)),
+            AudioNodeInit::IIRFilterNode(options) => Box::new(IIRFilterNode::new(options, ch)),
+            AudioNodeInit::AudioWorkletNode(options) => {
+                Box::new(AudioWorkletNode::new(options, ch, frames_per_block))
+            },
+            _ => unimplemented!(),
+        };
+        NewNode {
+            id,
+            engine,
+            needs_listener,
+            is_dest,
+        }
+    }
+}
+
 pub enum Sink {
     RealTime(Box<dyn AudioSink>),
     Offline(OfflineAudioSink),
@@ -54,11 +204,17 @@ impl AudioSink for Sink {
     fn init(
         &self,
         sample_rate: f32,
-        sender: Sender<AudioRenderThreadMsg>,
+        frames_per_block: usize,
+        latency_hint: LatencyCategory,
+        waker: RenderThreadWaker,
     ) -> Result<(), AudioSinkError> {
         match *self {
-            Sink::RealTime(ref sink) => sink.init(sample_rate, sender),
-            Sink::Offline(ref sink) => Ok(sink.init(sample_rate, sender).unwrap()),
+            Sink::RealTime(ref sink) => {
+                sink.init(sample_rate, frames_per_block, latency_hint, waker)
+            },
+            Sink::Offline(ref sink) => Ok(sink
+                .init(sample_rate, frames_per_block, latency_hint, waker)
+                .unwrap()),
         }
     }
 
@@ -90,7 +246,21 @@ impl AudioSink for Sink {
     fn push_data(&self, chunk: Chunk) -> Result<(), AudioSinkError> {
         match *self {
             Sink::RealTime(ref sink) => sink.push_data(chunk),
-            Sink::Offline(ref sink) => Ok(sink.push_data(chunk).unwrap()),
+            Sink::Offline(ref sink) => sink.push_data(chunk),
+        }
+    }
+
+    fn is_starved(&self) -> bool {
+        match *self {
+            Sink::RealTime(ref sink) => sink.is_starved(),
+            Sink::Offline(ref sink) => sink.is_starved(),
+        }
+    }
+
+    fn latency(&self) -> AudioSinkLatency {
+        match *self {
+            Sink::RealTime(ref sink) => sink.latency(),
+            Sink::Offline(ref sink) => sink.latency(),
         }
     }
 
@@ -105,16 +275,45 @@ impl AudioSink for Sink {
     }
 }
 
+/// Graph changes waiting for their sample-frame to be rendered
+pub struct ScheduledCommands {
+    frame: Tick,
+    commands: Vec<GraphCommand>,
+}
+
+/// A point at which an offline context suspends rendering by itself
+pub struct ScheduledSuspension {
+    frame: Tick,
+    /// Notified once rendering is suspended
+    notify: SyncSender<()>,
+}
+
 pub struct AudioRenderThread {
     pub graph: AudioGraph,
     pub sink: Sink,
-    pub sink_factory: Box<dyn Fn() -> Result<Box<dyn AudioSink + 'static>, AudioSinkError>>,
-    pub reader_factory: Box<dyn Fn(MediaStreamId, f32) -> Box<dyn AudioStreamReader + Send>>,
     pub state: ProcessingState,
+    /// `state`, for the control thread to read without a round trip
+    pub(crate) published_state: Arc<PublishedState>,
     pub sample_rate: f32,
+    /// The render quantum size
+    pub frames_per_block: usize,
     pub current_time: f64,
+    /// `current_time`, as bits of an f64, for the control thread to read
+    /// without a round trip
+    pub published_time: Arc<AtomicU64>,
     pub current_frame: Tick,
     pub muted: bool,
+    pub event_sender: Option<Sender<AudioContextEvent>>,
+    /// Whether the sink was starved when last checked
+    pub starved: bool,
+    /// Whether the last push into the sink failed
+    pub push_failed: bool,
+    /// Ordered by frame, then by arrival
+    pub scheduled: Vec<ScheduledCommands>,
+    /// Ordered by frame, at most one per frame
+    pub suspensions: Vec<ScheduledSuspension>,
+    /// Set while render capacity reports are requested
+    pub(crate) render_capacity: Option<RenderCapacity>,
 }
 
 impl AudioRenderThread {
@@ -122,150 +321,310 @@ impl AudioRenderThread {
     ///
     /// You must call .event_loop() on this to run it!
     fn prepare_thread<B: AudioBackend>(
-        sender: Sender<AudioRenderThreadMsg>,
         sample_rate: f32,
+        published_time: Arc<AtomicU64>,
+        published_state: Arc<PublishedState>,
         graph: AudioGraph,
         options: AudioContextOptions,
     ) -> Result<Self, AudioSinkError> {
-        let sink_factory = Box::new(|| B::make_sink().map(|s| Box::new(s) as Box<dyn AudioSink>));
-        let reader_factory = Box::new(|id, sample_rate| B::make_streamreader(id, sample_rate));
-        let sink = match options {
-            AudioContextOptions::RealTimeAudioContext(_) => Sink::RealTime(sink_factory()?),
-            AudioContextOptions::OfflineAudioContext(options) => Sink::Offline(
-                OfflineAudioSink::new(options.channels as usize, options.length),
+        let waker = RenderThreadWaker::new(thread::current());
+        let (sink, latency_hint, frames_per_block) = match options {
+            AudioContextOptions::RealTimeAudioContext(options) => (
+                Sink::RealTime(Self::make_sink::<B>(&options.sink_id)?),
+                options.latency_hint,
+                options.render_quantum_size,
+            ),
+            AudioContextOptions::OfflineAudioContext(options) => (
+                Sink::Offline(OfflineAudioSink::new(
+                    options.channels as usize,
+                    options.length,
+                )),
+                LatencyCategory::Playback,
+                options.render_quantum_size,
             ),
         };
 
-        sink.init(sample_rate, sender)?;
+        sink.init(sample_rate, frames_per_block, latency_hint, waker)?;
 
         Ok(Self {
             graph,
             sink,
-            sink_factory,
-            reader_factory,
             state: ProcessingState::Suspended,
+            published_state,
             sample_rate,
+            frames_per_block,
             current_time: 0.,
+            published_time,
             current_frame: Tick(0),
             muted: false,
+            event_sender: None,
+            starved: false,
+            push_failed: false,
+            scheduled: Vec::with_capacity(SCHEDULED_CAPACITY),
+            suspensions: Vec::with_capacity(SCHEDULED_CAPACITY),
+            render_capacity: None,
         })
     }
 
+    /// Make a sink playing to the audio output device with the given id.
+    /// Looking a device up can take a while, so this is only done before
+    /// rendering starts, or on the control thread.
+    pub(crate) fn make_sink<B: AudioBackend>(
+        sink_id: &AudioSinkId,
+    ) -> Result<Box<dyn AudioSink>, AudioSinkError> {
+        match *sink_id {
+            AudioSinkId::Default => B::make_sink(None).map(|s| Box::new(s) as Box<dyn AudioSink>),
+            AudioSinkId::Device(ref device_id) => {
+                B::make_sink(Some(device_id)).map(|s| Box::new(s) as Box<dyn AudioSink>)
+            },
+            AudioSinkId::None => Ok(Box::new(NullAudioSink::new())),
+        }
+    }
+
+    /// Replace the sink with one initialized on the control thread,
+    /// carrying over the processing state. Nothing changes if the new
+    /// sink can't be started.
+    ///
+    /// https://webaudio.github.io/web-audio-api/#sink-id-at-construction
+    fn set_sink(&mut self, sink: Box<dyn AudioSink>) -> Result<(), AudioSinkError> {
+        if let Sink::Offline(_) = self.sink {
+            self.graph.dispose(Garbage::Sink(sink));
+            return Err(AudioSinkError::NotSupported);
+        }
+        if self.state == ProcessingState::Running
+            && let Err(error) = sink.play()
+        {
+            self.graph.dispose(Garbage::Sink(sink));
+            return Err(error);
+        }
+        if let Sink::RealTime(old) = std::mem::replace(&mut self.sink, Sink::RealTime(sink)) {
+            let _ = old.stop();
+            self.graph.dispose(Garbage::Sink(old));
+        }
+        Ok(())
+    }
+
     /// Start the audio render thread
     ///
     /// In case something fails, it will instead start a thread with a dummy backend
-    pub fn start<B: AudioBackend>(
-        event_queue: Receiver<AudioRenderThreadMsg>,
-        sender: Sender<AudioRenderThreadMsg>,
+    pub(crate) fn start<B: AudioBackend>(
+        event_queue: Consumer<AudioRenderThreadMsg>,
         sample_rate: f32,
+        published_time: Arc<AtomicU64>,
+        published_state: Arc<PublishedState>,
         graph: AudioGraph,
         options: AudioContextOptions,
         init_sender: Sender<Result<(), AudioSinkError>>,
     ) {
-        let mut thread =
-            match Self::prepare_thread::<B>(sender.clone(), sample_rate, graph, options) {
-                Ok(thread) => {
-                    let _ = init_sender.send(Ok(()));
-                    thread
-                },
-                Err(e) => {
-                    let _ = init_sender.send(Err(e));
-                    return;
-                },
-            };
+        let mut thread = match Self::prepare_thread::<B>(
+            sample_rate,
+            published_time,
+            published_state,
+            graph,
+            options,
+        ) {
+            Ok(thread) => {
+                let _ = init_sender.send(Ok(()));
+                thread
+            },
+            Err(e) => {
+                let _ = init_sender.send(Err(e));
+                return;
+            },
+        };
 
-        thread.event_loop(event_queue);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| thread.event_loop(event_queue)));
+        if let Err(payload) = result {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown error".to_owned());
+            thread.send_event(AudioContextEvent::RenderThreadFailed(message));
+            thread.set_state(ProcessingState::Closed);
+            thread.send_event(AudioContextEvent::StateChanged(ProcessingState::Closed));
+        }
+    }
+
+    fn send_event(&self, event: AudioContextEvent) {
+        if let Some(ref sender) = self.event_sender {
+            let _ = sender.send(event);
+        }
+    }
+
+    fn set_state(&mut self, state: ProcessingState) {
+        self.state = state;
+        self.published_state.set(state);
     }
 
     make_render_thread_state_change!(resume, Running, play);
 
     make_render_thread_state_change!(suspend, Suspended, stop);
 
-    fn create_node(&mut self, node_type: AudioNodeInit, ch: ChannelInfo) -> NodeId {
-        let mut needs_listener = false;
-        let mut is_dest = false;
-        let node: Box<dyn AudioNodeEngine> = match node_type {
-            AudioNodeInit::AnalyserNode(sender) => Box::new(AnalyserNode::new(sender, ch)),
-            AudioNodeInit::AudioBufferSourceNode(options) => {
-                Box::new(AudioBufferSourceNode::new(options, ch))
-            },
-            AudioNodeInit::BiquadFilterNode(options) => {
-                Box::new(BiquadFilterNode::new(options, ch, self.sample_rate))
-            },
-            AudioNodeInit::GainNode(options) => Box::new(GainNode::new(options, ch)),
-            AudioNodeInit::StereoPannerNode(options) => {
-                Box::new(StereoPannerNode::new(options, ch))
-            },
-            AudioNodeInit::PannerNode(options) => {
-                needs_listener = true;
-                Box::new(PannerNode::new(options, ch))
-            },
-            AudioNodeInit::MediaStreamSourceNode(id) => {
-                let reader = (self.reader_factory)(id, self.sample_rate);
-                Box::new(MediaStreamSourceNode::new(reader, ch))
-            },
-            AudioNodeInit::OscillatorNode(options) => Box::new(OscillatorNode::new(options, ch)),
-            AudioNodeInit::ChannelMergerNode(options) => {
-                Box::new(ChannelMergerNode::new(options, ch))
-            },
-            AudioNodeInit::ConstantSourceNode(options) => {
-                Box::new(ConstantSourceNode::new(options, ch))
-            },
-            AudioNodeInit::MediaStreamDestinationNode(socket) => {
-                is_dest = true;
-                Box::new(MediaStreamDestinationNode::new(
-                    socket,
-                    self.sample_rate,
-                    (self.sink_factory)().unwrap(),
-                    ch,
-                ))
-            },
-            AudioNodeInit::ChannelSplitterNode => Box::new(ChannelSplitterNode::new(ch)),
-            AudioNodeInit::WaveShaperNode(options) => Box::new(WaveShaperNode::new(options, ch)),
-            AudioNodeInit::MediaElementSourceNode => Box::new(MediaElementSourceNode::new(ch)),
-            AudioNodeInit::IIRFilterNode(options) => Box::new(IIRFilterNode::new(options, ch)),
-            _ => unimplemented!(),
+    /// Suspend rendering once it reaches the given frame, which must be
+    /// a render quantum boundary, and is only supported by offline contexts
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-offlineaudiocontext-suspend
+    fn suspend_at(&mut self, frame: Tick, notify: SyncSender<()>) -> Result<(), SuspendError> {
+        let length = match self.sink {
+            Sink::Offline(ref sink) => sink.length() as u64,
+            Sink::RealTime(_) => return Err(SuspendError::NotSupported),
+        };
+        if frame <= self.current_frame || frame.0 >= length {
+            return Err(SuspendError::InvalidState);
+        }
+        let index = self.suspensions.partition_point(|s| s.frame < frame);
+        if self
+            .suspensions
+            .get(index)
+            .is_some_and(|s| s.frame == frame)
+        {
+            return Err(SuspendError::InvalidState);
+        }
+        self.suspensions
+            .insert(index, ScheduledSuspension { frame, notify });
+        Ok(())
+    }
+
+    /// Suspend rendering if it has reached a frame it was asked to
+    /// suspend at. Returns whether it did.
+    fn suspend_if_scheduled(&mut self) -> bool {
+        if self
+            .suspensions
+            .first()
+            .is_none_or(|s| s.frame != self.current_frame)
+        {
+            return false;
+        }
+        let suspension = self.suspensions.remove(0);
+        if self.suspend().is_err() {
+            return false;
+        }
+        let _ = suspension.notify.send(());
+        true
+    }
+
+    fn close(&mut self) -> StateChangeResult {
+        let result = if self.state == ProcessingState::Running {
+            self.sink.stop().map_err(|error| {
+                self.send_event(AudioContextEvent::SinkError(error));
+            })
+        } else {
+            Ok(())
         };
-        let id = self.graph.add_node(node);
-        if needs_listener {
+        self.set_state(ProcessingState::Closed);
+        self.send_event(AudioContextEvent::StateChanged(ProcessingState::Closed));
+        result
+    }
+
+    fn add_node(&mut self, node: NewNode) {
+        self.graph.add_node(node.id, node.engine);
+        if node.needs_listener {
             let listener = self.graph.listener_id().output(0);
-            self.graph.add_edge(listener, id.listener());
+            self.graph.add_edge(listener, node.id.listener());
         }
-        if is_dest {
-            self.graph.add_extra_dest(id);
+        if node.is_dest {
+            self.graph.add_extra_dest(node.id);
         }
-        id
     }
 
     fn connect_ports(&mut self, output: PortId<OutputPort>, input: PortId<InputPort>) {
         self.graph.add_edge(output, input)
     }
 
+    fn schedule_commands(&mut self, time: f64, commands: Vec<GraphCommand>) {
+        let frame = Tick::from_time(time, self.sample_rate);
+        let index = self.scheduled.partition_point(|s| s.frame <= frame);
+        self.scheduled
+            .insert(index, ScheduledCommands { frame, commands });
+    }
+
+    /// Apply the graph changes due during the next block, each from
+    /// its own sample-frame on
+    fn apply_scheduled_commands(&mut self) {
+        let block_end = self.current_frame + self.frames_per_block as u64;
+        while self
+            .scheduled
+            .first()
+            .is_some_and(|scheduled| scheduled.frame < block_end)
+        {
+            let mut scheduled = self.scheduled.remove(0);
+            let frame = cmp::max(scheduled.frame, self.current_frame);
+            self.graph
+                .set_mutation_offset((frame - self.current_frame).0 as usize);
+            for command in scheduled.commands.drain(..) {
+                self.apply_command(command, frame);
+            }
+            self.graph.dispose(Garbage::Commands(scheduled.commands));
+        }
+        self.graph.set_mutation_offset(0);
+    }
+
+    fn apply_command(&mut self, command: GraphCommand, frame: Tick) {
+        match command {
+            GraphCommand::ConnectPorts(from, to) => self.graph.add_edge(from, to),
+            GraphCommand::DisconnectAllFrom(id) => self.graph.disconnect_all_from(id),
+            GraphCommand::DisconnectOutput(out) => self.graph.disconnect_output(out),
+            GraphCommand::DisconnectBetween(from, to) => self.graph.disconnect_between(from, to),
+            GraphCommand::DisconnectTo(from, to) => self.graph.disconnect_to(from, to),
+            GraphCommand::DisconnectOutputBetween(from, to) => {
+                self.graph.disconnect_output_between(from, to)
+            },
+            GraphCommand::DisconnectOutputBetweenTo(from, to) => {
+                self.graph.disconnect_output_between_to(from, to)
+            },
+            GraphCommand::SetParam(id, param, event) => {
+                let event = match event {
+                    UserAutomationEvent::SetValue(value) => {
+                        AutomationEvent::SetValueAtTime(value, frame)
+                    },
+                    event => event.to_event(self.sample_rate),
+                };
+                self.graph.node_mut(id).get_param(param).insert_event(event);
+            },
+        }
+    }
+
     fn process(&mut self) -> Chunk {
+        self.apply_scheduled_commands();
+
         if self.muted {
-            return Chunk::explicit_silence();
+            return Chunk::explicit_silence(self.frames_per_block);
         }
 
         let info = BlockInfo {
             sample_rate: self.sample_rate,
             frame: self.current_frame,
             time: self.current_time,
+            frames_per_block: self.frames_per_block,
         };
         self.graph.process(&info)
     }
 
+    /// The context time of the sample-frame being played by the output
+    /// device right now
+    ///
+    /// https://webaudio.github.io/web-audio-api/#dom-audiocontext-getoutputtimestamp
+    fn output_timestamp(&self) -> AudioTimestamp {
+        let latency = self.sink.latency();
+        let performance_time = Instant::now();
+        let context_time = (self.current_time - latency.base - latency.output).max(0.);
+        AudioTimestamp {
+            context_time,
+            performance_time,
+        }
+    }
+
     fn set_mute(&mut self, val: bool) -> () {
         self.muted = val;
     }
 
-    fn event_loop(&mut self, event_queue: Receiver<AudioRenderThreadMsg>) {
+    fn event_loop(&mut self, event_queue: Consumer<AudioRenderThreadMsg>) {
         let sample_rate = self.sample_rate;
         let handle_msg = move |context: &mut Self, msg: AudioRenderThreadMsg| -> bool {
             let mut break_loop = false;
             match msg {
-                AudioRenderThreadMsg::CreateNode(node_type, tx, ch) => {
-                    let _ = tx.send(context.create_node(node_type, ch));
-                },
+                AudioRenderThreadMsg::AddNode(node) => context.add_node(node),
                 AudioRenderThreadMsg::ConnectPorts(output, input) => {
                     context.connect_ports(output, input);
                 },
@@ -275,19 +634,50 @@ impl AudioRenderThread {
                 AudioRenderThreadMsg::Suspend(tx) => {
                     let _ = tx.send(context.suspend());
                 },
+                AudioRenderThreadMsg::SuspendAt(frame, notify, tx) => {
+                    let _ = tx.send(context.suspend_at(frame, notify));
+                },
                 AudioRenderThreadMsg::Close(tx) => {
-                    let _ = tx.send(context.suspend());
+                    let _ = tx.send(context.close());
                     break_loop = true;
                 },
-                AudioRenderThreadMsg::GetCurrentTime(response) => {
-                    response.send(context.current_time).unwrap()
+                AudioRenderThreadMsg::GetLatency(response) => {
+                    let _ = response.send(context.sink.latency());
                 },
-                AudioRenderThreadMsg::MessageNode(id, msg) => {
-                    context.graph.node_mut(id).message(msg, sample_rate)
+                AudioRenderThreadMsg::GetOutputTimestamp(response) => {
+                    let _ = response.send(context.output_timestamp());
+                },
+                AudioRenderThreadMsg::SetSink(sink, response) => {
+                    let _ = response.send(context.set_sink(sink));
+                },
+                AudioRenderThreadMsg::SetEventSender(sender) => {
+                    context.event_sender = Some(sender);
+                },
+                AudioRenderThreadMsg::ScheduleCommands(time, commands) => {
+                    context.schedule_commands(time, commands);
+                },
+                AudioRenderThreadMsg::GetGraphSnapshot(response) => {
+                    let _ = response.send(context.graph.snapshot());
+                },
+                AudioRenderThreadMsg::StartRenderCapacity(update_interval) => {
+                    context.render_capacity = Some(RenderCapacity::new(
+                        update_interval,
+                        context.sample_rate,
+                        context.frames_per_block,
+                    ));
                 },
-                AudioRenderThreadMsg::SinkNeedData => {
-                    // Do nothing. This will simply unblock the thread so we
-                    // can restart the non-blocking event loop.
+                AudioRenderThreadMsg::StopRenderCapacity => {
+                    context.render_capacity = None;
+                },
+                AudioRenderThreadMsg::SetNodeProfiling(enabled) => {
+                    context.graph.set_profiling(enabled);
+                },
+                AudioRenderThreadMsg::GetNodeProfiles(response) => {
+                    let _ = response.send(context.graph.node_profiles());
+                },
+                AudioRenderThreadMsg::ReleaseNode(id) => context.graph.release_node(id),
+                AudioRenderThreadMsg::MessageNode(id, msg) => {
+                    context.graph.message_node(id, msg, sample_rate)
                 },
                 AudioRenderThreadMsg::DisconnectAllFrom(id) => {
                     context.graph.disconnect_all_from(id)
@@ -308,6 +698,13 @@ impl AudioRenderThread {
                 AudioRenderThreadMsg::SetSinkEosCallback(callback) => {
                     context.sink.set_eos_callback(callback);
                 },
+                AudioRenderThreadMsg::SetSinkStreamCallback(chunk_frames, callback, response) => {
+                    let result = match context.sink {
+                        Sink::Offline(ref sink) => sink.set_stream_callback(chunk_frames, callback),
+                        Sink::RealTime(_) => Err(AudioSinkError::NotSupported),
+                    };
+                    let _ = response.send(result);
+                },
                 AudioRenderThreadMsg::SetMute(val) => {
                     context.set_mute(val);
                 },
@@ -321,36 +718,66 @@ impl AudioRenderThread {
                 // If we are not processing audio or
                 // if we have already pushed enough data into the audio sink
                 // we wait for messages coming from the control thread or
-                // the audio sink. The audio sink will notify whenever it
-                // needs more data.
-                if let Ok(msg) = event_queue.recv() {
-                    if handle_msg(self, msg) {
-                        break;
-                    }
+                // the audio sink. Both wake the thread up: the control
+                // thread whenever it sends a message, the audio sink
+                // whenever it needs more data.
+                let closed = event_queue.is_closed();
+                match event_queue.pop() {
+                    Some(msg) => {
+                        if handle_msg(self, msg) {
+                            break;
+                        }
+                    },
+                    // The control thread is gone without closing the context
+                    None if closed => break,
+                    None => thread::park(),
                 }
             } else {
                 // If we have not pushed enough data into the audio sink yet,
                 // we process the control message queue
-                if let Ok(msg) = event_queue.try_recv() {
-                    if handle_msg(self, msg) {
-                        break;
-                    }
+                if let Some(msg) = event_queue.pop()
+                    && handle_msg(self, msg)
+                {
+                    break;
                 }
 
-                if self.state == ProcessingState::Suspended {
+                if self.state == ProcessingState::Suspended || self.suspend_if_scheduled() {
                     // Bail out if we just suspended processing.
                     continue;
                 }
 
+                let starved = self.sink.is_starved();
+                if starved && !self.starved {
+                    self.send_event(AudioContextEvent::Underrun);
+                }
+                self.starved = starved;
+
                 // push into the audio sink the result of processing a
                 // render quantum.
+                let start = self.render_capacity.is_some().then(Instant::now);
                 let data = self.process();
-                if self.sink.push_data(data).is_ok() {
-                    // increment current frame by the render quantum size.
-                    self.current_frame += FRAMES_PER_BLOCK;
-                    self.current_time = self.current_frame / self.sample_rate as f64;
-                } else {
-                    eprintln!("Could not push data to audio sink");
+                if let (Some(start), Some(capacity)) = (start, self.render_capacity.as_mut()) {
+                    let timestamp =
+                        self.current_time + self.frames_per_block as f64 / self.sample_rate as f64;
+                    if let Some(report) = capacity.record(start.elapsed(), starved, timestamp) {
+                        self.send_event(AudioContextEvent::RenderCapacity(report));
+                    }
+                }
+                match self.sink.push_data(data) {
+                    Ok(()) => {
+                        // increment current frame by the render quantum size.
+                        self.current_frame += Tick(self.frames_per_block as u64);
+                        self.current_time = self.current_frame / self.sample_rate as f64;
+                        self.published_time
+                            .store(self.current_time.to_bits(), Ordering::Relaxed);
+                        self.push_failed = false;
+                    },
+                    Err(error) => {
+                        if !self.push_failed {
+                            self.send_event(AudioContextEvent::SinkError(error));
+                        }
+                        self.push_failed = true;
+                    },
                 }
             }
         }
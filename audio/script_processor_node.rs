@@ -0,0 +1,374 @@
+use crate::block::{Block, Chunk, Tick};
+use crate::buffer_source_node::AudioBuffer;
+use crate::node::{AudioNodeEngine, AudioNodeError, AudioNodeType, BlockInfo};
+use crate::node::{ChannelCountMode, ChannelInfo};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::Builder;
+
+/// The data handed to a ScriptProcessorNode's callback.
+///
+/// https://webaudio.github.io/web-audio-api/#AudioProcessingEvent
+pub struct AudioProcessingEvent {
+    /// The time at which the output will be played, in seconds
+    pub playback_time: f64,
+    pub input_buffer: AudioBuffer,
+    /// Zero-filled, to be written to by the callback
+    pub output_buffer: AudioBuffer,
+}
+
+/// The number of events a node allocates: two for double-buffering, and
+/// more for the worker to lag behind
+const EVENT_COUNT: usize = 4;
+
+pub type ScriptProcessorCallback = Box<dyn FnMut(&mut AudioProcessingEvent) + Send>;
+
+/// This specifies options for constructing a ScriptProcessorNode.
+pub struct ScriptProcessorNodeOptions {
+    /// One of 256, 512, 1024, 2048, 4096, 8192 or 16384
+    pub buffer_size: usize,
+    pub number_of_input_channels: u8,
+    pub number_of_output_channels: u8,
+    /// Called on a dedicated thread for every `buffer_size` frames
+    pub callback: ScriptProcessorCallback,
+}
+
+impl ScriptProcessorNodeOptions {
+    /// https://webaudio.github.io/web-audio-api/#dom-baseaudiocontext-createscriptprocessor
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        let buffer_size =
+            self.buffer_size.is_power_of_two() && (256..=16384).contains(&self.buffer_size);
+        let inputs = self.number_of_input_channels;
+        let outputs = self.number_of_output_channels;
+        if buffer_size && (inputs > 0 || outputs > 0) && inputs <= 32 && outputs <= 32 {
+            Ok(())
+        } else {
+            Err(AudioNodeError::IndexSize)
+        }
+    }
+}
+
+/// ScriptProcessorNode engine.
+///
+/// Input is gathered into buffers of `buffer_size` frames, each of which
+/// is sent to a worker thread running the callback. The output the callback
+/// produces is played one buffer later. Events are double-buffered: one is
+/// being filled with input while the other's output is being played.
+///
+/// Offline contexts wait for the callback, so that rendering is
+/// deterministic. Real-time contexts never block the render thread: if the
+/// callback hasn't returned in time, silence is played and the latency
+/// grows to absorb the delay. Events are all allocated upfront, and if the
+/// callback lags so far behind that none is free, input buffers are dropped
+/// until one is returned.
+///
+/// https://webaudio.github.io/web-audio-api/#ScriptProcessorNode
+#[derive(AudioNodeCommon)]
+pub(crate) struct ScriptProcessorNode {
+    channel_info: ChannelInfo,
+    buffer_size: usize,
+    number_of_output_channels: u8,
+    sample_rate: f32,
+    /// Whether to wait for the callback instead of playing silence
+    blocking: bool,
+    /// The event whose input buffer is being filled
+    filling: Option<AudioProcessingEvent>,
+    /// Frames of input gathered in `filling`
+    fill_pos: usize,
+    /// The event whose output buffer is being played
+    playing: Option<AudioProcessingEvent>,
+    /// Frames of output already played from `playing`
+    play_pos: usize,
+    /// Events that are free to be filled, never more than `EVENT_COUNT`
+    spare: Vec<AudioProcessingEvent>,
+    /// Number of events sent to the worker and not yet received back
+    in_flight: usize,
+    to_worker: Sender<AudioProcessingEvent>,
+    from_worker: Receiver<AudioProcessingEvent>,
+}
+
+impl ScriptProcessorNode {
+    pub fn new(
+        options: ScriptProcessorNodeOptions,
+        mut channel_info: ChannelInfo,
+        sample_rate: f32,
+        frames_per_block: usize,
+        blocking: bool,
+    ) -> Self {
+        debug_assert!(options.check().is_ok());
+        channel_info.count = options.number_of_input_channels.max(1);
+        channel_info.mode = ChannelCountMode::Explicit;
+
+        let (to_worker, worker_rx) = mpsc::channel();
+        let (worker_tx, from_worker) = mpsc::channel();
+        let mut callback = options.callback;
+        Builder::new()
+            .name("ScriptProcessor".to_owned())
+            .spawn(move || {
+                // Exits once the node is dropped
+                while let Ok(mut event) = worker_rx.recv() {
+                    callback(&mut event);
+                    if worker_tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn ScriptProcessor thread");
+
+        let mut node = Self {
+            channel_info,
+            // Buffers must hold a whole number of blocks, which only
+            // takes growing them when the render quantum is large
+            buffer_size: options.buffer_size.max(frames_per_block),
+            number_of_output_channels: options.number_of_output_channels,
+            sample_rate,
+            blocking,
+            filling: None,
+            fill_pos: 0,
+            playing: None,
+            play_pos: 0,
+            spare: Vec::with_capacity(EVENT_COUNT),
+            in_flight: 0,
+            to_worker,
+            from_worker,
+        };
+        let input_channels = options.number_of_input_channels;
+        for _ in 0..EVENT_COUNT {
+            let event = node.new_event(input_channels);
+            node.spare.push(event);
+        }
+        node.filling = node.spare.pop();
+        node
+    }
+
+    fn new_event(&self, input_channels: u8) -> AudioProcessingEvent {
+        let buffer = |chans: u8| AudioBuffer {
+            buffers: vec![vec![0.; self.buffer_size]; chans as usize],
+            sample_rate: self.sample_rate,
+        };
+        AudioProcessingEvent {
+            playback_time: 0.,
+            input_buffer: buffer(input_channels),
+            output_buffer: buffer(self.number_of_output_channels),
+        }
+    }
+
+    /// Produce the next block of output from the event being played,
+    /// moving on to the next one if it has run out
+    fn play(&mut self, info: &BlockInfo) -> Block {
+        let frames = info.frames_per_block;
+        if self.playing.is_none() && self.in_flight > 0 {
+            let event = if self.blocking {
+                self.from_worker.recv().ok()
+            } else {
+                self.from_worker.try_recv().ok()
+            };
+            if let Some(event) = event {
+                self.in_flight -= 1;
+                self.playing = Some(event);
+                self.play_pos = 0;
+            }
+        }
+
+        let mut block = Block::silence(frames);
+        match self.playing {
+            Some(ref event) if self.number_of_output_channels > 0 => {
+                block = Block::empty(frames);
+                let range = self.play_pos..self.play_pos + frames;
+                for chan in &event.output_buffer.buffers {
+                    block.push_chan(&chan[range.clone()]);
+                }
+                self.play_pos += frames;
+            },
+            Some(_) => self.play_pos += frames,
+            None => block.mix(
+                self.number_of_output_channels.max(1),
+                self.channel_info.interpretation,
+            ),
+        }
+        // Free the event as soon as it's played, for the buffer being
+        // gathered in this block
+        if self.play_pos == self.buffer_size
+            && let Some(event) = self.playing.take()
+        {
+            self.spare.push(event);
+        }
+        block
+    }
+
+    /// Gather a block of input, sending it off to the worker once
+    /// a whole buffer is ready and another event is free to be filled
+    fn gather(&mut self, input: &Block, info: &BlockInfo) {
+        let mut event = self.filling.take().expect("always filling an event");
+        let range = self.fill_pos..self.fill_pos + info.frames_per_block;
+        for (chan, buffer) in event.input_buffer.buffers.iter_mut().enumerate() {
+            if input.is_silence() {
+                buffer[range.clone()].fill(0.);
+            } else {
+                buffer[range.clone()].copy_from_slice(input.data_chan(chan as u8));
+            }
+        }
+        self.fill_pos += info.frames_per_block;
+        if self.fill_pos < self.buffer_size {
+            self.filling = Some(event);
+            return;
+        }
+        self.fill_pos = 0;
+        let Some(next) = self.spare.pop() else {
+            // The worker holds every other event: drop this buffer, and
+            // refill the event
+            self.filling = Some(event);
+            return;
+        };
+        self.filling = Some(next);
+
+        // This buffer's output starts playing with the next block
+        let next_frame = info.next_frame().0;
+        event.playback_time = next_frame as f64 / self.sample_rate as f64;
+        for chan in &mut event.output_buffer.buffers {
+            chan.fill(0.);
+        }
+        if self.to_worker.send(event).is_ok() {
+            self.in_flight += 1;
+        }
+    }
+}
+
+impl AudioNodeEngine for ScriptProcessorNode {
+    fn node_type(&self) -> AudioNodeType {
+        AudioNodeType::ScriptProcessorNode
+    }
+
+    fn has_finished(&self, _: Tick) -> bool {
+        // Scripts can produce output at any time
+        false
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
+        debug_assert!(inputs.len() == 1);
+
+        // Play before gathering, so that the output is exactly
+        // one buffer behind the input
+        let output = self.play(info);
+        self.gather(&inputs.blocks[0], info);
+        inputs.blocks[0] = output;
+        inputs
+    }
+
+    // Changes are rejected by `AudioContext::message_node`
+    fn set_channel_count(&mut self, _: u8) {}
+
+    fn set_channel_count_mode(&mut self, _: ChannelCountMode) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+    use std::sync::mpsc::SyncSender;
+    use std::thread;
+    use std::time::Duration;
+
+    const SAMPLE_RATE: f32 = 44100.;
+    const FRAMES: usize = 128;
+    const BUFFER_SIZE: usize = 256;
+
+    fn node(callback: ScriptProcessorCallback, blocking: bool) -> ScriptProcessorNode {
+        let options = ScriptProcessorNodeOptions {
+            buffer_size: BUFFER_SIZE,
+            number_of_input_channels: 1,
+            number_of_output_channels: 1,
+            callback,
+        };
+        ScriptProcessorNode::new(
+            options,
+            ChannelInfo::default(),
+            SAMPLE_RATE,
+            FRAMES,
+            blocking,
+        )
+    }
+
+    /// Copies its input to its output, reporting the playback times
+    fn copy(times: SyncSender<f64>) -> ScriptProcessorCallback {
+        Box::new(move |event: &mut AudioProcessingEvent| {
+            event.output_buffer.buffers[0].copy_from_slice(&event.input_buffer.buffers[0]);
+            let _ = times.try_send(event.playback_time);
+        })
+    }
+
+    /// Process blocks of input numbering their frames from 1, returning
+    /// the output
+    fn render(node: &mut ScriptProcessorNode, blocks: usize) -> Vec<f32> {
+        let mut output = Vec::new();
+        for block in 0..blocks {
+            let frame = (block * FRAMES) as u64;
+            let info = BlockInfo {
+                sample_rate: SAMPLE_RATE,
+                frame: Tick(frame),
+                time: frame as f64 / SAMPLE_RATE as f64,
+                frames_per_block: FRAMES,
+            };
+            let mut input = Block::for_channels_explicit(1, FRAMES);
+            for (i, sample) in input.data_chan_mut(0).iter_mut().enumerate() {
+                *sample = (frame as usize + i + 1) as f32;
+            }
+            let mut chunk = node.process(
+                Chunk {
+                    blocks: smallvec![input],
+                },
+                &info,
+            );
+            let block = &mut chunk.blocks[0];
+            block.explicit_silence();
+            output.extend_from_slice(block.data_chan(0));
+        }
+        output
+    }
+
+    #[test]
+    fn output_is_one_buffer_behind_the_input() {
+        let (tx, times) = mpsc::sync_channel(16);
+        let mut node = node(copy(tx), true);
+        let output = render(&mut node, 8);
+
+        let expected: Vec<f32> = (0..8 * FRAMES)
+            .map(|i| i.saturating_sub(BUFFER_SIZE - 1) as f32)
+            .collect();
+        assert_eq!(output, expected);
+        let times: Vec<f64> = times.try_iter().collect();
+        let expected: Vec<f64> = (1..=3)
+            .map(|i| (i * BUFFER_SIZE) as f64 / SAMPLE_RATE as f64)
+            .collect();
+        assert_eq!(&times[..3], &expected[..]);
+    }
+
+    #[test]
+    fn offline_rendering_waits_for_the_callback() {
+        let (tx, _times) = mpsc::sync_channel(16);
+        let mut copy = copy(tx);
+        let slow = Box::new(move |event: &mut AudioProcessingEvent| {
+            thread::sleep(Duration::from_millis(5));
+            copy(event);
+        });
+        let mut node = node(slow, true);
+        let output = render(&mut node, 16);
+
+        // No buffer was dropped or played late
+        let expected: Vec<f32> = (0..16 * FRAMES)
+            .map(|i| i.saturating_sub(BUFFER_SIZE - 1) as f32)
+            .collect();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn real_time_rendering_does_not_wait_for_the_callback() {
+        let (release, released) = mpsc::channel::<()>();
+        let stuck = Box::new(move |_: &mut AudioProcessingEvent| {
+            let _ = released.recv();
+        });
+        let mut node = node(stuck, false);
+        let output = render(&mut node, 16);
+        assert!(output.iter().all(|&s| s == 0.));
+        drop(release);
+    }
+}
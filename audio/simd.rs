@@ -0,0 +1,562 @@
+//! Vectorized kernels for the hot loops of block mixing.
+//!
+//! Each kernel picks the widest instruction set the CPU supports at
+//! runtime: AVX or SSE2 on x86_64, NEON on aarch64. Other targets, and
+//! the tails of slices which don't fill a vector, use the portable
+//! implementations in `scalar`.
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::is_aarch64_feature_detected;
+#[cfg(target_arch = "x86_64")]
+use std::arch::is_x86_feature_detected;
+
+/// `dst[i] += src[i]`
+pub fn add(dst: &mut [f32], src: &[f32]) {
+    assert_eq!(dst.len(), src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::add_avx(dst, src) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::add_sse2(dst, src) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if is_aarch64_feature_detected!("neon") {
+            return unsafe { aarch64::add_neon(dst, src) };
+        }
+    }
+    scalar::add(dst, src)
+}
+
+/// `dst[i] *= gain`
+pub fn scale(dst: &mut [f32], gain: f32) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::scale_avx(dst, gain) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::scale_sse2(dst, gain) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if is_aarch64_feature_detected!("neon") {
+            return unsafe { aarch64::scale_neon(dst, gain) };
+        }
+    }
+    scalar::scale(dst, gain)
+}
+
+/// `dst[i] *= gains[i]`
+pub fn mul(dst: &mut [f32], gains: &[f32]) {
+    assert_eq!(dst.len(), gains.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::mul_avx(dst, gains) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::mul_sse2(dst, gains) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if is_aarch64_feature_detected!("neon") {
+            return unsafe { aarch64::mul_neon(dst, gains) };
+        }
+    }
+    scalar::mul(dst, gains)
+}
+
+/// `dst[i] += src[i] * gain`
+pub fn add_scaled(dst: &mut [f32], src: &[f32], gain: f32) {
+    assert_eq!(dst.len(), src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::add_scaled_avx(dst, src, gain) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::add_scaled_sse2(dst, src, gain) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if is_aarch64_feature_detected!("neon") {
+            return unsafe { aarch64::add_scaled_neon(dst, src, gain) };
+        }
+    }
+    scalar::add_scaled(dst, src, gain)
+}
+
+/// Interleave `src`, made of `channels` planar channels of equal length,
+/// into `dst`
+pub fn interleave(src: &[f32], channels: usize, dst: &mut [f32]) {
+    assert_eq!(src.len(), dst.len());
+    assert!(channels > 0 && src.len().is_multiple_of(channels));
+    // Stereo is by far the most common output layout, other layouts
+    // don't map as neatly onto vector shuffles
+    if channels != 2 {
+        return scalar::interleave(src, channels, dst);
+    }
+    let (left, right) = src.split_at(src.len() / 2);
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            return unsafe { x86::interleave_stereo_avx(left, right, dst) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::interleave_stereo_sse2(left, right, dst) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if is_aarch64_feature_detected!("neon") {
+            return unsafe { aarch64::interleave_stereo_neon(left, right, dst) };
+        }
+    }
+    scalar::interleave_stereo(left, right, dst)
+}
+
+/// Portable implementations of the kernels, also used for the tails of
+/// the vectorized ones.
+pub mod scalar {
+    pub fn add(dst: &mut [f32], src: &[f32]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += s;
+        }
+    }
+
+    pub fn scale(dst: &mut [f32], gain: f32) {
+        for d in dst {
+            *d *= gain;
+        }
+    }
+
+    pub fn mul(dst: &mut [f32], gains: &[f32]) {
+        for (d, g) in dst.iter_mut().zip(gains) {
+            *d *= g;
+        }
+    }
+
+    pub fn add_scaled(dst: &mut [f32], src: &[f32], gain: f32) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += s * gain;
+        }
+    }
+
+    pub fn interleave_stereo(left: &[f32], right: &[f32], dst: &mut [f32]) {
+        for ((frame, l), r) in dst.chunks_exact_mut(2).zip(left).zip(right) {
+            frame[0] = *l;
+            frame[1] = *r;
+        }
+    }
+
+    pub fn interleave(src: &[f32], channels: usize, dst: &mut [f32]) {
+        let frames = src.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        for (chan, data) in src.chunks_exact(frames).enumerate() {
+            for (frame, sample) in data.iter().enumerate() {
+                dst[frame * channels + chan] = *sample;
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn add_avx(dst: &mut [f32], src: &[f32]) {
+        let split = dst.len() - dst.len() % 8;
+        for i in (0..split).step_by(8) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let s = _mm256_loadu_ps(src.as_ptr().add(i));
+                _mm256_storeu_ps(d, _mm256_add_ps(_mm256_loadu_ps(d), s));
+            }
+        }
+        scalar::add(&mut dst[split..], &src[split..]);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn add_sse2(dst: &mut [f32], src: &[f32]) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let s = _mm_loadu_ps(src.as_ptr().add(i));
+                _mm_storeu_ps(d, _mm_add_ps(_mm_loadu_ps(d), s));
+            }
+        }
+        scalar::add(&mut dst[split..], &src[split..]);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn scale_avx(dst: &mut [f32], gain: f32) {
+        let g = _mm256_set1_ps(gain);
+        let split = dst.len() - dst.len() % 8;
+        for i in (0..split).step_by(8) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                _mm256_storeu_ps(d, _mm256_mul_ps(_mm256_loadu_ps(d), g));
+            }
+        }
+        scalar::scale(&mut dst[split..], gain);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn scale_sse2(dst: &mut [f32], gain: f32) {
+        let g = _mm_set1_ps(gain);
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                _mm_storeu_ps(d, _mm_mul_ps(_mm_loadu_ps(d), g));
+            }
+        }
+        scalar::scale(&mut dst[split..], gain);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn mul_avx(dst: &mut [f32], gains: &[f32]) {
+        let split = dst.len() - dst.len() % 8;
+        for i in (0..split).step_by(8) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let g = _mm256_loadu_ps(gains.as_ptr().add(i));
+                _mm256_storeu_ps(d, _mm256_mul_ps(_mm256_loadu_ps(d), g));
+            }
+        }
+        scalar::mul(&mut dst[split..], &gains[split..]);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn mul_sse2(dst: &mut [f32], gains: &[f32]) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let g = _mm_loadu_ps(gains.as_ptr().add(i));
+                _mm_storeu_ps(d, _mm_mul_ps(_mm_loadu_ps(d), g));
+            }
+        }
+        scalar::mul(&mut dst[split..], &gains[split..]);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn add_scaled_avx(dst: &mut [f32], src: &[f32], gain: f32) {
+        let g = _mm256_set1_ps(gain);
+        let split = dst.len() - dst.len() % 8;
+        for i in (0..split).step_by(8) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let s = _mm256_mul_ps(_mm256_loadu_ps(src.as_ptr().add(i)), g);
+                _mm256_storeu_ps(d, _mm256_add_ps(_mm256_loadu_ps(d), s));
+            }
+        }
+        scalar::add_scaled(&mut dst[split..], &src[split..], gain);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn add_scaled_sse2(dst: &mut [f32], src: &[f32], gain: f32) {
+        let g = _mm_set1_ps(gain);
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let s = _mm_mul_ps(_mm_loadu_ps(src.as_ptr().add(i)), g);
+                _mm_storeu_ps(d, _mm_add_ps(_mm_loadu_ps(d), s));
+            }
+        }
+        scalar::add_scaled(&mut dst[split..], &src[split..], gain);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn interleave_stereo_avx(left: &[f32], right: &[f32], dst: &mut [f32]) {
+        let split = left.len() - left.len() % 8;
+        for i in (0..split).step_by(8) {
+            unsafe {
+                let l = _mm256_loadu_ps(left.as_ptr().add(i));
+                let r = _mm256_loadu_ps(right.as_ptr().add(i));
+                // Each unpack interleaves within 128 bit lanes:
+                // lo = l0 r0 l1 r1 | l4 r4 l5 r5, hi = l2 r2 l3 r3 | l6 r6 l7 r7
+                let lo = _mm256_unpacklo_ps(l, r);
+                let hi = _mm256_unpackhi_ps(l, r);
+                let out = dst.as_mut_ptr().add(2 * i);
+                _mm256_storeu_ps(out, _mm256_permute2f128_ps(lo, hi, 0x20));
+                _mm256_storeu_ps(out.add(8), _mm256_permute2f128_ps(lo, hi, 0x31));
+            }
+        }
+        scalar::interleave_stereo(&left[split..], &right[split..], &mut dst[2 * split..]);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn interleave_stereo_sse2(left: &[f32], right: &[f32], dst: &mut [f32]) {
+        let split = left.len() - left.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let l = _mm_loadu_ps(left.as_ptr().add(i));
+                let r = _mm_loadu_ps(right.as_ptr().add(i));
+                let out = dst.as_mut_ptr().add(2 * i);
+                _mm_storeu_ps(out, _mm_unpacklo_ps(l, r));
+                _mm_storeu_ps(out.add(4), _mm_unpackhi_ps(l, r));
+            }
+        }
+        scalar::interleave_stereo(&left[split..], &right[split..], &mut dst[2 * split..]);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use super::scalar;
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn add_neon(dst: &mut [f32], src: &[f32]) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                vst1q_f32(d, vaddq_f32(vld1q_f32(d), vld1q_f32(src.as_ptr().add(i))));
+            }
+        }
+        scalar::add(&mut dst[split..], &src[split..]);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn scale_neon(dst: &mut [f32], gain: f32) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                vst1q_f32(d, vmulq_n_f32(vld1q_f32(d), gain));
+            }
+        }
+        scalar::scale(&mut dst[split..], gain);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn mul_neon(dst: &mut [f32], gains: &[f32]) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                vst1q_f32(d, vmulq_f32(vld1q_f32(d), vld1q_f32(gains.as_ptr().add(i))));
+            }
+        }
+        scalar::mul(&mut dst[split..], &gains[split..]);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn add_scaled_neon(dst: &mut [f32], src: &[f32], gain: f32) {
+        let split = dst.len() - dst.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let d = dst.as_mut_ptr().add(i);
+                let s = vld1q_f32(src.as_ptr().add(i));
+                vst1q_f32(d, vmlaq_n_f32(vld1q_f32(d), s, gain));
+            }
+        }
+        scalar::add_scaled(&mut dst[split..], &src[split..], gain);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn interleave_stereo_neon(left: &[f32], right: &[f32], dst: &mut [f32]) {
+        let split = left.len() - left.len() % 4;
+        for i in (0..split).step_by(4) {
+            unsafe {
+                let frames = float32x4x2_t(
+                    vld1q_f32(left.as_ptr().add(i)),
+                    vld1q_f32(right.as_ptr().add(i)),
+                );
+                vst2q_f32(dst.as_mut_ptr().add(2 * i), frames);
+            }
+        }
+        scalar::interleave_stereo(&left[split..], &right[split..], &mut dst[2 * split..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Add = unsafe fn(&mut [f32], &[f32]);
+    type Scale = unsafe fn(&mut [f32], f32);
+    type AddScaled = unsafe fn(&mut [f32], &[f32], f32);
+    type Interleave = unsafe fn(&[f32], &[f32], &mut [f32]);
+
+    /// The kernels of one instruction set
+    struct Kernels {
+        name: &'static str,
+        add: Add,
+        scale: Scale,
+        mul: Add,
+        add_scaled: AddScaled,
+        interleave_stereo: Interleave,
+    }
+
+    /// The kernels of every instruction set the host supports
+    fn kernels() -> Vec<Kernels> {
+        #[allow(unused_mut)]
+        let mut kernels = vec![];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx") {
+                kernels.push(Kernels {
+                    name: "avx",
+                    add: x86::add_avx,
+                    scale: x86::scale_avx,
+                    mul: x86::mul_avx,
+                    add_scaled: x86::add_scaled_avx,
+                    interleave_stereo: x86::interleave_stereo_avx,
+                });
+            }
+            if is_x86_feature_detected!("sse2") {
+                kernels.push(Kernels {
+                    name: "sse2",
+                    add: x86::add_sse2,
+                    scale: x86::scale_sse2,
+                    mul: x86::mul_sse2,
+                    add_scaled: x86::add_scaled_sse2,
+                    interleave_stereo: x86::interleave_stereo_sse2,
+                });
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if is_aarch64_feature_detected!("neon") {
+                kernels.push(Kernels {
+                    name: "neon",
+                    add: aarch64::add_neon,
+                    scale: aarch64::scale_neon,
+                    mul: aarch64::mul_neon,
+                    add_scaled: aarch64::add_scaled_neon,
+                    interleave_stereo: aarch64::interleave_stereo_neon,
+                });
+            }
+        }
+        kernels
+    }
+
+    /// Lengths below, at and past a few multiples of every lane width
+    fn lengths() -> impl Iterator<Item = usize> {
+        (0..=33).chain([127, 128, 129, 131, 1021])
+    }
+
+    /// Some samples which aren't all alike
+    fn samples(len: usize, seed: f32) -> Vec<f32> {
+        (0..len).map(|i| (i as f32 * 0.731 + seed).sin()).collect()
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        for kernels in kernels() {
+            for len in lengths() {
+                let dst = samples(len, 1.);
+                let src = samples(len, 2.);
+                let check = |expected: &[f32], actual: &[f32], kernel: &str| {
+                    assert_eq!(
+                        expected, actual,
+                        "{} {} of {} samples",
+                        kernels.name, kernel, len
+                    );
+                };
+
+                let mut expected = dst.clone();
+                let mut actual = dst.clone();
+                scalar::add(&mut expected, &src);
+                unsafe { (kernels.add)(&mut actual, &src) };
+                check(&expected, &actual, "add");
+
+                let mut expected = dst.clone();
+                let mut actual = dst.clone();
+                scalar::scale(&mut expected, 0.3);
+                unsafe { (kernels.scale)(&mut actual, 0.3) };
+                check(&expected, &actual, "scale");
+
+                let mut expected = dst.clone();
+                let mut actual = dst.clone();
+                scalar::mul(&mut expected, &src);
+                unsafe { (kernels.mul)(&mut actual, &src) };
+                check(&expected, &actual, "mul");
+
+                let mut expected = dst.clone();
+                let mut actual = dst.clone();
+                scalar::add_scaled(&mut expected, &src, -0.7);
+                unsafe { (kernels.add_scaled)(&mut actual, &src, -0.7) };
+                check(&expected, &actual, "add_scaled");
+
+                let mut expected = vec![0.; 2 * len];
+                let mut actual = vec![0.; 2 * len];
+                scalar::interleave_stereo(&dst, &src, &mut expected);
+                unsafe { (kernels.interleave_stereo)(&dst, &src, &mut actual) };
+                check(&expected, &actual, "interleave_stereo");
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_matches_scalar() {
+        for len in lengths() {
+            let dst = samples(len, 1.);
+            let src = samples(len, 2.);
+
+            let mut expected = dst.clone();
+            let mut actual = dst.clone();
+            scalar::add(&mut expected, &src);
+            add(&mut actual, &src);
+            assert_eq!(expected, actual);
+
+            let mut expected = dst.clone();
+            let mut actual = dst.clone();
+            scalar::scale(&mut expected, 0.3);
+            scale(&mut actual, 0.3);
+            assert_eq!(expected, actual);
+
+            let mut expected = dst.clone();
+            let mut actual = dst.clone();
+            scalar::mul(&mut expected, &src);
+            mul(&mut actual, &src);
+            assert_eq!(expected, actual);
+
+            let mut expected = dst.clone();
+            let mut actual = dst;
+            scalar::add_scaled(&mut expected, &src, -0.7);
+            add_scaled(&mut actual, &src, -0.7);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn interleave_any_layout() {
+        for channels in 1..=6 {
+            for frames in lengths() {
+                let src = samples(channels * frames, 3.);
+                let mut actual = vec![0.; src.len()];
+                interleave(&src, channels, &mut actual);
+                for frame in 0..frames {
+                    for chan in 0..channels {
+                        assert_eq!(
+                            actual[frame * channels + chan],
+                            src[chan * frames + frame],
+                            "frame {} of channel {} in {} channels of {} frames",
+                            frame,
+                            chan,
+                            channels,
+                            frames
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use crate::block::Chunk;
-use crate::render_thread::AudioRenderThreadMsg;
+use crate::context::LatencyCategory;
+use crate::render_thread::RenderThreadWaker;
 use servo_media_streams::MediaSocket;
-use std::sync::mpsc::Sender;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum AudioSinkError {
     /// Backend specific error.
     Backend(String),
@@ -11,13 +11,32 @@ pub enum AudioSinkError {
     BufferPushFailed,
     /// Could not move to a different state.
     StateChangeFailed,
+    /// There is no audio output device with the requested id.
+    DeviceNotFound,
+    /// The operation is not supported by this kind of context.
+    NotSupported,
+}
+
+/// The delays audio goes through after leaving the render thread.
+///
+/// https://webaudio.github.io/web-audio-api/#dom-audiocontext-baselatency
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AudioSinkLatency {
+    /// Seconds between a chunk being pushed into the sink and the sink
+    /// handing it to the audio subsystem
+    pub base: f64,
+    /// Seconds between the audio subsystem receiving a sample-frame and
+    /// the audio output device playing it
+    pub output: f64,
 }
 
 pub trait AudioSink: Send {
     fn init(
         &self,
         sample_rate: f32,
-        render_thread_channel: Sender<AudioRenderThreadMsg>,
+        frames_per_block: usize,
+        latency_hint: LatencyCategory,
+        render_thread: RenderThreadWaker,
     ) -> Result<(), AudioSinkError>;
     fn init_stream(
         &self,
@@ -29,6 +48,12 @@ pub trait AudioSink: Send {
     fn stop(&self) -> Result<(), AudioSinkError>;
     fn has_enough_data(&self) -> bool;
     fn push_data(&self, chunk: Chunk) -> Result<(), AudioSinkError>;
+    /// Whether the sink played everything pushed to it while playing, so
+    /// the output device is playing silence until more data is pushed.
+    fn is_starved(&self) -> bool;
+    /// The current latency of the sink. Sinks may estimate it, and
+    /// it can change over time.
+    fn latency(&self) -> AudioSinkLatency;
     fn set_eos_callback(
         &self,
         callback: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>,
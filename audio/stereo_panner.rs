@@ -1,4 +1,4 @@
-use crate::block::{Chunk, FRAMES_PER_BLOCK, Tick};
+use crate::block::{Chunk, Tick};
 use crate::node::{AudioNodeEngine, BlockInfo};
 use crate::node::{AudioNodeType, ChannelInfo};
 use crate::param::{Param, ParamType};
@@ -55,9 +55,9 @@ impl AudioNodeEngine for StereoPannerNode {
                 false
             };
 
-            let (l, r) = block.data_mut().split_at_mut(FRAMES_PER_BLOCK.0 as usize);
+            let (l, r) = block.data_mut().split_at_mut(info.frames_per_block);
             let mut pan = self.pan.value();
-            for frame in 0..FRAMES_PER_BLOCK.0 {
+            for frame in 0..info.block_ticks().0 {
                 let frame = Tick(frame);
                 if self.update_parameters(info, frame) {
                     pan = self.pan.value();
@@ -121,4 +121,8 @@ impl AudioNodeEngine for StereoPannerNode {
             _ => panic!("Unknown param {:?} for PannerNode", id),
         }
     }
+
+    fn param_types(&self) -> &[ParamType] {
+        &[ParamType::Pan]
+    }
 }
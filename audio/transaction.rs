@@ -0,0 +1,94 @@
+use crate::context::AudioContext;
+use crate::graph::{InputPort, NodeId, OutputPort, PortId};
+use crate::node::{AudioNodeError, AudioNodeInit, ChannelInfo};
+use crate::param::{ParamType, UserAutomationEvent};
+
+/// A change to the audio graph, made as part of a `GraphTransaction`.
+pub enum GraphCommand {
+    ConnectPorts(PortId<OutputPort>, PortId<InputPort>),
+    DisconnectAllFrom(NodeId),
+    DisconnectOutput(PortId<OutputPort>),
+    DisconnectBetween(NodeId, NodeId),
+    DisconnectTo(NodeId, PortId<InputPort>),
+    DisconnectOutputBetween(PortId<OutputPort>, NodeId),
+    DisconnectOutputBetweenTo(PortId<OutputPort>, PortId<InputPort>),
+    /// Values set without a time are set at the time of the transaction
+    SetParam(NodeId, ParamType, UserAutomationEvent),
+}
+
+/// A batch of changes to the audio graph, which all take effect at the
+/// same sample-frame, even in the middle of a render quantum.
+///
+/// Nodes are created right away with `create_node_now`, so that the
+/// transaction can refer to them, but they can't be heard before it
+/// connects them.
+pub struct GraphTransaction<'a> {
+    context: &'a AudioContext,
+    commands: Vec<GraphCommand>,
+}
+
+impl<'a> GraphTransaction<'a> {
+    pub(crate) fn new(context: &'a AudioContext) -> Self {
+        Self {
+            context,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Create a node immediately, as `AudioContext::create_node` does, rather
+    /// than when the transaction is committed. It can be messaged right away,
+    /// but isn't heard before it is connected.
+    pub fn create_node_now(
+        &mut self,
+        node_type: AudioNodeInit,
+        ch: ChannelInfo,
+    ) -> Result<NodeId, AudioNodeError> {
+        self.context.create_node(node_type, ch)
+    }
+
+    pub fn connect_ports(&mut self, from: PortId<OutputPort>, to: PortId<InputPort>) {
+        self.commands.push(GraphCommand::ConnectPorts(from, to));
+    }
+
+    pub fn disconnect_all_from(&mut self, node: NodeId) {
+        self.commands.push(GraphCommand::DisconnectAllFrom(node));
+    }
+
+    pub fn disconnect_output(&mut self, out: PortId<OutputPort>) {
+        self.commands.push(GraphCommand::DisconnectOutput(out));
+    }
+
+    pub fn disconnect_between(&mut self, from: NodeId, to: NodeId) {
+        self.commands
+            .push(GraphCommand::DisconnectBetween(from, to));
+    }
+
+    pub fn disconnect_to(&mut self, from: NodeId, to: PortId<InputPort>) {
+        self.commands.push(GraphCommand::DisconnectTo(from, to));
+    }
+
+    pub fn disconnect_output_between(&mut self, out: PortId<OutputPort>, to: NodeId) {
+        self.commands
+            .push(GraphCommand::DisconnectOutputBetween(out, to));
+    }
+
+    pub fn disconnect_output_between_to(
+        &mut self,
+        out: PortId<OutputPort>,
+        inp: PortId<InputPort>,
+    ) {
+        self.commands
+            .push(GraphCommand::DisconnectOutputBetweenTo(out, inp));
+    }
+
+    pub fn set_param(&mut self, node: NodeId, param: ParamType, event: UserAutomationEvent) {
+        self.commands
+            .push(GraphCommand::SetParam(node, param, event));
+    }
+
+    /// Apply all the changes at the first sample-frame at or after the
+    /// given context time, or as soon as possible if it has passed.
+    pub fn commit(self, time: f64) {
+        self.context.schedule_commands(time, self.commands);
+    }
+}
@@ -1,5 +1,5 @@
-use crate::block::{Chunk, FRAMES_PER_BLOCK_USIZE};
-use crate::node::{AudioNodeEngine, AudioNodeType, BlockInfo, ChannelInfo};
+use crate::block::Chunk;
+use crate::node::{AudioNodeEngine, AudioNodeType, BlockInfo, ChannelInfo, Garbage};
 use speexdsp_resampler::State as SpeexResamplerState;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -60,6 +60,8 @@ pub(crate) struct WaveShaperNode {
     upsampler: Option<SpeexResamplerState>,
     downsampler: Option<SpeexResamplerState>,
     tailtime_blocks_left: TailtimeBlocks,
+    /// The curve replaced by the last SetCurve message
+    replaced: WaveShaperCurve,
 }
 
 impl WaveShaperNode {
@@ -79,6 +81,7 @@ impl WaveShaperNode {
             upsampler: None,
             downsampler: None,
             tailtime_blocks_left: TailtimeBlocks::Zero,
+            replaced: None,
         }
     }
 
@@ -89,7 +92,7 @@ impl WaveShaperNode {
                     panic!("InvalidStateError: cant set curve if it was already set");
                 }
                 self.curve_set = new_curve.is_some();
-                self.curve = new_curve;
+                self.replaced = std::mem::replace(&mut self.curve, new_curve);
             },
         }
     }
@@ -162,8 +165,8 @@ impl AudioNodeEngine for WaveShaperNode {
             let mut upsampler = self.upsampler.as_mut().unwrap();
             let mut downsampler = self.downsampler.as_mut().unwrap();
 
-            let mut oversampled_buffer: Vec<f32> =
-                vec![0.; FRAMES_PER_BLOCK_USIZE * sampling_factor];
+            let frames = block.frames();
+            let mut oversampled_buffer: Vec<f32> = vec![0.; frames * sampling_factor];
 
             for chan in 0..channels {
                 let out_len = WaveShaperNode::resample(
@@ -174,9 +177,9 @@ impl AudioNodeEngine for WaveShaperNode {
                 );
 
                 debug_assert!(
-                    out_len == 128 * sampling_factor,
+                    out_len == frames * sampling_factor,
                     "Expected {} samples in output after upsampling, got: {}",
-                    128 * sampling_factor,
+                    frames * sampling_factor,
                     out_len
                 );
 
@@ -190,8 +193,9 @@ impl AudioNodeEngine for WaveShaperNode {
                 );
 
                 debug_assert!(
-                    out_len == 128,
-                    "Expected 128 samples in output after downsampling, got {}",
+                    out_len == frames,
+                    "Expected {} samples in output after downsampling, got {}",
+                    frames,
                     out_len
                 );
             }
@@ -202,6 +206,10 @@ impl AudioNodeEngine for WaveShaperNode {
         inputs
     }
 
+    fn take_garbage(&mut self) -> Option<Garbage> {
+        self.replaced.take().map(Garbage::Samples)
+    }
+
     make_message_handler!(WaveShaperNode: handle_waveshaper_message);
 }
 
@@ -0,0 +1,169 @@
+//! A pool of threads helping the render thread process a block.
+//!
+//! The render thread hands the pool a job made of independent tasks and
+//! works on them alongside the worker threads, until all of them are done.
+//! Running a job doesn't allocate or take a lock. Idle workers spin for a
+//! little while before parking, so that the next job of a block can be
+//! picked up quickly.
+
+use std::hint;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::thread::{self, Builder, JoinHandle};
+
+/// How many times to check for work before yielding or parking
+const SPINS: u32 = 1 << 12;
+
+pub(crate) struct WorkerPool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    /// The job being run, null when there is none
+    job: AtomicPtr<Job>,
+    /// Number of workers which may be looking at `job`
+    active: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+struct Job {
+    /// Runs a task, given its index. It outlives the job, despite
+    /// the 'static lifetime.
+    task: *const (dyn Fn(usize) + Sync),
+    count: usize,
+    /// The index of the next task to start
+    next: AtomicUsize,
+    /// Number of tasks not finished yet
+    pending: AtomicUsize,
+    panicked: AtomicBool,
+}
+
+impl Job {
+    /// Run tasks until there are none left to start
+    fn help(&self) {
+        // Safety: the task is only dropped once every worker is done
+        // with the job
+        let task = unsafe { &*self.task };
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            if index >= self.count {
+                break;
+            }
+            if panic::catch_unwind(AssertUnwindSafe(|| task(index))).is_err() {
+                self.panicked.store(true, Ordering::Relaxed);
+            }
+            self.pending.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+impl WorkerPool {
+    /// Spawn `count` worker threads
+    pub fn new(count: usize) -> Self {
+        let shared = Arc::new(Shared {
+            job: AtomicPtr::new(ptr::null_mut()),
+            active: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = (0..count)
+            .map(|i| {
+                let shared = shared.clone();
+                Builder::new()
+                    .name(format!("AudioRenderWorker{}", i))
+                    .spawn(move || work(&shared))
+                    .expect("Failed to spawn AudioRenderWorker")
+            })
+            .collect();
+        WorkerPool { shared, threads }
+    }
+
+    /// Run `task` for each index in `0..count`, in no particular order
+    /// and possibly in parallel, then return once all of them are done
+    pub fn run<F: Fn(usize) + Sync>(&self, count: usize, task: F) {
+        if count < 2 || self.threads.is_empty() {
+            (0..count).for_each(task);
+            return;
+        }
+        let task: &(dyn Fn(usize) + Sync) = &task;
+        // Safety: the job is withdrawn, and no worker looks at it
+        // anymore, before this returns
+        let task = unsafe {
+            mem::transmute::<&(dyn Fn(usize) + Sync), *const (dyn Fn(usize) + Sync + 'static)>(task)
+        };
+        let job = Job {
+            task,
+            count,
+            next: AtomicUsize::new(0),
+            pending: AtomicUsize::new(count),
+            panicked: AtomicBool::new(false),
+        };
+        let shared = &*self.shared;
+        shared
+            .job
+            .store(&job as *const Job as *mut Job, Ordering::SeqCst);
+        for thread in &self.threads {
+            thread.thread().unpark();
+        }
+        job.help();
+        wait_until(|| job.pending.load(Ordering::Acquire) == 0);
+        shared.job.store(ptr::null_mut(), Ordering::SeqCst);
+        wait_until(|| shared.active.load(Ordering::SeqCst) == 0);
+        if job.panicked.load(Ordering::Relaxed) {
+            panic!("A task run by the render workers panicked");
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        for thread in self.threads.drain(..) {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(shared: &Shared) {
+    let mut spins = 0;
+    while !shared.shutdown.load(Ordering::Acquire) {
+        // Announced before looking at the job, so that the render thread
+        // knows it can't drop it yet
+        shared.active.fetch_add(1, Ordering::SeqCst);
+        let job = shared.job.load(Ordering::SeqCst);
+        if !job.is_null() {
+            unsafe { &*job }.help();
+        }
+        shared.active.fetch_sub(1, Ordering::SeqCst);
+
+        if !job.is_null() {
+            spins = 0;
+            // Let the render thread withdraw the job
+            thread::yield_now();
+        } else if spins < SPINS {
+            spins += 1;
+            hint::spin_loop();
+        } else {
+            spins = 0;
+            // Unparked by the next job, or on shutdown
+            thread::park();
+        }
+    }
+}
+
+/// Busy-wait for a condition expected to become true shortly
+fn wait_until(condition: impl Fn() -> bool) {
+    let mut spins = 0;
+    while !condition() {
+        if spins < SPINS {
+            spins += 1;
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::block::{Block, Chunk, Tick};
+use crate::node::ChannelInterpretation;
+use crate::node::{AudioNodeEngine, AudioNodeError, AudioNodeType, BlockInfo, ChannelInfo};
+use crate::param::{Param, ParamRate, ParamType};
+
+/// Describes a param declared by an `AudioWorkletProcessor`.
+///
+/// https://webaudio.github.io/web-audio-api/#audioparamdescriptor
+#[derive(Clone, Debug)]
+pub struct AudioParamDescriptor {
+    pub name: String,
+    pub default_value: f32,
+    pub min_value: f32,
+    pub max_value: f32,
+    pub automation_rate: ParamRate,
+}
+
+impl AudioParamDescriptor {
+    pub fn new(name: &str, default_value: f32) -> Self {
+        AudioParamDescriptor {
+            name: name.into(),
+            default_value,
+            min_value: f32::MIN,
+            max_value: f32::MAX,
+            automation_rate: ParamRate::ARate,
+        }
+    }
+}
+
+/// User-supplied DSP, run on the render thread by an AudioWorkletNode.
+///
+/// https://webaudio.github.io/web-audio-api/#audioworkletprocessor
+pub trait AudioWorkletProcessor: Send {
+    /// The params this processor accepts. They are addressed with
+    /// `ParamType::Custom(i)`, where `i` is the index in this list.
+    ///
+    /// Only called once, when the node is created.
+    fn parameter_descriptors(&self) -> Vec<AudioParamDescriptor> {
+        Vec::new()
+    }
+
+    /// Process a single render quantum.
+    ///
+    /// `inputs` have already been up/down-mixed according to the node's
+    /// channel count, mode and interpretation, and are never silent
+    /// shorthands, so their channel data can be read directly.
+    /// `outputs` are zero-filled blocks with the output channel counts.
+    /// `params` holds the value of each declared param for every frame,
+    /// or a single value if it is constant over the render quantum.
+    ///
+    /// Returns whether the processor should be kept alive even when all
+    /// of its inputs are silent. Once it returns false, it is not called
+    /// again until one of its inputs becomes non-silent.
+    fn process(
+        &mut self,
+        inputs: &[Block],
+        outputs: &mut [Block],
+        params: &[Vec<f32>],
+        info: &BlockInfo,
+    ) -> bool;
+}
+
+/// This specifies options for constructing an AudioWorkletNode.
+pub struct AudioWorkletNodeOptions {
+    pub number_of_inputs: u32,
+    pub number_of_outputs: u32,
+    /// The number of channels of each output. If empty, a node with a single
+    /// input and output mirrors the computed number of input channels, and
+    /// all other outputs are mono.
+    pub output_channel_count: Vec<u8>,
+    pub processor: Box<dyn AudioWorkletProcessor>,
+}
+
+impl AudioWorkletNodeOptions {
+    pub fn new(processor: Box<dyn AudioWorkletProcessor>) -> Self {
+        AudioWorkletNodeOptions {
+            number_of_inputs: 1,
+            number_of_outputs: 1,
+            output_channel_count: Vec::new(),
+            processor,
+        }
+    }
+
+    /// https://webaudio.github.io/web-audio-api/#dom-audioworkletnode-audioworkletnode
+    pub(crate) fn check(&self) -> Result<(), AudioNodeError> {
+        if self.number_of_inputs == 0 && self.number_of_outputs == 0 {
+            return Err(AudioNodeError::NotSupported);
+        }
+        if self.output_channel_count.is_empty() {
+            return Ok(());
+        }
+        if self.output_channel_count.len() != self.number_of_outputs as usize {
+            return Err(AudioNodeError::IndexSize);
+        }
+        if self.output_channel_count.contains(&0) {
+            return Err(AudioNodeError::NotSupported);
+        }
+        Ok(())
+    }
+}
+
+/// AudioWorkletNode engine.
+///
+/// https://webaudio.github.io/web-audio-api/#AudioWorkletNode
+#[derive(AudioNodeCommon)]
+pub(crate) struct AudioWorkletNode {
+    channel_info: ChannelInfo,
+    processor: Box<dyn AudioWorkletProcessor>,
+    number_of_inputs: u32,
+    number_of_outputs: u32,
+    output_channel_count: Vec<u8>,
+    descriptors: Vec<AudioParamDescriptor>,
+    params: Vec<Param>,
+    /// The id of every param, `ParamType::Custom` with its index
+    param_types: Vec<ParamType>,
+    /// Scratch space for the values of `params` over the current block
+    param_values: Vec<Vec<f32>>,
+    /// The last value returned by the processor
+    keep_alive: bool,
+}
+
+impl AudioWorkletNode {
+    pub fn new(
+        options: AudioWorkletNodeOptions,
+        channel_info: ChannelInfo,
+        frames_per_block: usize,
+    ) -> Self {
+        debug_assert!(options.check().is_ok());
+        let descriptors = options.processor.parameter_descriptors();
+        let params = descriptors
+            .iter()
+            .map(|d| {
+                let mut param = Param::new(d.default_value);
+                param.set_rate(d.automation_rate);
+                param
+            })
+            .collect();
+        let param_types = (0..descriptors.len() as u32)
+            .map(ParamType::Custom)
+            .collect();
+        let param_values = descriptors
+            .iter()
+            .map(|_| Vec::with_capacity(frames_per_block))
+            .collect();
+        Self {
+            channel_info,
+            processor: options.processor,
+            number_of_inputs: options.number_of_inputs,
+            number_of_outputs: options.number_of_outputs,
+            output_channel_count: options.output_channel_count,
+            descriptors,
+            params,
+            param_types,
+            param_values,
+            keep_alive: true,
+        }
+    }
+
+    /// Compute the values of every param over this block
+    fn update_param_values(&mut self, info: &BlockInfo) {
+        for ((param, values), descriptor) in self
+            .params
+            .iter_mut()
+            .zip(&mut self.param_values)
+            .zip(&self.descriptors)
+        {
+            let clamp = |v: f32| v.clamp(descriptor.min_value, descriptor.max_value);
+            values.clear();
+            param.update(info, Tick(0));
+            values.push(clamp(param.value()));
+            if descriptor.automation_rate == ParamRate::KRate {
+                continue;
+            }
+            let mut changed = false;
+            for tick in 1..info.frames_per_block {
+                changed |= param.update(info, Tick(tick as u64));
+                values.push(clamp(param.value()));
+            }
+            if !changed {
+                values.truncate(1);
+            }
+        }
+    }
+}
+
+impl AudioNodeEngine for AudioWorkletNode {
+    fn node_type(&self) -> AudioNodeType {
+        AudioNodeType::AudioWorkletNode
+    }
+
+    fn has_finished(&self, _: Tick) -> bool {
+        // Without input, the processor is only called while it asks to be
+        // kept alive
+        !self.keep_alive
+    }
+
+    fn process(&mut self, mut inputs: Chunk, info: &BlockInfo) -> Chunk {
+        debug_assert!(inputs.len() == self.number_of_inputs as usize);
+
+        let silent = inputs.blocks.iter().all(|b| b.is_silence());
+        let output_channels = |i: usize| {
+            if let Some(&count) = self.output_channel_count.get(i) {
+                count
+            } else if self.number_of_inputs == 1 && self.number_of_outputs == 1 {
+                inputs.blocks[0].chan_count()
+            } else {
+                1
+            }
+        };
+        let mut outputs = Chunk::default();
+        if silent && !self.keep_alive {
+            for i in 0..self.number_of_outputs as usize {
+                let mut block = Block::silence(info.frames_per_block);
+                block.mix(output_channels(i), ChannelInterpretation::Discrete);
+                outputs.blocks.push(block);
+            }
+            return outputs;
+        }
+        for i in 0..self.number_of_outputs as usize {
+            outputs.blocks.push(Block::for_channels_explicit(
+                output_channels(i),
+                info.frames_per_block,
+            ));
+        }
+
+        for block in &mut inputs.blocks {
+            block.explicit_silence();
+            block.explicit_repeat();
+        }
+        self.update_param_values(info);
+        self.keep_alive = self.processor.process(
+            &inputs.blocks,
+            &mut outputs.blocks,
+            &self.param_values,
+            info,
+        );
+        outputs
+    }
+
+    fn input_count(&self) -> u32 {
+        self.number_of_inputs
+    }
+
+    fn output_count(&self) -> u32 {
+        self.number_of_outputs
+    }
+
+    fn get_param(&mut self, id: ParamType) -> &mut Param {
+        match id {
+            ParamType::Custom(i) if (i as usize) < self.params.len() => {
+                &mut self.params[i as usize]
+            },
+            _ => panic!("Unknown param {:?} for AudioWorkletNode", id),
+        }
+    }
+
+    fn param_types(&self) -> &[ParamType] {
+        &self.param_types
+    }
+}
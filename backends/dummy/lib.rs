@@ -9,10 +9,10 @@ extern crate servo_media_webrtc;
 use ipc_channel::ipc::IpcSender;
 use servo_media::{Backend, BackendInit, SupportsMediaType};
 use servo_media_audio::block::{Block, Chunk};
-use servo_media_audio::context::{AudioContext, AudioContextOptions};
+use servo_media_audio::context::{AudioContext, AudioContextOptions, LatencyCategory};
 use servo_media_audio::decoder::{AudioDecoder, AudioDecoderCallbacks, AudioDecoderOptions};
-use servo_media_audio::render_thread::AudioRenderThreadMsg;
-use servo_media_audio::sink::{AudioSink, AudioSinkError};
+use servo_media_audio::render_thread::RenderThreadWaker;
+use servo_media_audio::sink::{AudioSink, AudioSinkError, AudioSinkLatency};
 use servo_media_audio::{AudioBackend, AudioStreamReader};
 use servo_media_player::context::PlayerGLContext;
 use servo_media_player::{Player, PlayerError, PlayerEvent, StreamType, audio, video};
@@ -28,7 +28,7 @@ use servo_media_webrtc::{
 };
 use std::any::Any;
 use std::ops::Range;
-use std::sync::mpsc::{self, Sender};
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 
 pub struct DummyBackend;
@@ -124,24 +124,25 @@ impl AudioBackend for DummyBackend {
         Box::new(DummyAudioDecoder)
     }
 
-    fn make_sink() -> Result<Self::Sink, AudioSinkError> {
+    fn make_sink(_: Option<&str>) -> Result<Self::Sink, AudioSinkError> {
         Ok(DummyAudioSink)
     }
     fn make_streamreader(
         _id: MediaStreamId,
         _sample_rate: f32,
+        frames_per_block: usize,
     ) -> Box<dyn AudioStreamReader + Send> {
-        Box::new(DummyStreamReader)
+        Box::new(DummyStreamReader(frames_per_block))
     }
 }
 
 pub struct DummyPlayer;
 
-pub struct DummyStreamReader;
+pub struct DummyStreamReader(usize);
 
 impl AudioStreamReader for DummyStreamReader {
     fn pull(&self) -> Block {
-        Default::default()
+        Block::silence(self.0)
     }
     fn start(&self) {}
     fn stop(&self) {}
@@ -278,7 +279,13 @@ impl Drop for DummyMediaStream {
 pub struct DummyAudioSink;
 
 impl AudioSink for DummyAudioSink {
-    fn init(&self, _: f32, _: Sender<AudioRenderThreadMsg>) -> Result<(), AudioSinkError> {
+    fn init(
+        &self,
+        _: f32,
+        _: usize,
+        _: LatencyCategory,
+        _: RenderThreadWaker,
+    ) -> Result<(), AudioSinkError> {
         Ok(())
     }
     fn init_stream(&self, _: u8, _: f32, _: Box<dyn MediaSocket>) -> Result<(), AudioSinkError> {
@@ -296,6 +303,12 @@ impl AudioSink for DummyAudioSink {
     fn push_data(&self, _: Chunk) -> Result<(), AudioSinkError> {
         Ok(())
     }
+    fn is_starved(&self) -> bool {
+        false
+    }
+    fn latency(&self) -> AudioSinkLatency {
+        AudioSinkLatency::default()
+    }
     fn set_eos_callback(&self, _: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>) {}
 }
 
@@ -1,30 +1,99 @@
+use crate::device_monitor;
 use crate::media_stream::GstreamerMediaSocket;
 use byte_slice_cast::*;
 use gst;
 use gst::prelude::*;
 use gst_app::{AppSrc, AppSrcCallbacks};
 use gst_audio;
-use servo_media_audio::block::{Chunk, FRAMES_PER_BLOCK};
-use servo_media_audio::render_thread::AudioRenderThreadMsg;
-use servo_media_audio::sink::{AudioSink, AudioSinkError};
+use servo_media_audio::block::{Block, Chunk, FRAMES_PER_BLOCK_USIZE};
+use servo_media_audio::context::LatencyCategory;
+use servo_media_audio::render_thread::RenderThreadWaker;
+use servo_media_audio::sink::{AudioSink, AudioSinkError, AudioSinkLatency};
 use servo_media_streams::MediaSocket;
 use std::cell::{Cell, RefCell};
 use std::sync::Arc;
-use std::sync::mpsc::Sender;
 use std::thread::Builder;
 
 const DEFAULT_SAMPLE_RATE: f32 = 44100.;
 
+/// The latency, in seconds, aimed for by each latency category.
+/// Interactive contexts ask for no latency, and get the smallest buffers
+/// that are still safe.
+const BALANCED_LATENCY: f64 = 0.1;
+const PLAYBACK_LATENCY: f64 = 0.5;
+
+/// Bounds on the audio sink's buffering, in seconds. Going lower
+/// glitches on most devices.
+const MIN_BUFFER_TIME: f64 = 0.02;
+const MIN_LATENCY_TIME: f64 = 0.01;
+const MAX_LATENCY: f64 = 2.;
+
+/// How much audio is queued at each stage of the pipeline.
+#[derive(Clone, Copy, Debug)]
+struct Buffering {
+    /// Frames appsrc queues before it has enough data. The render thread
+    /// sleeps until the queue drains, so larger queues mean fewer wakeups.
+    queue_frames: u64,
+    /// The audio sink's ring buffer size, in microseconds
+    buffer_time: i64,
+    /// The size of a single segment of the audio sink's ring buffer, i.e.
+    /// how often the device is fed, in microseconds
+    latency_time: i64,
+}
+
+impl Buffering {
+    fn new(latency_hint: LatencyCategory, sample_rate: f32, frames_per_block: usize) -> Self {
+        let latency = match latency_hint {
+            LatencyCategory::Interactive => 0.,
+            LatencyCategory::Balanced => BALANCED_LATENCY,
+            LatencyCategory::Playback => PLAYBACK_LATENCY,
+            LatencyCategory::Seconds(seconds) if seconds.is_finite() => {
+                seconds.clamp(0., MAX_LATENCY)
+            },
+            LatencyCategory::Seconds(_) => 0.,
+        };
+        // A quarter of the latency goes into appsrc's queue, the rest into
+        // the sink's ring buffer
+        let block = frames_per_block as u64;
+        let queue_frames = (latency / 4. * sample_rate as f64) as u64;
+        let queue_frames = queue_frames.div_ceil(block).max(1) * block;
+        let buffer_time = (latency * 3. / 4.).max(MIN_BUFFER_TIME);
+        let latency_time = (buffer_time / 4.).max(MIN_LATENCY_TIME);
+        let micros = |seconds: f64| (seconds * 1_000_000.) as i64;
+        Buffering {
+            queue_frames,
+            buffer_time: micros(buffer_time),
+            latency_time: micros(latency_time),
+        }
+    }
+
+    /// Apply to an audio sink element. Sinks without a ring buffer
+    /// are left as is.
+    fn configure(&self, sink: &impl IsA<glib::Object>) {
+        if sink.find_property("buffer-time").is_some() {
+            sink.set_property("buffer-time", self.buffer_time);
+        }
+        if sink.find_property("latency-time").is_some() {
+            sink.set_property("latency-time", self.latency_time);
+        }
+    }
+}
+
 pub struct GStreamerAudioSink {
     pipeline: gst::Pipeline,
     appsrc: Arc<AppSrc>,
     sample_rate: Cell<f32>,
+    frames_per_block: Cell<usize>,
     audio_info: RefCell<Option<gst_audio::AudioInfo>>,
     sample_offset: Cell<u64>,
+    /// Only set for sinks playing to an audio device
+    buffering: Cell<Option<Buffering>>,
+    /// The audio output device to play to, if not the default one
+    device: Option<gst::Device>,
 }
 
 impl GStreamerAudioSink {
-    pub fn new() -> Result<Self, AudioSinkError> {
+    pub fn new(device_id: Option<&str>) -> Result<Self, AudioSinkError> {
         if let Some(category) = gst::DebugCategory::get("openslessink") {
             category.set_threshold(gst::DebugLevel::Trace);
         }
@@ -39,12 +108,23 @@ impl GStreamerAudioSink {
             })?;
         let appsrc = appsrc.downcast::<AppSrc>().unwrap();
 
+        let device = match device_id {
+            Some(device_id) => Some(
+                device_monitor::find_audio_output(device_id)
+                    .ok_or(AudioSinkError::DeviceNotFound)?,
+            ),
+            None => None,
+        };
+
         Ok(Self {
             pipeline: gst::Pipeline::new(),
             appsrc: Arc::new(appsrc),
             sample_rate: Cell::new(DEFAULT_SAMPLE_RATE),
+            frames_per_block: Cell::new(FRAMES_PER_BLOCK_USIZE),
             audio_info: RefCell::new(None),
             sample_offset: Cell::new(0),
+            buffering: Cell::new(None),
+            device,
         })
     }
 }
@@ -59,6 +139,10 @@ impl GStreamerAudioSink {
         .build()
         .map_err(|error| AudioSinkError::Backend(format!("AudioInfo failed: {error:?}")))?;
         self.appsrc.set_caps(audio_info.to_caps().ok().as_ref());
+        if let Some(buffering) = self.buffering.get() {
+            self.appsrc
+                .set_max_bytes(buffering.queue_frames * audio_info.bpf() as u64);
+        }
         *self.audio_info.borrow_mut() = Some(audio_info);
         Ok(())
     }
@@ -81,24 +165,23 @@ impl AudioSink for GStreamerAudioSink {
     fn init(
         &self,
         sample_rate: f32,
-        graph_thread_channel: Sender<AudioRenderThreadMsg>,
+        frames_per_block: usize,
+        latency_hint: LatencyCategory,
+        render_thread: RenderThreadWaker,
     ) -> Result<(), AudioSinkError> {
+        let buffering = Buffering::new(latency_hint, sample_rate, frames_per_block);
+        self.buffering.set(Some(buffering));
         self.sample_rate.set(sample_rate);
+        self.frames_per_block.set(frames_per_block);
+        // This also limits appsrc's queue
         self.set_audio_info(sample_rate, 2)?;
         self.appsrc.set_format(gst::Format::Time);
 
-        // Allow only a single chunk.
-        self.appsrc.set_max_bytes(1);
-
         let appsrc = self.appsrc.clone();
         Builder::new()
             .name("GstAppSrcCallbacks".to_owned())
             .spawn(move || {
-                let need_data = move |_: &AppSrc, _: u32| {
-                    if let Err(e) = graph_thread_channel.send(AudioRenderThreadMsg::SinkNeedData) {
-                        log::warn!("Error sending need data event: {:?}", e);
-                    }
-                };
+                let need_data = move |_: &AppSrc, _: u32| render_thread.wake();
                 appsrc.set_callbacks(AppSrcCallbacks::builder().need_data(need_data).build());
             })
             .unwrap();
@@ -114,11 +197,22 @@ impl AudioSink for GStreamerAudioSink {
             .map_err(|error| {
                 AudioSinkError::Backend(format!("audioconvert creation failed: {error:?}"))
             })?;
-        let sink = gst::ElementFactory::make("autoaudiosink")
-            .build()
-            .map_err(|error| {
-                AudioSinkError::Backend(format!("autoaudiosink creation failed: {error:?}"))
-            })?;
+        let sink = match self.device {
+            Some(ref device) => device.create_element(None).map_err(|error| {
+                AudioSinkError::Backend(format!("audio device sink creation failed: {error:?}"))
+            })?,
+            None => gst::ElementFactory::make("autoaudiosink")
+                .build()
+                .map_err(|error| {
+                    AudioSinkError::Backend(format!("autoaudiosink creation failed: {error:?}"))
+                })?,
+        };
+        buffering.configure(&sink);
+        // autoaudiosink only creates the actual sink once it starts, so
+        // configure it then
+        if let Some(sink) = sink.dynamic_cast_ref::<gst::ChildProxy>() {
+            sink.connect_child_added(move |_, child, _| buffering.configure(child));
+        }
         self.pipeline
             .add_many(&[&appsrc, &resample, &convert, &sink])
             .map_err(|error| AudioSinkError::Backend(error.to_string()))?;
@@ -177,12 +271,14 @@ impl AudioSink for GStreamerAudioSink {
     }
 
     fn has_enough_data(&self) -> bool {
+        // max-bytes is kept at `Buffering::queue_frames` worth of data
         self.appsrc.current_level_bytes() >= self.appsrc.max_bytes()
     }
 
     fn push_data(&self, mut chunk: Chunk) -> Result<(), AudioSinkError> {
         if let Some(block) = chunk.blocks.get(0) {
             self.set_channels_if_changed(block.chan_count())?;
+            self.frames_per_block.set(block.frames());
         }
 
         let sample_rate = self.sample_rate.get() as u64;
@@ -191,7 +287,7 @@ impl AudioSink for GStreamerAudioSink {
         let channels = audio_info.channels();
         let bpf = audio_info.bpf() as usize;
         assert_eq!(bpf, 4 * channels as usize);
-        let n_samples = FRAMES_PER_BLOCK.0;
+        let n_samples = self.frames_per_block.get() as u64;
         let buf_size = (n_samples as usize) * (bpf);
         let mut buffer = gst::Buffer::with_size(buf_size).unwrap();
         {
@@ -215,7 +311,7 @@ impl AudioSink for GStreamerAudioSink {
 
             // sometimes nothing reaches the output
             if chunk.len() == 0 {
-                chunk.blocks.push(Default::default());
+                chunk.blocks.push(Block::silence(n_samples as usize));
                 chunk.blocks[0].repeat(channels as u8);
             }
             debug_assert!(chunk.len() == 1);
@@ -237,6 +333,45 @@ impl AudioSink for GStreamerAudioSink {
             .map_err(|_| AudioSinkError::BufferPushFailed)
     }
 
+    fn is_starved(&self) -> bool {
+        let sample_offset = self.sample_offset.get();
+        if sample_offset == 0 || self.pipeline.current_state() != gst::State::Playing {
+            return false;
+        }
+        // The position keeps following the clock while the device plays
+        // silence, so it only gets past what was pushed if we fell behind
+        let pushed = gst::ClockTime::from_nseconds(
+            sample_offset
+                .mul_div_floor(
+                    gst::ClockTime::SECOND.nseconds(),
+                    self.sample_rate.get() as u64,
+                )
+                .unwrap(),
+        );
+        self.pipeline
+            .query_position::<gst::ClockTime>()
+            .is_some_and(|position| position >= pushed)
+    }
+
+    fn latency(&self) -> AudioSinkLatency {
+        let sample_rate = self.sample_rate.get() as f64;
+        // Audio waits in appsrc's queue before being handed over
+        let queue_frames = self
+            .buffering
+            .get()
+            .map_or(self.frames_per_block.get() as u64, |b| b.queue_frames);
+        let base = queue_frames as f64 / sample_rate;
+
+        // Everything pushed and not yet played is in flight somewhere
+        // between us and the speakers
+        let pushed = self.sample_offset.get() as f64 / sample_rate;
+        let output = match self.pipeline.query_position::<gst::ClockTime>() {
+            Some(position) => (pushed - position.seconds_f64() - base).max(0.),
+            None => 0.,
+        };
+        AudioSinkLatency { base, output }
+    }
+
     fn set_eos_callback(&self, _: Box<dyn Fn(Box<dyn AsRef<[f32]>>) + Send + Sync + 'static>) {}
 }
 
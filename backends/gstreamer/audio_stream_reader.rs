@@ -1,6 +1,6 @@
 use crate::media_stream::GStreamerMediaStream;
 use servo_media_audio::AudioStreamReader;
-use servo_media_audio::block::{Block, FRAMES_PER_BLOCK_USIZE};
+use servo_media_audio::block::Block;
 use servo_media_streams::registry::{MediaStreamId, get_stream};
 use std::sync::mpsc::{Receiver, channel};
 
@@ -15,7 +15,11 @@ pub struct GStreamerAudioStreamReader {
 }
 
 impl GStreamerAudioStreamReader {
-    pub fn new(stream: MediaStreamId, sample_rate: f32) -> Result<Self, String> {
+    pub fn new(
+        stream: MediaStreamId,
+        sample_rate: f32,
+        frames_per_block: usize,
+    ) -> Result<Self, String> {
         let (tx, rx) = channel();
         let stream = get_stream(&stream).unwrap();
         let mut stream = stream.lock().unwrap();
@@ -26,7 +30,7 @@ impl GStreamerAudioStreamReader {
         let element = g_stream.src_element();
         let pipeline = g_stream.pipeline_or_new();
         drop(stream);
-        let time_per_block = Fraction::new(FRAMES_PER_BLOCK_USIZE as i32, sample_rate as i32);
+        let time_per_block = Fraction::new(frames_per_block as i32, sample_rate as i32);
 
         // XXXManishearth this is only necessary because of an upstream
         // gstreamer bug. https://github.com/servo/media/pull/362#issuecomment-647947034
@@ -84,7 +88,7 @@ impl GStreamerAudioStreamReader {
                         .as_slice_of::<f32>()
                         .map_err(|_| gst::FlowError::Error)?;
 
-                    let block = Block::for_vec(floatref.into());
+                    let block = Block::for_vec(floatref.into(), frames_per_block);
                     tx.send(block).map_err(|_| gst::FlowError::Error)?;
                     Ok(gst::FlowSuccess::Ok)
                 })
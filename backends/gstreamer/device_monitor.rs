@@ -4,6 +4,21 @@ use std::cell::RefCell;
 
 use servo_media_streams::device_monitor::{MediaDeviceInfo, MediaDeviceKind, MediaDeviceMonitor};
 
+const AUDIO_SOURCE: &str = "Audio/Source";
+const AUDIO_SINK: &str = "Audio/Sink";
+const VIDEO_SOURCE: &str = "Video/Source";
+
+/// Find the audio output device with the given `MediaDeviceInfo::device_id`
+pub fn find_audio_output(device_id: &str) -> Option<gst::Device> {
+    let device_monitor = GstDeviceMonitor::new();
+    let audio_caps = gst_audio::AudioCapsBuilder::new().build();
+    device_monitor.add_filter(Some(AUDIO_SINK), Some(&audio_caps));
+    device_monitor
+        .devices()
+        .into_iter()
+        .find(|device| device.display_name().as_str() == device_id)
+}
+
 pub struct GStreamerDeviceMonitor {
     devices: RefCell<Option<Vec<MediaDeviceInfo>>>,
 }
@@ -16,9 +31,6 @@ impl GStreamerDeviceMonitor {
     }
 
     fn get_devices(&self) -> Result<Vec<MediaDeviceInfo>, ()> {
-        const AUDIO_SOURCE: &str = "Audio/Source";
-        const AUDIO_SINK: &str = "Audio/Sink";
-        const VIDEO_SOURCE: &str = "Video/Source";
         let device_monitor = GstDeviceMonitor::new();
         let audio_caps = gst_audio::AudioCapsBuilder::new().build();
         device_monitor.add_filter(Some(AUDIO_SOURCE), Some(&audio_caps));
@@ -298,12 +298,19 @@ impl AudioBackend for GStreamerBackend {
     fn make_decoder() -> Box<dyn AudioDecoder> {
         Box::new(audio_decoder::GStreamerAudioDecoder::new())
     }
-    fn make_sink() -> Result<Self::Sink, AudioSinkError> {
-        audio_sink::GStreamerAudioSink::new()
+    fn make_sink(device_id: Option<&str>) -> Result<Self::Sink, AudioSinkError> {
+        audio_sink::GStreamerAudioSink::new(device_id)
     }
 
-    fn make_streamreader(id: MediaStreamId, sample_rate: f32) -> Box<dyn AudioStreamReader + Send> {
-        Box::new(audio_stream_reader::GStreamerAudioStreamReader::new(id, sample_rate).unwrap())
+    fn make_streamreader(
+        id: MediaStreamId,
+        sample_rate: f32,
+        frames_per_block: usize,
+    ) -> Box<dyn AudioStreamReader + Send> {
+        Box::new(
+            audio_stream_reader::GStreamerAudioStreamReader::new(id, sample_rate, frames_per_block)
+                .unwrap(),
+        )
     }
 }
 
@@ -45,7 +45,7 @@ impl AudioStream {
             let dest = context.dest_node();
             context.connect_ports(osc.output(0), gain.input(0));
             context.connect_ports(gain.output(0), dest.input(0));
-            context.message_node(
+            let _ = context.message_node(
                 osc,
                 AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(
                     0.,
@@ -71,11 +71,11 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create buffer source node");
     let dest = context.dest_node();
     context.connect_ports(buffer_source.output(0), dest.input(0));
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioBufferSourceNode(AudioBufferSourceNodeMessage::SetBuffer(Some(
             AudioBuffer::from_buffers(decoded_audio.lock().unwrap().to_vec(), sample_rate),
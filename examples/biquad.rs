@@ -42,15 +42,15 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(osc2.output(0), biquad.input(0));
     context.connect_ports(biquad.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc1,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         osc2,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         biquad,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -59,7 +59,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     );
 
     thread::sleep(time::Duration::from_millis(2200));
-    context.message_node(
+    let _ = context.message_node(
         biquad,
         AudioNodeMessage::BiquadFilterNode(BiquadFilterNodeMessage::SetFilterType(
             FilterType::BandPass,
@@ -52,22 +52,22 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(merger.output(0), gain.input(0));
     context.connect_ports(osc3.output(0), gain.input(0));
     context.connect_ports(gain.output(0), dest.input(0));
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         osc2,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         osc3,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     let _ = context.resume();
 
     thread::sleep(time::Duration::from_millis(2000));
-    context.message_node(dest, AudioNodeMessage::SetChannelCount(1));
+    let _ = context.message_node(dest, AudioNodeMessage::SetChannelCount(1));
     thread::sleep(time::Duration::from_millis(2000));
     let _ = context.close();
 }
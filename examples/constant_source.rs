@@ -44,22 +44,22 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(gain.output(0), dest.input(0));
 
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::SetParam(
             ParamType::Offset,
@@ -67,7 +67,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::SetParam(
             ParamType::Offset,
@@ -75,7 +75,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::SetParam(
             ParamType::Offset,
@@ -83,7 +83,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::SetParam(
             ParamType::Offset,
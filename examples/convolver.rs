@@ -0,0 +1,76 @@
+extern crate servo_media;
+extern crate servo_media_auto;
+
+use servo_media::audio::buffer_source_node::AudioBuffer;
+use servo_media::audio::convolver_node::ConvolverNodeOptions;
+use servo_media::audio::node::{AudioNodeInit, AudioNodeMessage, AudioScheduledSourceNodeMessage};
+use servo_media::audio::oscillator_node::{OscillatorNodeOptions, OscillatorType};
+use servo_media::{ClientContextId, ServoMedia};
+use std::sync::Arc;
+use std::{thread, time};
+
+/// A synthetic stereo "room": exponentially decaying noise, with
+/// slightly different noise on each channel
+fn impulse_response(sample_rate: f32, seconds: f32) -> AudioBuffer {
+    let len = (sample_rate * seconds) as usize;
+    let mut seed: u32 = 0x1234_5678;
+    let mut noise = move || {
+        // xorshift
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as f32 / u32::MAX as f32 * 2. - 1.
+    };
+    let buffers = (0..2)
+        .map(|_| {
+            (0..len)
+                .map(|i| noise() * (-3. * i as f32 / len as f32).exp())
+                .collect()
+        })
+        .collect();
+    AudioBuffer::from_buffers(buffers, sample_rate)
+}
+
+fn run_example(servo_media: Arc<ServoMedia>) {
+    let context = servo_media
+        .create_audio_context(&ClientContextId::build(1, 1), Default::default())
+        .unwrap();
+    let context = context.lock().unwrap();
+    let dest = context.dest_node();
+
+    let mut options = OscillatorNodeOptions::default();
+    options.oscillator_type = OscillatorType::Square;
+    options.freq = 220.;
+    let osc = context
+        .create_node(AudioNodeInit::OscillatorNode(options), Default::default())
+        .expect("Failed to create oscillator node");
+    let convolver = context
+        .create_node(
+            AudioNodeInit::ConvolverNode(ConvolverNodeOptions {
+                buffer: Some(impulse_response(44100., 2.)),
+                normalize: true,
+            }),
+            Default::default(),
+        )
+        .expect("Failed to create convolver node");
+    context.connect_ports(osc.output(0), convolver.input(0));
+    context.connect_ports(convolver.output(0), dest.input(0));
+    let _ = context.resume();
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+    );
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Stop(0.5)),
+    );
+    // Let the reverb tail ring out
+    thread::sleep(time::Duration::from_millis(3000));
+    let _ = context.close();
+}
+
+fn main() {
+    ServoMedia::init::<servo_media_auto::Backend>();
+    let servo_media = ServoMedia::get();
+    run_example(servo_media);
+}
@@ -0,0 +1,62 @@
+extern crate servo_media;
+extern crate servo_media_auto;
+
+use servo_media::audio::delay_node::DelayNodeOptions;
+use servo_media::audio::gain_node::GainNodeOptions;
+use servo_media::audio::node::{AudioNodeInit, AudioNodeMessage, AudioScheduledSourceNodeMessage};
+use servo_media::{ClientContextId, ServoMedia};
+use std::sync::Arc;
+use std::{thread, time};
+
+fn run_example(servo_media: Arc<ServoMedia>) {
+    let context = servo_media
+        .create_audio_context(&ClientContextId::build(1, 1), Default::default())
+        .unwrap();
+    let context = context.lock().unwrap();
+    let dest = context.dest_node();
+    let osc = context
+        .create_node(
+            AudioNodeInit::OscillatorNode(Default::default()),
+            Default::default(),
+        )
+        .expect("Failed to create oscillator node");
+    let delay = context
+        .create_node(
+            AudioNodeInit::DelayNode(DelayNodeOptions {
+                max_delay_time: 1.,
+                delay_time: 0.3,
+            }),
+            Default::default(),
+        )
+        .expect("Failed to create delay node");
+    let feedback = context
+        .create_node(
+            AudioNodeInit::GainNode(GainNodeOptions { gain: 0.5 }),
+            Default::default(),
+        )
+        .expect("Failed to create gain node");
+    // osc -> dest, osc -> delay -> dest, with delay -> feedback -> delay
+    // making an echo that decays by half on every repeat
+    context.connect_ports(osc.output(0), dest.input(0));
+    context.connect_ports(osc.output(0), delay.input(0));
+    context.connect_ports(delay.output(0), dest.input(0));
+    context.connect_ports(delay.output(0), feedback.input(0));
+    context.connect_ports(feedback.output(0), delay.input(0));
+    let _ = context.resume();
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+    );
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Stop(0.1)),
+    );
+    thread::sleep(time::Duration::from_millis(3000));
+    let _ = context.close();
+}
+
+fn main() {
+    ServoMedia::init::<servo_media_auto::Backend>();
+    let servo_media = ServoMedia::get();
+    run_example(servo_media);
+}
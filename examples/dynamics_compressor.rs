@@ -0,0 +1,69 @@
+extern crate servo_media;
+extern crate servo_media_auto;
+
+use servo_media::audio::dynamics_compressor_node::{
+    DynamicsCompressorNodeMessage, DynamicsCompressorNodeOptions,
+};
+use servo_media::audio::gain_node::GainNodeOptions;
+use servo_media::audio::node::{AudioNodeInit, AudioNodeMessage, AudioScheduledSourceNodeMessage};
+use servo_media::{ClientContextId, ServoMedia};
+use std::sync::{Arc, mpsc};
+use std::{thread, time};
+
+fn run_example(servo_media: Arc<ServoMedia>) {
+    let context = servo_media
+        .create_audio_context(&ClientContextId::build(1, 1), Default::default())
+        .unwrap();
+    let context = context.lock().unwrap();
+    let dest = context.dest_node();
+    let osc = context
+        .create_node(
+            AudioNodeInit::OscillatorNode(Default::default()),
+            Default::default(),
+        )
+        .expect("Failed to create oscillator node");
+    // Drive the compressor well above its threshold
+    let gain = context
+        .create_node(
+            AudioNodeInit::GainNode(GainNodeOptions { gain: 4. }),
+            Default::default(),
+        )
+        .expect("Failed to create gain node");
+    let compressor = context
+        .create_node(
+            AudioNodeInit::DynamicsCompressionNode(DynamicsCompressorNodeOptions {
+                threshold: -12.,
+                knee: 0.,
+                ratio: 20.,
+                ..Default::default()
+            }),
+            Default::default(),
+        )
+        .expect("Failed to create compressor node");
+    context.connect_ports(osc.output(0), gain.input(0));
+    context.connect_ports(gain.output(0), compressor.input(0));
+    context.connect_ports(compressor.output(0), dest.input(0));
+    let _ = context.resume();
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+    );
+    for _ in 0..4 {
+        thread::sleep(time::Duration::from_millis(500));
+        let (tx, rx) = mpsc::sync_channel(1);
+        let _ = context.message_node(
+            compressor,
+            AudioNodeMessage::DynamicsCompressionNode(DynamicsCompressorNodeMessage::GetReduction(
+                tx,
+            )),
+        );
+        println!("Gain reduction: {:.1}dB", rx.recv().unwrap());
+    }
+    let _ = context.close();
+}
+
+fn main() {
+    ServoMedia::init::<servo_media_auto::Backend>();
+    let servo_media = ServoMedia::get();
+    run_example(servo_media);
+}
@@ -41,7 +41,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
 
         context.connect_ports(osc.output(0), dest.input(0));
         let _ = context.resume();
-        context.message_node(
+        let _ = context.message_node(
             osc,
             AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
         );
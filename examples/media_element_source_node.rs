@@ -45,7 +45,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create MediaElementSourceNode node");
 
     let (sender, receiver) = mpsc::channel();
-    context.message_node(
+    let _ = context.message_node(
         source_node,
         AudioNodeMessage::MediaElementSourceNode(MediaElementSourceNodeMessage::GetAudioRenderer(
             sender,
@@ -145,56 +145,56 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     let _ = context.resume();
 
     // trace a square around your head twice
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.8),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -202,56 +202,56 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.0),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.0),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -259,14 +259,14 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // now it runs away
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 10000., 3.),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         listener,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -274,7 +274,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // chase it
-    context.message_node(
+    let _ = context.message_node(
         listener,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
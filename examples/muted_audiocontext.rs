@@ -25,7 +25,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
             .expect("Failed to create oscillator node");
         context.connect_ports(osc1.output(0), dest.input(0));
         let _ = context.resume();
-        context.message_node(
+        let _ = context.message_node(
             osc1,
             AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
         );
@@ -48,7 +48,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         context.connect_ports(osc3.output(0), dest.input(0));
 
         let _ = context.resume();
-        context.message_node(
+        let _ = context.message_node(
             osc3,
             AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
         );
@@ -41,7 +41,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create oscillator node");
     let dest = context.dest_node();
     context.connect_ports(osc.output(0), dest.input(0));
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -63,11 +63,11 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create oscillator node");
     let dest = context.dest_node();
     context.connect_ports(buffer_source.output(0), dest.input(0));
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioBufferSourceNode(AudioBufferSourceNodeMessage::SetBuffer(Some(
             AudioBuffer::from_buffer(processed_audio_.lock().unwrap().to_vec(), sample_rate),
@@ -7,6 +7,7 @@ use servo_media::audio::oscillator_node::OscillatorType::Custom;
 use servo_media::audio::oscillator_node::OscillatorType::Sawtooth;
 use servo_media::audio::oscillator_node::OscillatorType::Square;
 use servo_media::audio::oscillator_node::OscillatorType::Triangle;
+use servo_media::audio::periodic_wave::{PeriodicWave, PeriodicWaveOptions};
 use servo_media::{ClientContextId, ServoMedia};
 use std::sync::Arc;
 use std::{thread, time};
@@ -26,7 +27,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create oscillator node");
     context.connect_ports(osc1.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc1,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -49,7 +50,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create oscillator node");
     context.connect_ports(osc2.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc2,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -74,7 +75,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     thread::sleep(time::Duration::from_millis(3000));
 
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc3,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -98,7 +99,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     thread::sleep(time::Duration::from_millis(3000));
 
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc4,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -108,6 +109,12 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     thread::sleep(time::Duration::from_millis(3000));
 
     options.oscillator_type = Custom;
+    // An organ-like tone: the fundamental and a few odd harmonics
+    options.periodic_wave = Some(PeriodicWave::new(PeriodicWaveOptions {
+        real: vec![0., 0., 0., 0., 0., 0.],
+        imag: vec![0., 1., 0., 0.5, 0., 0.25],
+        disable_normalization: false,
+    }));
 
     let context = servo_media
         .create_audio_context(&ClientContextId::build(1, 5), Default::default())
@@ -124,8 +131,8 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     thread::sleep(time::Duration::from_millis(3000));
 
     let _ = context.resume();
-    context.message_node(
-        osc4,
+    let _ = context.message_node(
+        osc5,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
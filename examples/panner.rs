@@ -34,61 +34,61 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(osc.output(0), panner.input(0));
     context.connect_ports(panner.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     // trace a square around your head twice
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 0.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 0.8),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -96,56 +96,56 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.0),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.0),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.2),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, -100., 1.4),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::X),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 100., 1.6),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -153,14 +153,14 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // now it runs away
-    context.message_node(
+    let _ = context.message_node(
         panner,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
             UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 10000., 3.),
         ),
     );
-    context.message_node(
+    let _ = context.message_node(
         listener,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
@@ -168,7 +168,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // chase it
-    context.message_node(
+    let _ = context.message_node(
         listener,
         AudioNodeMessage::SetParam(
             ParamType::Position(ParamDir::Z),
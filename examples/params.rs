@@ -28,12 +28,12 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(osc.output(0), gain.input(0));
     context.connect_ports(gain.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     // 0.5s: Set frequency to 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -41,7 +41,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 1s: Set frequency to 220Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -49,7 +49,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 0.75s: Set gain to 0.25
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::SetParam(
             ParamType::Gain,
@@ -57,7 +57,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 0.75s - 1.5s: Exponentially ramp gain to 1
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::SetParam(
             ParamType::Gain,
@@ -65,7 +65,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 0.75s - 1.75s: Linearly ramp frequency to 880Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -73,7 +73,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 1.75s - 2.5s: Exponentially ramp frequency to 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -82,7 +82,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     );
 
     // 2.75s: Exponentially approach 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -90,7 +90,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 3.3s: But actually stop at 3.3Hz and hold
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
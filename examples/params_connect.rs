@@ -35,17 +35,17 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(gain.output(0), dest.input(0));
     context.connect_ports(osc.output(0), gain.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         lfo,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     thread::sleep(time::Duration::from_millis(3000));
     // 0.75s - 1.75s: Linearly ramp frequency to 880Hz
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::SetParam(
             ParamType::Gain,
@@ -21,12 +21,12 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         .expect("Failed to create oscillator node");
     context.connect_ports(osc.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     // 0.1s: Set frequency to 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -34,7 +34,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 0.3s: Start increasing frequency to 440Hz exponentially with a time constant of 1
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -44,7 +44,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     // 1.5s: Start increasing frequency to 1760Hz exponentially
     // this event effectively doesn't happen, but instead sets a starting point
     // for the next ramp event
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -52,7 +52,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 1.5s - 3s Linearly ramp down from the previous event (1.5s) to 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
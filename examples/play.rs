@@ -29,18 +29,18 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     let dest = context.dest_node();
     context.connect_ports(osc.output(0), gain.input(0));
     context.connect_ports(gain.output(0), dest.input(0));
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Stop(3.)),
     );
     let callback = OnEndedCallback::new(|| {
         println!("Playback ended");
     });
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(
             AudioScheduledSourceNodeMessage::RegisterOnEndedCallback(callback),
@@ -49,7 +49,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     assert_eq!(context.current_time(), 0.);
     let _ = context.resume();
     // 0.5s: Set frequency to 110Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -57,7 +57,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 1s: Set frequency to 220Hz
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -65,7 +65,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 0.75s: Set gain to 0.25
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::SetParam(
             ParamType::Gain,
@@ -27,11 +27,11 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         buffers[0].push(rand::random::<f32>());
         buffers[1].push(rand::random::<f32>());
     }
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioBufferSourceNode(AudioBufferSourceNodeMessage::SetBuffer(Some(
             AudioBuffer::from_buffers(buffers, 44100.),
@@ -40,7 +40,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     let callback = OnEndedCallback::new(|| {
         println!("Playback ended");
     });
-    context.message_node(
+    let _ = context.message_node(
         buffer_source,
         AudioNodeMessage::AudioScheduledSourceNode(
             AudioScheduledSourceNodeMessage::RegisterOnEndedCallback(callback),
@@ -0,0 +1,59 @@
+extern crate servo_media;
+extern crate servo_media_auto;
+
+use servo_media::audio::node::{AudioNodeInit, AudioNodeMessage, AudioScheduledSourceNodeMessage};
+use servo_media::audio::script_processor_node::{AudioProcessingEvent, ScriptProcessorNodeOptions};
+use servo_media::{ClientContextId, ServoMedia};
+use std::sync::Arc;
+use std::{thread, time};
+
+fn run_example(servo_media: Arc<ServoMedia>) {
+    let context = servo_media
+        .create_audio_context(&ClientContextId::build(1, 1), Default::default())
+        .unwrap();
+    let context = context.lock().unwrap();
+    let dest = context.dest_node();
+    let osc = context
+        .create_node(
+            AudioNodeInit::OscillatorNode(Default::default()),
+            Default::default(),
+        )
+        .expect("Failed to create oscillator node");
+    // Tremolo, computed off the render thread
+    let mut phase: f32 = 0.;
+    let script = context
+        .create_node(
+            AudioNodeInit::ScriptProcessorNode(ScriptProcessorNodeOptions {
+                buffer_size: 1024,
+                number_of_input_channels: 1,
+                number_of_output_channels: 1,
+                callback: Box::new(move |event: &mut AudioProcessingEvent| {
+                    let step = 2. * std::f32::consts::PI * 4. / event.input_buffer.sample_rate;
+                    let input = &event.input_buffer.buffers[0];
+                    let output = &mut event.output_buffer.buffers[0];
+                    for (out, sample) in output.iter_mut().zip(input) {
+                        *out = sample * (0.5 + 0.5 * phase.sin());
+                        phase += step;
+                    }
+                    println!("Processed buffer for {:.3}s", event.playback_time);
+                }),
+            }),
+            Default::default(),
+        )
+        .expect("Failed to create script processor node");
+    context.connect_ports(osc.output(0), script.input(0));
+    context.connect_ports(script.output(0), dest.input(0));
+    let _ = context.resume();
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+    );
+    thread::sleep(time::Duration::from_millis(3000));
+    let _ = context.close();
+}
+
+fn main() {
+    ServoMedia::init::<servo_media_auto::Backend>();
+    let servo_media = ServoMedia::get();
+    run_example(servo_media);
+}
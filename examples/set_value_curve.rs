@@ -53,22 +53,22 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(gain.output(0), dest.input(0));
 
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         gain,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         cs,
         AudioNodeMessage::SetParam(
             ParamType::Offset,
@@ -76,7 +76,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
 
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::SetParam(
             ParamType::Frequency,
@@ -28,17 +28,17 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     context.connect_ports(osc.output(0), pan.input(0));
     context.connect_ports(pan.output(0), dest.input(0));
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
     // 2s: Set pan to -1
-    context.message_node(
+    let _ = context.message_node(
         pan,
         AudioNodeMessage::SetParam(ParamType::Pan, UserAutomationEvent::SetValueAtTime(-1., 2.)),
     );
     // 4s: Linearly ramp pan to 0
-    context.message_node(
+    let _ = context.message_node(
         pan,
         AudioNodeMessage::SetParam(
             ParamType::Pan,
@@ -46,7 +46,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         ),
     );
     // 6s: Linearly ramp pan to 1
-    context.message_node(
+    let _ = context.message_node(
         pan,
         AudioNodeMessage::SetParam(
             ParamType::Pan,
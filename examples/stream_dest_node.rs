@@ -33,7 +33,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
     let mut output = servo_media.create_stream_output();
     output.add_stream(&id);
     let _ = context.resume();
-    context.message_node(
+    let _ = context.message_node(
         osc1,
         AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
     );
@@ -56,7 +56,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
 
         context.connect_ports(osc.output(0), dest.input(0));
         let _ = context.resume();
-        context.message_node(
+        let _ = context.message_node(
             osc,
             AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
         );
@@ -92,7 +92,7 @@ fn run_example(servo_media: Arc<ServoMedia>) {
         thread::sleep(time::Duration::from_millis(2000));
 
         println!("oscillator through waveshaper with no curve (should be same as raw oscillator)");
-        context.message_node(
+        let _ = context.message_node(
             wsh,
             AudioNodeMessage::WaveShaperNode(WaveShaperNodeMessage::SetCurve(None)),
         );
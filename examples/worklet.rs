@@ -0,0 +1,88 @@
+extern crate servo_media;
+extern crate servo_media_auto;
+
+use servo_media::audio::block::Block;
+use servo_media::audio::node::BlockInfo;
+use servo_media::audio::node::{AudioNodeInit, AudioNodeMessage, AudioScheduledSourceNodeMessage};
+use servo_media::audio::param::{ParamType, RampKind, UserAutomationEvent};
+use servo_media::audio::worklet_node::{
+    AudioParamDescriptor, AudioWorkletNodeOptions, AudioWorkletProcessor,
+};
+use servo_media::{ClientContextId, ServoMedia};
+use std::sync::Arc;
+use std::{thread, time};
+
+/// Reduces the bit depth of its input, with the number of bits
+/// controlled by its only param
+struct BitCrusher;
+
+impl AudioWorkletProcessor for BitCrusher {
+    fn parameter_descriptors(&self) -> Vec<AudioParamDescriptor> {
+        let mut bits = AudioParamDescriptor::new("bits", 16.);
+        bits.min_value = 1.;
+        bits.max_value = 16.;
+        vec![bits]
+    }
+
+    fn process(
+        &mut self,
+        inputs: &[Block],
+        outputs: &mut [Block],
+        params: &[Vec<f32>],
+        _info: &BlockInfo,
+    ) -> bool {
+        let bits = &params[0];
+        for chan in 0..inputs[0].chan_count() {
+            let input = inputs[0].data_chan(chan);
+            let output = outputs[0].data_chan_mut(chan);
+            for (i, (out, sample)) in output.iter_mut().zip(input).enumerate() {
+                let steps = 2f32.powf(bits[i.min(bits.len() - 1)] - 1.);
+                *out = (sample * steps).round() / steps;
+            }
+        }
+        false
+    }
+}
+
+fn run_example(servo_media: Arc<ServoMedia>) {
+    let context = servo_media
+        .create_audio_context(&ClientContextId::build(1, 1), Default::default())
+        .unwrap();
+    let context = context.lock().unwrap();
+    let dest = context.dest_node();
+    let osc = context
+        .create_node(
+            AudioNodeInit::OscillatorNode(Default::default()),
+            Default::default(),
+        )
+        .expect("Failed to create oscillator node");
+    let crusher = context
+        .create_node(
+            AudioNodeInit::AudioWorkletNode(AudioWorkletNodeOptions::new(Box::new(BitCrusher))),
+            Default::default(),
+        )
+        .expect("Failed to create worklet node");
+    context.connect_ports(osc.output(0), crusher.input(0));
+    context.connect_ports(crusher.output(0), dest.input(0));
+    let _ = context.resume();
+    let _ = context.message_node(
+        osc,
+        AudioNodeMessage::AudioScheduledSourceNode(AudioScheduledSourceNodeMessage::Start(0.)),
+    );
+    // Crush the sine down to a couple of bits over three seconds
+    let _ = context.message_node(
+        crusher,
+        AudioNodeMessage::SetParam(
+            ParamType::Custom(0),
+            UserAutomationEvent::RampToValueAtTime(RampKind::Linear, 2., 3.),
+        ),
+    );
+    thread::sleep(time::Duration::from_millis(4000));
+    let _ = context.close();
+}
+
+fn main() {
+    ServoMedia::init::<servo_media_auto::Backend>();
+    let servo_media = ServoMedia::get();
+    run_example(servo_media);
+}
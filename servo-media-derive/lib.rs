@@ -19,14 +19,16 @@ fn impl_audio_scheduled_source_node(ast: &syn::DeriveInput) -> proc_macro2::Toke
     let name = &ast.ident;
     quote! {
         impl #name {
-            fn should_play_at(&mut self, tick: Tick) -> ShouldPlay {
+            fn should_play_at(&mut self, info: &BlockInfo) -> ShouldPlay {
+                let tick = info.frame;
+                let frames = info.block_ticks();
                 let start = if let Some(start) = self.start_at {
                     start
                 } else {
                     return ShouldPlay::No;
                 };
 
-                let frame_end = tick + Tick::FRAMES_PER_BLOCK;
+                let frame_end = tick + frames;
                 if tick < start {
                     if frame_end < start {
                         ShouldPlay::No
@@ -38,23 +40,23 @@ fn impl_audio_scheduled_source_node(ast: &syn::DeriveInput) -> proc_macro2::Toke
                                 return ShouldPlay::No;
                             }
                             if stop > frame_end {
-                                ShouldPlay::Between(delta_start, Tick::FRAMES_PER_BLOCK)
+                                ShouldPlay::Between(delta_start, frames)
                             } else {
                                 self.maybe_trigger_onended_callback();
                                 ShouldPlay::Between(delta_start, stop - tick)
                             }
                         } else {
-                            ShouldPlay::Between(delta_start, Tick::FRAMES_PER_BLOCK)
+                            ShouldPlay::Between(delta_start, frames)
                         }
                     }
                 } else {
                     let stop = if let Some(stop) = self.stop_at {
                         stop
                     } else {
-                        return ShouldPlay::Between(Tick(0), Tick::FRAMES_PER_BLOCK);
+                        return ShouldPlay::Between(Tick(0), frames);
                     };
                     if stop > frame_end {
-                        ShouldPlay::Between(Tick(0), Tick::FRAMES_PER_BLOCK)
+                        ShouldPlay::Between(Tick(0), frames)
                     } else if stop < tick {
                         self.maybe_trigger_onended_callback();
                         ShouldPlay::No
@@ -65,6 +67,18 @@ fn impl_audio_scheduled_source_node(ast: &syn::DeriveInput) -> proc_macro2::Toke
                 }
             }
 
+            /// Whether the source won't play from the given tick on,
+            /// because it was never started or has been stopped. The block
+            /// the source stops in still has to be processed, to fire
+            /// the ended callback.
+            fn has_ended(&self, tick: Tick) -> bool {
+                match (self.start_at, self.stop_at) {
+                    (None, _) => true,
+                    (Some(_), Some(stop)) => stop < tick,
+                    (Some(_), None) => false,
+                }
+            }
+
             fn start(&mut self, tick: Tick) -> bool {
                 // We can only allow a single call to `start` and always before
                 // any `stop` calls.